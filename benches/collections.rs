@@ -0,0 +1,63 @@
+//! Measures the time needed to build `Collections` from large lists of file names.
+//!
+//! Run with `cargo bench --bench collections`.
+
+use std::time::Instant;
+
+use ruplicity::collections::Collections;
+use ruplicity::timefmt::format_time_str;
+use time::Timespec;
+
+// builds the file names of a backup with the given number of chains, incremental sets per chain
+// and volumes per set; every set has its manifest and signature
+fn backup_names(chains: usize, incs: usize, volumes: usize) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut time = 1_400_000_000;
+    for _ in 0..chains {
+        let full = format_time_str(Timespec::new(time, 0));
+        names.push(format!("duplicity-full.{}.manifest", full));
+        names.push(format!("duplicity-full-signatures.{}.sigtar.gz", full));
+        for vol in 1..=volumes {
+            names.push(format!("duplicity-full.{}.vol{}.difftar.gz", full, vol));
+        }
+        let mut start = full;
+        for _ in 0..incs {
+            time += 3600;
+            let end = format_time_str(Timespec::new(time, 0));
+            let set = format!("{}.to.{}", start, end);
+            names.push(format!("duplicity-inc.{}.manifest", set));
+            names.push(format!("duplicity-new-signatures.{}.sigtar.gz", set));
+            for vol in 1..=volumes {
+                names.push(format!("duplicity-inc.{}.vol{}.difftar.gz", set, vol));
+            }
+            start = end;
+        }
+        time += 3600;
+    }
+    names
+}
+
+fn bench(name: &str, names: &[String]) {
+    let start = Instant::now();
+    let collections = Collections::from_filenames(names);
+    let elapsed = start.elapsed();
+    assert_eq!(collections.diagnostics().count(), 0);
+    println!(
+        "{}: {} names, {} snapshots in {:?}",
+        name,
+        names.len(),
+        collections.num_snapshots(),
+        elapsed
+    );
+}
+
+fn main() {
+    // many small sets, as in a multi-year bucket with daily incrementals
+    let mut names = backup_names(50, 365, 10);
+    bench("sorted", &names);
+    names.reverse();
+    bench("reversed", &names);
+    // few large sets
+    let names = backup_names(10, 10, 2000);
+    bench("large sets", &names);
+}
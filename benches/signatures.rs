@@ -0,0 +1,153 @@
+//! Measures the memory and the time needed to load large signature chains, and to stream the
+//! entries of their last snapshot.
+//!
+//! Run with `cargo bench --bench signatures`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ruplicity::backend::memory::MemoryBackend;
+use ruplicity::collections::Collections;
+use ruplicity::signatures::{self, Chain};
+use ruplicity::timefmt::format_time_str;
+use time::Timespec;
+
+// an allocator keeping track of the allocated bytes, and of their peak
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// the path of the file with the given number, in a tree of nested directories
+fn file_path(num: usize) -> String {
+    format!(
+        "home/user/documents/project{}/src/module{}/file{}.txt",
+        num / 10_000,
+        num / 100,
+        num
+    )
+}
+
+// builds a compressed signature tar, with a snapshot of the given files and the deleted ones
+fn sigtar(files: &[usize], deleted: &[usize], mtime: u64) -> Vec<u8> {
+    let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+    let mut entries: Vec<_> = files
+        .iter()
+        .map(|&num| (file_path(num), "snapshot"))
+        .chain(deleted.iter().map(|&num| (file_path(num), "deleted")))
+        .collect();
+    entries.sort();
+    for (path, kind) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_path(format!("{}/{}", kind, path)).unwrap();
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_uid(1000);
+        header.set_gid(1000);
+        header.set_mtime(mtime);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        tar.append(&header, &[][..]).unwrap();
+    }
+    let mut gz = tar.into_inner().unwrap();
+    gz.flush().unwrap();
+    gz.finish().unwrap()
+}
+
+// a backend with a full signature of the given files, and incremental signatures changing a
+// tenth of them, and deleting a hundredth
+fn backend(num_files: usize, num_incs: usize) -> (MemoryBackend, Collections) {
+    let backend = MemoryBackend::new();
+    let mut time = 1_400_000_000;
+    let full = format_time_str(Timespec::new(time, 0));
+    let files: Vec<_> = (0..num_files).collect();
+    let name = format!("duplicity-full-signatures.{}.sigtar.gz", full);
+    backend.insert(&name, sigtar(&files, &[], time as u64));
+    let mut names = vec![name];
+    let mut start = full;
+    for inc in 0..num_incs {
+        time += 3600;
+        let end = format_time_str(Timespec::new(time, 0));
+        let changed: Vec<_> = (inc..num_files).step_by(10).collect();
+        let deleted: Vec<_> = (inc + 5..num_files).step_by(100).collect();
+        let name = format!("duplicity-new-signatures.{}.to.{}.sigtar.gz", start, end);
+        backend.insert(&name, sigtar(&changed, &deleted, time as u64));
+        names.push(name);
+        start = end;
+    }
+    (backend, Collections::from_filenames(&names))
+}
+
+fn bench(num_files: usize, num_incs: usize) {
+    let (backend, collections) = backend(num_files, num_incs);
+    let sigchain = collections.signature_chains().next().unwrap();
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let start = Instant::now();
+    let chain = Chain::from_sigchain(sigchain, &backend).unwrap();
+    let elapsed = start.elapsed();
+    let retained = ALLOCATED.load(Ordering::Relaxed) - before;
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    let entries = chain.snapshots().last().unwrap().files().count();
+    println!(
+        "{} files, {} incremental signatures: {} entries in {:?}, {:.1} MiB retained, \
+         {:.1} MiB peak, {} bytes per file",
+        num_files,
+        num_incs,
+        entries,
+        elapsed,
+        retained as f64 / (1 << 20) as f64,
+        peak as f64 / (1 << 20) as f64,
+        retained / num_files
+    );
+}
+
+fn bench_stream(num_files: usize, num_incs: usize) {
+    let (backend, collections) = backend(num_files, num_incs);
+    let sigchain = collections.signature_chains().next().unwrap();
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let start = Instant::now();
+    let mut entries = 0;
+    signatures::stream_entries(sigchain, num_incs, &backend, |_| entries += 1).unwrap();
+    let elapsed = start.elapsed();
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    println!(
+        "{} files, {} incremental signatures: {} entries streamed in {:?}, {:.1} MiB peak",
+        num_files,
+        num_incs,
+        entries,
+        elapsed,
+        peak as f64 / (1 << 20) as f64
+    );
+}
+
+fn main() {
+    bench(100_000, 0);
+    bench(500_000, 10);
+    bench_stream(100_000, 0);
+    bench_stream(500_000, 10);
+}
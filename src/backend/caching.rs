@@ -0,0 +1,319 @@
+//! Backend wrapper caching files on the local disk.
+
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{check_file_name, Backend, FileInfo, RangeBackend, WritableBackend};
+use crate::collections::file_naming::FileNameParser;
+use crate::collections::FileNaming;
+
+// distinguishes the temporary files of concurrent downloads within the same process
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A backend that keeps a local disk copy of the files read through another backend.
+///
+/// The first time a file is opened, it is fully downloaded from the inner backend into the cache
+/// directory; subsequent reads are served from the local copy. Listings are always forwarded to the
+/// inner backend.
+///
+/// Duplicity never modifies a backup file once written, since file names contain the backup
+/// time, so cached files are not checked when opened. Anyway, when files are listed by
+/// `file_infos`, the cached copies whose size differs from the listed one, or older than the
+/// listed modification time, are evicted, since the file has been replaced in the meantime.
+/// Partial files (e.g. `.part` manifests), which can be overwritten by a resumed backup, are never
+/// cached, as well as the names that are not plain file names.
+///
+/// # Example
+///
+/// ```
+/// use ruplicity::Backup;
+/// use ruplicity::backend::CachingBackend;
+/// use ruplicity::backend::local::LocalBackend;
+///
+/// let cache_dir = std::env::temp_dir().join(format!("ruplicity-doc-cache-{}", std::process::id()));
+/// let backend = CachingBackend::new(LocalBackend::new("tests/backups/single_vol"), &cache_dir);
+/// let backup = Backup::new(backend).unwrap();
+/// for snapshot in backup.snapshots().unwrap() {
+///     // signatures are now cached in `cache_dir`
+///     snapshot.entries().unwrap();
+/// }
+/// # std::fs::remove_dir_all(cache_dir).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct CachingBackend<B> {
+    inner: B,
+    cache_dir: PathBuf,
+    parser: FileNameParser,
+}
+
+impl<B: Backend> CachingBackend<B> {
+    /// Creates a new caching backend, storing files into the given directory.
+    ///
+    /// The directory is created on demand if not present.
+    pub fn new<P: AsRef<Path>>(inner: B, cache_dir: P) -> Self {
+        Self::with_naming(inner, cache_dir, &FileNaming::default())
+    }
+
+    /// Creates a new caching backend for a backup using custom file names.
+    ///
+    /// The naming is used to recognize the partial files, which are never cached.
+    pub fn with_naming<P: AsRef<Path>>(inner: B, cache_dir: P, naming: &FileNaming) -> Self {
+        CachingBackend {
+            inner,
+            cache_dir: cache_dir.as_ref().to_path_buf(),
+            parser: FileNameParser::with_naming(naming),
+        }
+    }
+
+    /// Returns the directory containing the cached files.
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// Returns a reference to the inner backend.
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    /// Unwraps this backend and returns the inner one.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// Removes the given file from the cache, if present.
+    pub fn evict(&self, name: &Path) -> io::Result<()> {
+        if !self.is_cacheable(name) {
+            return Ok(());
+        }
+        match fs::remove_file(self.cache_dir.join(name)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
+
+    // partial files and names escaping from the cache directory are always read remotely
+    fn is_cacheable(&self, name: &Path) -> bool {
+        check_file_name(name).is_ok() && !self.is_partial(name)
+    }
+
+    fn is_partial(&self, name: &Path) -> bool {
+        match name.to_str().and_then(|n| self.parser.parse(n)) {
+            Some(info) => info.tp.is_partial(),
+            None => false,
+        }
+    }
+
+    // checks whether the cached copy of a file is older than the listed one
+    fn is_stale<N: AsRef<Path>>(&self, info: &FileInfo<N>) -> io::Result<bool> {
+        if !self.is_cacheable(info.name.as_ref()) {
+            return Ok(false);
+        }
+        let meta = match fs::metadata(self.cache_dir.join(info.name.as_ref())) {
+            Ok(meta) => meta,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if info.size.is_some() && info.size != Some(meta.len()) {
+            return Ok(true);
+        }
+        let cached_time = meta.modified()?;
+        let mtime = info.mtime.and_then(|mtime| {
+            let secs = u64::try_from(mtime.sec).ok()?;
+            Some(UNIX_EPOCH + Duration::new(secs, mtime.nsec as u32))
+        });
+        Ok(match mtime {
+            Some(mtime) => mtime > cached_time,
+            None => false,
+        })
+    }
+
+    fn cached_file(&self, name: &Path) -> io::Result<File> {
+        let path = self.cache_dir.join(name);
+        match File::open(&path) {
+            Ok(file) => return Ok(file),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        // download into a temporary file first, so an interrupted transfer never leaves a
+        // truncated file in the cache
+        fs::create_dir_all(&self.cache_dir)?;
+        let mut tmp_name = name.as_os_str().to_owned();
+        let counter = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        tmp_name.push(format!(".{}.{}.tmp", process::id(), counter));
+        let tmp_path = self.cache_dir.join(tmp_name);
+        let result = File::create(&tmp_path).and_then(|mut tmp| {
+            let mut src = self.inner.open_file(name)?;
+            io::copy(&mut src, &mut tmp)?;
+            tmp.sync_all()
+        });
+        if let Err(e) = result.and_then(|_| fs::rename(&tmp_path, &path)) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        File::open(path)
+    }
+}
+
+impl<B: Backend> Backend for CachingBackend<B> {
+    type FileName = B::FileName;
+    type FileNameIter = B::FileNameIter;
+    type FileStream = CachedFile<B::FileStream>;
+
+    fn file_names(&self) -> io::Result<Self::FileNameIter> {
+        self.inner.file_names()
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Self::FileStream> {
+        if !self.is_cacheable(name) {
+            self.inner.open_file(name).map(CachedFile::Remote)
+        } else {
+            self.cached_file(name).map(CachedFile::Local)
+        }
+    }
+
+    fn file_infos(&self) -> io::Result<Vec<FileInfo<Self::FileName>>> {
+        let infos = self.inner.file_infos()?;
+        for info in &infos {
+            if self.is_stale(info)? {
+                self.evict(info.name.as_ref())?;
+            }
+        }
+        Ok(infos)
+    }
+}
+
+/// Writes are forwarded to the inner backend, and the affected files evicted from the cache.
+impl<B: WritableBackend> WritableBackend for CachingBackend<B> {
+    type FileWriter = B::FileWriter;
+
+    fn create_file(&self, name: &Path) -> io::Result<Self::FileWriter> {
+        self.evict(name)?;
+        self.inner.create_file(name)
+    }
+
+    fn delete_file(&self, name: &Path) -> io::Result<()> {
+        self.evict(name)?;
+        self.inner.delete_file(name)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.evict(from)?;
+        self.evict(to)?;
+        self.inner.rename(from, to)
+    }
+}
+
+impl<B: Backend> RangeBackend for CachingBackend<B> {
+    type RangeStream = io::Take<CachedFile<B::FileStream>>;
+
+    fn open_range(&self, name: &Path, offset: u64, len: u64) -> io::Result<Self::RangeStream> {
+        let mut file = self.open_file(name)?;
+        match file {
+            CachedFile::Local(ref mut f) => {
+                f.seek(SeekFrom::Start(offset))?;
+            }
+            CachedFile::Remote(ref mut r) => {
+                io::copy(&mut r.take(offset), &mut io::sink())?;
+            }
+        }
+        Ok(file.take(len))
+    }
+}
+
+/// A file opened through a `CachingBackend`.
+#[derive(Debug)]
+pub enum CachedFile<R> {
+    /// The local copy of the file.
+    Local(File),
+    /// A file read directly from the inner backend, because it is not cacheable.
+    Remote(R),
+}
+
+impl<R: Read> Read for CachedFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            CachedFile::Local(ref mut f) => f.read(buf),
+            CachedFile::Remote(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::local::LocalBackend;
+    use std::env;
+
+    fn read_all<R: Read>(mut file: R) -> Vec<u8> {
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn cache_files() {
+        let cache_dir = env::temp_dir().join(format!("ruplicity-cache-test-{}", process::id()));
+        let backend = CachingBackend::new(LocalBackend::new("tests/backend"), &cache_dir);
+        let name = Path::new("file2");
+
+        assert_eq!(read_all(backend.open_file(name).unwrap()), b"bbbbbb\n");
+        assert!(cache_dir.join(name).exists());
+        // the cached copy is used from now on
+        fs::write(cache_dir.join(name), b"cached\n").unwrap();
+        assert_eq!(read_all(backend.open_file(name).unwrap()), b"cached\n");
+        assert_eq!(read_all(backend.open_range(name, 2, 3).unwrap()), b"che");
+        backend.evict(name).unwrap();
+        assert_eq!(read_all(backend.open_file(name).unwrap()), b"bbbbbb\n");
+
+        // a cached copy not matching the listed size is evicted
+        fs::write(cache_dir.join(name), b"stale\n").unwrap();
+        backend.file_infos().unwrap();
+        assert!(!cache_dir.join(name).exists());
+        assert_eq!(read_all(backend.open_file(name).unwrap()), b"bbbbbb\n");
+        backend.file_infos().unwrap();
+        assert!(cache_dir.join(name).exists());
+
+        // missing files are not cached
+        assert!(backend.open_file(Path::new("missing")).is_err());
+        assert!(!cache_dir.join("missing").exists());
+        fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn partial_names() {
+        let backend = CachingBackend::new(LocalBackend::new("tests/backend"), env::temp_dir());
+        let partial = |name| backend.is_partial(Path::new(name));
+        assert!(partial("duplicity-full.20150617T182545Z.manifest.part"));
+        assert!(partial("df.nq3pux.m.p"));
+        assert!(partial("dns.nq3pux.nq3pvs.st.p.z"));
+        assert!(!partial("duplicity-full.20150617T182545Z.manifest"));
+        assert!(!partial("dfs.nq3pux.st.z"));
+        // only the partial flag of the parsed name matters
+        assert!(!partial("duplicity-full.20150617T182545Z.vol1.difftar.gz"));
+        assert!(!partial("other.part"));
+
+        let naming = FileNaming::new().prefix("pre-");
+        let backend = CachingBackend::with_naming(backend.into_inner(), env::temp_dir(), &naming);
+        assert!(backend.is_partial(Path::new(
+            "pre-duplicity-full.20150617T182545Z.manifest.part"
+        )));
+    }
+
+    #[test]
+    fn invalid_names() {
+        let base_dir = env::temp_dir().join(format!("ruplicity-cache-names-{}", process::id()));
+        let cache_dir = base_dir.join("cache");
+        let backend = CachingBackend::new(LocalBackend::new("tests/backend"), &cache_dir);
+        // names escaping the cache directory are read remotely and never written locally
+        let name = Path::new("../backend/file2");
+        assert_eq!(read_all(backend.open_file(name).unwrap()), b"bbbbbb\n");
+        assert!(!base_dir.join("backend").exists());
+        assert!(!cache_dir.exists());
+        backend.evict(name).unwrap();
+    }
+}
@@ -23,11 +23,19 @@
 //! }
 //! ```
 
-use super::Backend;
+use super::{check_file_name, Backend, FileInfo, FileWriter, RangeBackend, WritableBackend};
 use std::ffi::OsString;
-use std::fs::{self, File};
-use std::io;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+#[cfg(feature = "async")]
+use futures::future::{self, BoxFuture};
+#[cfg(feature = "async")]
+use futures::io::AllowStdIo;
+use time::Timespec;
 
 /// Backend for some directory in the local file system.
 #[derive(Debug)]
@@ -38,6 +46,17 @@ pub struct LocalBackend {
 /// Iterator over a set of file names.
 pub struct FileNameIterator(fs::ReadDir);
 
+/// A file being written into a `LocalBackend`.
+///
+/// Contents are written into a hidden temporary file in the same directory, that is renamed to
+/// the final name on commit. If the writer is dropped before, the temporary file is removed.
+#[derive(Debug)]
+pub struct LocalWriter {
+    file: Option<File>,
+    tmp_path: PathBuf,
+    path: PathBuf,
+}
+
 impl LocalBackend {
     /// Creates a new local backend for the given directory.
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
@@ -45,6 +64,12 @@ impl LocalBackend {
             base_path: path.as_ref().to_path_buf(),
         }
     }
+
+    // file names used for writing must not escape the base directory
+    fn checked_path(&self, name: &Path) -> io::Result<PathBuf> {
+        check_file_name(name)?;
+        Ok(self.base_path.join(name))
+    }
 }
 
 impl Backend for LocalBackend {
@@ -62,6 +87,111 @@ impl Backend for LocalBackend {
         path.push(name);
         File::open(path)
     }
+
+    fn file_infos(&self) -> io::Result<Vec<FileInfo<OsString>>> {
+        let dir = fs::read_dir(self.base_path.as_path())?;
+        dir.map(|entry| {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            Ok(FileInfo {
+                name: entry.file_name(),
+                size: Some(meta.len()),
+                mtime: mtime(&meta),
+            })
+        })
+        .collect()
+    }
+}
+
+/// Asynchronous access to the local file system.
+///
+/// Note that file operations are performed in a blocking way. This implementation is provided
+/// for convenience (e.g. testing); an executor-specific backend should be preferred otherwise.
+#[cfg(feature = "async")]
+impl super::AsyncBackend for LocalBackend {
+    type FileName = OsString;
+    type FileNameIter = Vec<OsString>;
+    type FileStream = AllowStdIo<File>;
+
+    fn file_names(&self) -> BoxFuture<'_, io::Result<Self::FileNameIter>> {
+        Box::pin(future::ready(
+            Backend::file_names(self).map(Iterator::collect),
+        ))
+    }
+
+    fn open_file<'a>(&'a self, name: &'a Path) -> BoxFuture<'a, io::Result<Self::FileStream>> {
+        Box::pin(future::ready(
+            Backend::open_file(self, name).map(AllowStdIo::new),
+        ))
+    }
+
+    fn file_infos(&self) -> BoxFuture<'_, io::Result<Vec<FileInfo<OsString>>>> {
+        Box::pin(future::ready(Backend::file_infos(self)))
+    }
+}
+
+impl WritableBackend for LocalBackend {
+    type FileWriter = LocalWriter;
+
+    fn create_file(&self, name: &Path) -> io::Result<LocalWriter> {
+        let path = self.checked_path(name)?;
+        // the leading dot keeps the temporary file hidden, and prevents it to be mistaken for a
+        // backup file
+        let mut tmp_name = OsString::from(".");
+        tmp_name.push(name.as_os_str());
+        tmp_name.push(format!(".{}.tmp", process::id()));
+        let tmp_path = self.base_path.join(tmp_name);
+        let file = File::create(&tmp_path)?;
+        Ok(LocalWriter {
+            file: Some(file),
+            tmp_path,
+            path,
+        })
+    }
+
+    fn delete_file(&self, name: &Path) -> io::Result<()> {
+        fs::remove_file(self.checked_path(name)?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(self.checked_path(from)?, self.checked_path(to)?)
+    }
+}
+
+impl FileWriter for LocalWriter {
+    fn commit(mut self) -> io::Result<()> {
+        let file = self.file.take().unwrap();
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.tmp_path, &self.path)
+    }
+}
+
+impl Write for LocalWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for LocalWriter {
+    fn drop(&mut self) {
+        // remove the temporary file if not committed, or if the commit failed
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+impl RangeBackend for LocalBackend {
+    type RangeStream = io::Take<File>;
+
+    fn open_range(&self, name: &Path, offset: u64, len: u64) -> io::Result<Self::RangeStream> {
+        let mut file = Backend::open_file(self, name)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file.take(len))
+    }
 }
 
 impl Iterator for FileNameIterator {
@@ -77,10 +207,19 @@ impl Iterator for FileNameIterator {
     }
 }
 
+fn mtime(meta: &Metadata) -> Option<Timespec> {
+    let elapsed = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(Timespec::new(
+        elapsed.as_secs() as i64,
+        elapsed.subsec_nanos() as i32,
+    ))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::backend::Backend;
+    use crate::backend::{Backend, RangeBackend};
+    use std::env;
 
     #[test]
     fn multi_chain_files() {
@@ -111,4 +250,69 @@ mod test {
         ];
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn open_range() {
+        let backend = LocalBackend::new("tests/backend");
+        let read_range = |offset, len| {
+            let mut file = backend.open_range(Path::new("file2"), offset, len).unwrap();
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents).unwrap();
+            contents
+        };
+        assert_eq!(read_range(0, 3), b"bbb");
+        assert_eq!(read_range(4, 100), b"bb\n");
+        assert_eq!(read_range(100, 3), b"");
+    }
+
+    #[test]
+    fn file_infos() {
+        let backend = LocalBackend::new("tests/backend");
+        let mut infos = backend.file_infos().unwrap();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        let actual = infos
+            .iter()
+            .map(|i| (i.name.to_str().unwrap(), i.size))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec![("file1", Some(4)), ("file2", Some(7))]);
+        assert!(infos.iter().all(|i| i.mtime.is_some()));
+    }
+
+    #[test]
+    fn writes() {
+        let dir = env::temp_dir().join(format!("ruplicity-local-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let backend = LocalBackend::new(&dir);
+        let name = Path::new("file");
+        let names = || {
+            let mut names = Backend::file_names(&backend).unwrap().collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        let mut writer = backend.create_file(name).unwrap();
+        writer.write_all(b"contents").unwrap();
+        assert!(!dir.join(name).exists());
+        writer.commit().unwrap();
+        assert_eq!(fs::read(dir.join(name)).unwrap(), b"contents");
+        assert_eq!(names(), vec!["file"]);
+
+        // an aborted write leaves no trace
+        let mut writer = backend.create_file(name).unwrap();
+        writer.write_all(b"other").unwrap();
+        drop(writer);
+        assert_eq!(fs::read(dir.join(name)).unwrap(), b"contents");
+        assert_eq!(names(), vec!["file"]);
+
+        backend.rename(name, Path::new("renamed")).unwrap();
+        assert_eq!(names(), vec!["renamed"]);
+        backend.delete_file(Path::new("renamed")).unwrap();
+        assert!(names().is_empty());
+
+        // names outside the base directory are rejected
+        assert!(backend.create_file(Path::new("../file")).is_err());
+        assert!(backend.delete_file(Path::new("/file")).is_err());
+        assert!(backend.rename(Path::new("a/b"), name).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,368 @@
+//! In-memory backend.
+//!
+//! This sub-module provides a backend keeping all the files in memory. It is useful to build
+//! backups in tests, or to embed small backups into an application without touching the disk.
+//! Backups can be loaded from a tar archive, or from a zip archive with the `zip` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use ruplicity::backend::{Backend, FileWriter, WritableBackend};
+//! use ruplicity::backend::memory::MemoryBackend;
+//! use std::io::{Read, Write};
+//! use std::path::Path;
+//!
+//! let backend = MemoryBackend::new();
+//! backend.insert("file1", b"aaa\n".to_vec());
+//! // simulate an upload
+//! let mut writer = backend.create_file(Path::new("file2")).unwrap();
+//! writer.write_all(b"bbb\n").unwrap();
+//! writer.commit().unwrap();
+//!
+//! let mut contents = String::new();
+//! let mut file = backend.open_file(Path::new("file2")).unwrap();
+//! file.read_to_string(&mut contents).unwrap();
+//! assert_eq!(contents, "bbb\n");
+//! assert_eq!(backend.file_names().unwrap().len(), 2);
+//! ```
+
+use std::collections::BTreeMap;
+#[cfg(feature = "zip")]
+use std::io::Seek;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(feature = "async")]
+use futures::future::{self, BoxFuture};
+#[cfg(feature = "async")]
+use futures::io::Cursor as AsyncCursor;
+use tar;
+
+use super::{check_file_name, Backend, FileInfo, FileWriter, WritableBackend};
+
+/// Backend storing files in memory.
+///
+/// Files can be added and removed through a shared reference, so a backup opened on this
+/// backend can observe new uploads.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    files: Arc<Mutex<FileMap>>,
+}
+
+/// A file read from a `MemoryBackend`.
+pub type MemoryFile = Cursor<Arc<[u8]>>;
+
+/// A file being written into a `MemoryBackend`.
+///
+/// The written contents become visible in the backend only after a call to `commit`. If the
+/// writer is dropped before, the file is discarded, like an interrupted upload.
+#[derive(Debug)]
+pub struct MemoryWriter {
+    files: Arc<Mutex<FileMap>>,
+    name: PathBuf,
+    buf: Vec<u8>,
+}
+
+type FileMap = BTreeMap<PathBuf, Arc<[u8]>>;
+
+impl MemoryBackend {
+    /// Creates a new empty backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a backend containing all the regular files of a tar archive.
+    ///
+    /// Files are stored with their name inside the archive. Directories and other special
+    /// entries are ignored.
+    ///
+    /// # Errors
+    /// An `InvalidData` error is returned if the archive is malformed, or if a file name is not a
+    /// plain name (e.g. an absolute or a nested path).
+    pub fn from_tar<R: Read>(archive: R) -> io::Result<Self> {
+        let backend = MemoryBackend::new();
+        let mut archive = tar::Archive::new(archive);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = archive_name(&entry.path()?)?;
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            backend.insert(name, contents);
+        }
+        Ok(backend)
+    }
+
+    /// Creates a backend containing all the files of a zip archive.
+    ///
+    /// Files are stored with their name inside the archive, and directories are ignored. This
+    /// function requires the `zip` feature.
+    ///
+    /// # Errors
+    /// An `InvalidData` error is returned if the archive is malformed, or if a file name is not a
+    /// plain name (e.g. an absolute or a nested path).
+    #[cfg(feature = "zip")]
+    pub fn from_zip<R: Read + Seek>(archive: R) -> io::Result<Self> {
+        let backend = MemoryBackend::new();
+        let mut archive = zip::ZipArchive::new(archive)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+            let name = archive_name(Path::new(file.name()))?;
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            backend.insert(name, contents);
+        }
+        Ok(backend)
+    }
+
+    /// Adds a file to the backend, replacing a previous file with the same name.
+    pub fn insert<P: AsRef<Path>>(&self, name: P, contents: Vec<u8>) {
+        self.lock()
+            .insert(name.as_ref().to_path_buf(), contents.into());
+    }
+
+    /// Removes a file from the backend, returning whether it was present.
+    pub fn remove<P: AsRef<Path>>(&self, name: P) -> bool {
+        self.lock().remove(name.as_ref()).is_some()
+    }
+
+    /// Returns a copy of the contents of the given file, if present.
+    pub fn get<P: AsRef<Path>>(&self, name: P) -> Option<Vec<u8>> {
+        self.lock().get(name.as_ref()).map(|c| c.to_vec())
+    }
+
+    /// Returns whether the given file is present.
+    pub fn contains<P: AsRef<Path>>(&self, name: P) -> bool {
+        self.lock().contains_key(name.as_ref())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FileMap> {
+        lock(&self.files)
+    }
+
+    fn not_found(name: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("file not found: {}", name.display()),
+        )
+    }
+}
+
+impl Backend for MemoryBackend {
+    type FileName = PathBuf;
+    type FileNameIter = Vec<PathBuf>;
+    type FileStream = MemoryFile;
+
+    fn file_names(&self) -> io::Result<Self::FileNameIter> {
+        Ok(self.lock().keys().cloned().collect())
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Self::FileStream> {
+        match self.lock().get(name) {
+            Some(contents) => Ok(Cursor::new(contents.clone())),
+            None => Err(Self::not_found(name)),
+        }
+    }
+
+    fn file_infos(&self) -> io::Result<Vec<FileInfo<PathBuf>>> {
+        Ok(self
+            .lock()
+            .iter()
+            .map(|(name, contents)| FileInfo {
+                name: name.clone(),
+                size: Some(contents.len() as u64),
+                mtime: None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(feature = "async")]
+impl super::AsyncBackend for MemoryBackend {
+    type FileName = PathBuf;
+    type FileNameIter = Vec<PathBuf>;
+    type FileStream = AsyncCursor<Arc<[u8]>>;
+
+    fn file_names(&self) -> BoxFuture<'_, io::Result<Self::FileNameIter>> {
+        Box::pin(future::ready(Backend::file_names(self)))
+    }
+
+    fn open_file<'a>(&'a self, name: &'a Path) -> BoxFuture<'a, io::Result<Self::FileStream>> {
+        let file = Backend::open_file(self, name).map(|f| AsyncCursor::new(f.into_inner()));
+        Box::pin(future::ready(file))
+    }
+
+    fn file_infos(&self) -> BoxFuture<'_, io::Result<Vec<FileInfo<PathBuf>>>> {
+        Box::pin(future::ready(Backend::file_infos(self)))
+    }
+}
+
+impl WritableBackend for MemoryBackend {
+    type FileWriter = MemoryWriter;
+
+    fn create_file(&self, name: &Path) -> io::Result<MemoryWriter> {
+        Ok(MemoryWriter {
+            files: self.files.clone(),
+            name: name.to_path_buf(),
+            buf: Vec::new(),
+        })
+    }
+
+    fn delete_file(&self, name: &Path) -> io::Result<()> {
+        if self.remove(name) {
+            Ok(())
+        } else {
+            Err(Self::not_found(name))
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.lock();
+        let contents = files.remove(from).ok_or_else(|| Self::not_found(from))?;
+        files.insert(to.to_path_buf(), contents);
+        Ok(())
+    }
+}
+
+impl FileWriter for MemoryWriter {
+    fn commit(self) -> io::Result<()> {
+        lock(&self.files).insert(self.name, self.buf.into());
+        Ok(())
+    }
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn lock(files: &Mutex<FileMap>) -> MutexGuard<'_, FileMap> {
+    // a panic while holding the lock cannot leave the map in an inconsistent state
+    files.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// checks the name of a file read from an archive, which is then used as a backend file name
+fn archive_name(name: &Path) -> io::Result<PathBuf> {
+    check_file_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(name.to_path_buf())
+}
+
+// returns one of the test backups, embedded in the test binaries so that tests do not depend on
+// the working directory
+#[cfg(test)]
+pub(crate) fn fixture(name: &str) -> MemoryBackend {
+    let archive: &[u8] = match name {
+        "single_vol" => include_bytes!("../../tests/backups/single_vol.tar"),
+        "multi_chain" => include_bytes!("../../tests/backups/multi_chain.tar"),
+        _ => panic!("unknown test backup: {}", name),
+    };
+    MemoryBackend::from_tar(archive).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::local::LocalBackend;
+    use crate::collections::Collections;
+
+    // the embedded test backups are the same as the ones on disk, used in the examples
+    fn assert_same_as_dir(memory: &MemoryBackend, dir: &str) {
+        let local = LocalBackend::new(dir);
+        let mut local_names = local
+            .file_names()
+            .unwrap()
+            .map(PathBuf::from)
+            .filter(|name| Path::new(dir).join(name).is_file())
+            .collect::<Vec<_>>();
+        local_names.sort();
+        assert_eq!(memory.file_names().unwrap(), local_names);
+        for name in &local_names {
+            let mut expected = Vec::new();
+            local
+                .open_file(name)
+                .unwrap()
+                .read_to_end(&mut expected)
+                .unwrap();
+            assert_eq!(memory.get(name).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn same_as_local() {
+        assert_same_as_dir(&fixture("single_vol"), "tests/backups/single_vol");
+        let memory = fixture("multi_chain");
+        assert_same_as_dir(&memory, "tests/backups/multi_chain");
+        let coll = Collections::from_file_infos(memory.file_infos().unwrap());
+        assert_eq!(coll.backup_chains().count(), 2);
+        assert!(coll.backup_chains().all(|c| c.total_size().is_some()));
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn from_zip() {
+        let archive = include_bytes!("../../tests/backups/multi_chain.zip");
+        let memory = MemoryBackend::from_zip(Cursor::new(&archive[..])).unwrap();
+        assert_same_as_dir(&memory, "tests/backups/multi_chain");
+        assert!(MemoryBackend::from_zip(Cursor::new(&b"not a zip"[..])).is_err());
+
+        for name in &["../file", "/file", "dir/file"] {
+            let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+            writer.start_file(*name, Default::default()).unwrap();
+            writer.write_all(b"contents").unwrap();
+            let archive = writer.finish().unwrap().into_inner();
+            let err = MemoryBackend::from_zip(Cursor::new(archive)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+
+    #[test]
+    fn invalid_tar_names() {
+        for name in &[&b"../file"[..], b"/file", b"dir/file", b"."] {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name);
+            header.set_size(8);
+            header.set_cksum();
+            let mut builder = tar::Builder::new(Vec::new());
+            builder.append(&header, &b"contents"[..]).unwrap();
+            let archive = builder.into_inner().unwrap();
+            let err = MemoryBackend::from_tar(&archive[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn writes() {
+        let backend = MemoryBackend::new();
+        let name = Path::new("file");
+        let mut writer = backend.create_file(name).unwrap();
+        writer.write_all(b"contents").unwrap();
+        // not visible until committed
+        assert!(!backend.contains(name));
+        writer.commit().unwrap();
+        assert_eq!(backend.get(name).unwrap(), b"contents");
+
+        // an aborted write leaves the backend untouched
+        let mut writer = backend.create_file(name).unwrap();
+        writer.write_all(b"other").unwrap();
+        drop(writer);
+        assert_eq!(backend.get(name).unwrap(), b"contents");
+
+        backend.rename(name, Path::new("renamed")).unwrap();
+        assert!(!backend.contains(name));
+        assert!(backend.rename(name, Path::new("renamed")).is_err());
+        backend.delete_file(Path::new("renamed")).unwrap();
+        assert!(backend.delete_file(Path::new("renamed")).is_err());
+        assert!(backend.file_names().unwrap().is_empty());
+    }
+}
@@ -5,9 +5,24 @@
 //! a network drive, a cloud service, or whatever.
 
 pub mod local;
+pub mod memory;
 
-use std::io::{self, Read};
-use std::path::Path;
+mod caching;
+mod retry;
+
+pub use self::caching::{CachedFile, CachingBackend};
+pub use self::retry::{RetryBackend, RetryFile};
+
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+
+#[cfg(feature = "async")]
+use futures::executor::block_on;
+#[cfg(feature = "async")]
+use futures::future::BoxFuture;
+#[cfg(feature = "async")]
+use futures::io::{AsyncRead, AsyncReadExt};
+use time::Timespec;
 
 /// A trait used to provide a transport layer for backup files.
 pub trait Backend {
@@ -27,4 +42,183 @@ pub trait Backend {
 
     /// Opens a file for reading.
     fn open_file(&self, name: &Path) -> io::Result<Self::FileStream>;
+
+    /// Returns a list of available files, along with their metadata.
+    ///
+    /// Backends able to get size and modification time of a file cheaply (e.g. in the same
+    /// request used to list the files) should override this function. The default implementation
+    /// returns the file names given by `file_names`, without any metadata.
+    fn file_infos(&self) -> io::Result<Vec<FileInfo<Self::FileName>>> {
+        Ok(self
+            .file_names()?
+            .into_iter()
+            .map(FileInfo::from_name)
+            .collect())
+    }
+}
+
+/// A trait used to provide an asynchronous transport layer for backup files.
+///
+/// This is the asynchronous counterpart of `Backend`. Files are exposed as `futures::io::AsyncRead`
+/// streams; adapters for other runtimes (e.g. `tokio-util`'s `compat` module) can be used to
+/// implement it on top of their I/O types.
+///
+/// Backends must be `Send` and `Sync`, so that the futures returned by an asynchronous `Backup`
+/// can be spawned on a multi-threaded executor. The synchronous functions of a `Backup` opened
+/// on such a backend block the current thread, so they must not be called from an executor
+/// thread.
+#[cfg(feature = "async")]
+pub trait AsyncBackend: Send + Sync {
+    /// A file name. It must be convertible to a `Path`.
+    type FileName: AsRef<Path> + Send;
+
+    /// An iterator over filenames.
+    type FileNameIter: IntoIterator<Item = Self::FileName> + Send;
+
+    /// A file managed by the backend. It must implement the `AsyncRead` trait.
+    type FileStream: AsyncRead + Unpin + Send;
+
+    /// Returns a list of available file names.
+    ///
+    /// The file names returned should have an extension, and do not contain the base path.
+    fn file_names(&self) -> BoxFuture<'_, io::Result<Self::FileNameIter>>;
+
+    /// Opens a file for reading.
+    fn open_file<'a>(&'a self, name: &'a Path) -> BoxFuture<'a, io::Result<Self::FileStream>>;
+
+    /// Returns a list of available files, along with their metadata.
+    ///
+    /// See `Backend::file_infos`.
+    fn file_infos(&self) -> BoxFuture<'_, io::Result<Vec<FileInfo<Self::FileName>>>> {
+        Box::pin(async move {
+            Ok(self
+                .file_names()
+                .await?
+                .into_iter()
+                .map(FileInfo::from_name)
+                .collect())
+        })
+    }
+}
+
+// Allows to access an asynchronous backend through the synchronous interface, by blocking the
+// current thread until each operation completes.
+//
+// It must never be used on a thread running an executor: if the I/O of the backend is driven by
+// that executor (e.g. a single threaded runtime) the operations never complete, and some runtimes
+// panic when blocked. For this reason the asynchronous functions of `Backup` never use it.
+#[cfg(feature = "async")]
+pub(crate) struct Blocking<'a, B>(pub &'a B);
+
+// A file read from an asynchronous backend, blocking the current thread on each read.
+#[cfg(feature = "async")]
+pub(crate) struct BlockingFile<R>(R);
+
+/// Information about a file managed by a backend.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileInfo<N> {
+    /// The file name, as returned by `Backend::file_names`.
+    pub name: N,
+    /// The file size in bytes, if known.
+    pub size: Option<u64>,
+    /// The last modification time, if known.
+    pub mtime: Option<Timespec>,
+}
+
+/// A trait for backends allowing to upload, delete and rename backup files.
+///
+/// This is an optional capability, required by the operations modifying a backup (e.g. cleanup or
+/// pruning).
+pub trait WritableBackend: Backend {
+    /// A file being written through the backend.
+    type FileWriter: FileWriter;
+
+    /// Creates a new file for writing.
+    ///
+    /// The file becomes visible under the given name only after the writer is committed, replacing
+    /// an existing file with the same name, if any. If the writer is dropped before, the file is
+    /// discarded. In this way a failed upload never leaves a truncated file behind.
+    fn create_file(&self, name: &Path) -> io::Result<Self::FileWriter>;
+
+    /// Deletes a file.
+    fn delete_file(&self, name: &Path) -> io::Result<()>;
+
+    /// Renames a file, replacing the destination if it already exists.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+}
+
+/// A file being written through a `WritableBackend`.
+pub trait FileWriter: Write {
+    /// Completes the file, making it visible in the backend.
+    fn commit(self) -> io::Result<()>;
+}
+
+/// A trait for backends able to read a portion of a file.
+///
+/// This is an optional capability. Backends implementing it allow to fetch a small piece of a
+/// big file (e.g. a single member of a volume) without transferring everything before it.
+pub trait RangeBackend: Backend {
+    /// A portion of a file managed by the backend. It must implement the `Read` trait.
+    type RangeStream: Read;
+
+    /// Opens a file for reading at most `len` bytes, starting from `offset`.
+    ///
+    /// The returned stream ends earlier if the file is shorter than `offset + len` bytes.
+    fn open_range(&self, name: &Path, offset: u64, len: u64) -> io::Result<Self::RangeStream>;
+}
+
+impl<N> FileInfo<N> {
+    /// Creates a file info with the given name and no metadata.
+    pub fn from_name(name: N) -> Self {
+        FileInfo {
+            name,
+            size: None,
+            mtime: None,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a, B: AsyncBackend> Backend for Blocking<'a, B> {
+    type FileName = B::FileName;
+    type FileNameIter = B::FileNameIter;
+    type FileStream = BlockingFile<B::FileStream>;
+
+    fn file_names(&self) -> io::Result<Self::FileNameIter> {
+        block_on(self.0.file_names())
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Self::FileStream> {
+        block_on(self.0.open_file(name)).map(BlockingFile)
+    }
+
+    fn file_infos(&self) -> io::Result<Vec<FileInfo<Self::FileName>>> {
+        block_on(self.0.file_infos())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> Read for BlockingFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        block_on(self.0.read(buf))
+    }
+}
+
+impl<N: AsRef<Path>> AsRef<Path> for FileInfo<N> {
+    fn as_ref(&self) -> &Path {
+        self.name.as_ref()
+    }
+}
+
+// checks that a file name is a single plain path component, so that joining it to a local
+// directory never escapes from it
+pub(crate) fn check_file_name(name: &Path) -> io::Result<()> {
+    let mut components = name.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid file name: {}", name.display()),
+        )),
+    }
 }
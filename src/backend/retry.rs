@@ -0,0 +1,302 @@
+//! Backend wrapper retrying failed operations.
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::{Backend, FileInfo, WritableBackend};
+
+/// A backend that retries the operations failed with a transient error.
+///
+/// An error is considered transient if its kind suggests a temporary network or resource
+/// problem (e.g. `TimedOut`, `ConnectionReset`, `Interrupted`). Failed operations are retried up
+/// to a maximum number of times, waiting an exponentially increasing time between each attempt.
+///
+/// Errors happening while reading a file are retried too: the file is opened again and the
+/// bytes already read are skipped, so a network blip does not abort a long transfer.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use ruplicity::Backup;
+/// use ruplicity::backend::RetryBackend;
+/// use ruplicity::backend::local::LocalBackend;
+///
+/// let backend = RetryBackend::new(LocalBackend::new("tests/backups/single_vol"))
+///     .max_retries(5)
+///     .initial_backoff(Duration::from_millis(200));
+/// let backup = Backup::new(backend).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct RetryBackend<B> {
+    inner: Arc<B>,
+    policy: RetryPolicy,
+}
+
+/// A file opened through a `RetryBackend`.
+///
+/// It is able to reopen the file if a transient error occurs while reading.
+#[derive(Debug)]
+pub struct RetryFile<B: Backend> {
+    backend: Arc<B>,
+    policy: RetryPolicy,
+    name: PathBuf,
+    stream: Option<B::FileStream>,
+    position: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl<B: Backend> RetryBackend<B> {
+    /// Creates a new retrying backend with default settings.
+    ///
+    /// By default an operation is retried 3 times, starting with a 500 milliseconds delay.
+    pub fn new(inner: B) -> Self {
+        RetryBackend {
+            inner: Arc::new(inner),
+            policy: RetryPolicy {
+                max_retries: 3,
+                initial_backoff: Duration::from_millis(500),
+                max_backoff: Duration::from_secs(30),
+            },
+        }
+    }
+
+    /// Sets the maximum number of retries for a single operation.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.policy.max_retries = retries;
+        self
+    }
+
+    /// Sets the delay before the first retry. It is doubled at every subsequent retry.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.policy.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum delay between two retries.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.policy.max_backoff = backoff;
+        self
+    }
+
+    /// Returns a reference to the inner backend.
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B: Backend> Backend for RetryBackend<B> {
+    type FileName = B::FileName;
+    type FileNameIter = B::FileNameIter;
+    type FileStream = RetryFile<B>;
+
+    fn file_names(&self) -> io::Result<Self::FileNameIter> {
+        self.policy.retry(|| self.inner.file_names())
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Self::FileStream> {
+        let stream = self.policy.retry(|| self.inner.open_file(name))?;
+        Ok(RetryFile {
+            backend: self.inner.clone(),
+            policy: self.policy,
+            name: name.to_path_buf(),
+            stream: Some(stream),
+            position: 0,
+        })
+    }
+
+    fn file_infos(&self) -> io::Result<Vec<FileInfo<Self::FileName>>> {
+        self.policy.retry(|| self.inner.file_infos())
+    }
+}
+
+/// Deletions and renames are retried. File creation is retried as well, but errors while writing
+/// are not, since the backend can't replay the data already written.
+impl<B: WritableBackend> WritableBackend for RetryBackend<B> {
+    type FileWriter = B::FileWriter;
+
+    fn create_file(&self, name: &Path) -> io::Result<Self::FileWriter> {
+        self.policy.retry(|| self.inner.create_file(name))
+    }
+
+    fn delete_file(&self, name: &Path) -> io::Result<()> {
+        self.policy.retry(|| self.inner.delete_file(name))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.policy.retry(|| self.inner.rename(from, to))
+    }
+}
+
+impl<B: Backend> RetryFile<B> {
+    fn reopen(&mut self) -> io::Result<()> {
+        let mut stream = self.backend.open_file(&self.name)?;
+        let skipped = io::copy(&mut (&mut stream).take(self.position), &mut io::sink())?;
+        if skipped < self.position {
+            // a changed file cannot be read again, so the error is not transient
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file shrunk while reading",
+            ));
+        }
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+impl<B: Backend> Read for RetryFile<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let policy = self.policy;
+        let read = policy.retry(|| {
+            if self.stream.is_none() {
+                self.reopen()?;
+            }
+            let result = self.stream.as_mut().unwrap().read(buf);
+            if result.is_err() {
+                // the stream state is unknown after an error: drop it
+                self.stream = None;
+            }
+            result
+        })?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl RetryPolicy {
+    fn retry<T, F>(&self, mut op: F) -> io::Result<T>
+    where
+        F: FnMut() -> io::Result<T>,
+    {
+        let mut backoff = self.initial_backoff;
+        let mut retries = 0;
+        loop {
+            match op() {
+                Err(ref e) if retries < self.max_retries && is_transient(e) => {
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    use std::io::ErrorKind::*;
+
+    // the crate `matches!` macro does not support alternatives
+    std::matches!(
+        err.kind(),
+        Interrupted
+            | TimedOut
+            | WouldBlock
+            | ConnectionReset
+            | ConnectionAborted
+            | ConnectionRefused
+            | NotConnected
+            | BrokenPipe
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::local::LocalBackend;
+    use std::cell::Cell;
+    use std::fs::File;
+    use std::rc::Rc;
+
+    // fails the first `failures` opens, and the first `read_failures` reads after 2 bytes
+    struct FlakyBackend {
+        inner: LocalBackend,
+        failures: Cell<u32>,
+        read_failures: Rc<Cell<u32>>,
+        kind: io::ErrorKind,
+    }
+
+    struct FlakyFile {
+        inner: File,
+        read: usize,
+        read_failures: Rc<Cell<u32>>,
+    }
+
+    impl Backend for FlakyBackend {
+        type FileName = <LocalBackend as Backend>::FileName;
+        type FileNameIter = <LocalBackend as Backend>::FileNameIter;
+        type FileStream = FlakyFile;
+
+        fn file_names(&self) -> io::Result<Self::FileNameIter> {
+            self.inner.file_names()
+        }
+
+        fn open_file(&self, name: &Path) -> io::Result<FlakyFile> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err(io::Error::new(self.kind, "flaky"));
+            }
+            Ok(FlakyFile {
+                inner: self.inner.open_file(name)?,
+                read: 0,
+                read_failures: self.read_failures.clone(),
+            })
+        }
+    }
+
+    impl Read for FlakyFile {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.read >= 2 && self.read_failures.get() > 0 {
+                self.read_failures.set(self.read_failures.get() - 1);
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "flaky"));
+            }
+            let n = self.inner.read(&mut buf[..1])?;
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    fn flaky_backend(failures: u32, kind: io::ErrorKind) -> RetryBackend<FlakyBackend> {
+        let backend = FlakyBackend {
+            inner: LocalBackend::new("tests/backend"),
+            failures: Cell::new(failures),
+            read_failures: Rc::new(Cell::new(2)),
+            kind,
+        };
+        RetryBackend::new(backend).initial_backoff(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn retry_open() {
+        let backend = flaky_backend(3, io::ErrorKind::TimedOut);
+        assert!(backend.open_file(Path::new("file1")).is_ok());
+
+        let backend = flaky_backend(4, io::ErrorKind::TimedOut);
+        assert!(backend.open_file(Path::new("file1")).is_err());
+
+        // non transient errors are not retried
+        let backend = flaky_backend(1, io::ErrorKind::PermissionDenied);
+        assert!(backend.open_file(Path::new("file1")).is_err());
+        // neither are truncated files
+        let backend = flaky_backend(1, io::ErrorKind::UnexpectedEof);
+        assert!(backend.open_file(Path::new("file1")).is_err());
+    }
+
+    #[test]
+    fn retry_read() {
+        let backend = flaky_backend(0, io::ErrorKind::TimedOut);
+        let mut contents = String::new();
+        let mut file = backend.open_file(Path::new("file2")).unwrap();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "bbbbbb\n");
+    }
+}
@@ -0,0 +1,258 @@
+//! Detection and removal of extraneous backup files.
+//!
+//! This sub-module implements the duplicity `cleanup` command. An interrupted backup leaves
+//! behind files that are not part of any usable snapshot: partially written manifests and
+//! signatures, volumes without a manifest, and incremental files whose parent is missing. These
+//! files are collected in a `CleanupPlan`, that can be inspected and then executed with a backend
+//! able to delete files.
+//!
+//! # Example
+//!
+//! ```
+//! use ruplicity::Backup;
+//! use ruplicity::backend::local::LocalBackend;
+//!
+//! let backup = Backup::new(LocalBackend::new("tests/backups/multi_chain")).unwrap();
+//! let plan = backup.cleanup();
+//! // a backup without interruptions has nothing to clean
+//! assert!(plan.is_empty());
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::Path;
+
+use crate::backend::WritableBackend;
+use crate::collections::{BackupSet, Collections, SignatureFile};
+
+/// The list of extraneous files found in a backup.
+///
+/// Nothing is actually deleted until `execute` is called, so the plan can be used as a dry run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CleanupPlan {
+    files: Vec<ExtraneousFile>,
+}
+
+/// A file to be removed by a cleanup operation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExtraneousFile {
+    /// The name of the file.
+    pub name: String,
+    /// Why the file is not needed.
+    pub reason: Reason,
+}
+
+/// The reason why a file is extraneous.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Reason {
+    /// The file belongs to a backup set or a signature that has not been completely written.
+    Incomplete,
+    /// The file belongs to an incremental backup set or signature that depends on a missing or
+    /// incomplete one.
+    Orphaned,
+}
+
+impl CleanupPlan {
+    /// Computes the cleanup plan for the given collections.
+    pub fn new(collections: &Collections) -> Self {
+        let mut plan = CleanupPlan::default();
+        for chain in collections.backup_chains() {
+            let sets = Some(chain.full_set()).into_iter().chain(chain.inc_sets());
+            plan.add_broken(
+                sets,
+                |set| set.is_partial() || !set.is_complete(),
+                |p, set, r| p.add_set(set, r),
+            );
+        }
+        for set in collections.orphaned_sets() {
+            plan.add_set(set, Reason::Orphaned);
+        }
+        for chain in collections.signature_chains() {
+            let sigs = Some(chain.full_signature())
+                .into_iter()
+                .chain(chain.inc_signatures());
+            plan.add_broken(sigs, |sig| sig.partial, CleanupPlan::add_sig);
+        }
+        for sig in collections.orphaned_signatures() {
+            let reason = if sig.partial {
+                Reason::Incomplete
+            } else {
+                Reason::Orphaned
+            };
+            plan.add_sig(sig, reason);
+        }
+        plan
+    }
+
+    /// Returns the extraneous files found.
+    pub fn extraneous_files(&self) -> &[ExtraneousFile] {
+        &self.files
+    }
+
+    /// Returns the names of the files to be removed.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|f| f.name.as_ref())
+    }
+
+    /// Returns whether there is nothing to remove.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Deletes the files in the plan by using the given backend.
+    ///
+    /// # Errors
+    /// The operation stops at the first error returned by the backend. Files already missing
+    /// are ignored.
+    pub fn execute<B: WritableBackend>(&self, backend: &B) -> io::Result<()> {
+        for file in self.files() {
+            match backend.delete_file(Path::new(file)) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                res => res?,
+            }
+        }
+        Ok(())
+    }
+
+    // adds the first incomplete element of a chain, and all the following ones, since they
+    // depend on it
+    fn add_broken<'a, T: 'a, I, P, F>(&mut self, chain: I, is_incomplete: P, mut add: F)
+    where
+        I: Iterator<Item = &'a T>,
+        P: Fn(&T) -> bool,
+        F: FnMut(&mut Self, &T, Reason),
+    {
+        let mut reason = None;
+        for elem in chain {
+            if reason.is_none() && is_incomplete(elem) {
+                reason = Some(Reason::Incomplete);
+            }
+            if let Some(r) = reason {
+                add(self, elem, r);
+                reason = Some(Reason::Orphaned);
+            }
+        }
+    }
+
+    fn add_set(&mut self, set: &BackupSet, reason: Reason) {
+        self.files
+            .extend(set.file_names().map(|name| ExtraneousFile {
+                name: name.to_owned(),
+                reason,
+            }));
+    }
+
+    fn add_sig(&mut self, sig: &SignatureFile, reason: Reason) {
+        self.files.push(ExtraneousFile {
+            name: sig.file_name.clone(),
+            reason,
+        });
+    }
+}
+
+impl Display for CleanupPlan {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.files.is_empty() {
+            return writeln!(f, "No extraneous files found, nothing deleted.");
+        }
+        writeln!(f, "Found {} extraneous files:", self.files.len())?;
+        for file in &self.files {
+            writeln!(f, "  {} ({})", file.name, file.reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Reason::Incomplete => write!(f, "incomplete"),
+            Reason::Orphaned => write!(f, "orphaned"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn extraneous(plan: &CleanupPlan) -> Vec<(&str, Reason)> {
+        plan.extraneous_files()
+            .iter()
+            .map(|f| (f.name.as_ref(), f.reason))
+            .collect()
+    }
+
+    #[test]
+    fn complete() {
+        let coll = Collections::from_filenames(&[
+            "duplicity-full.20160108T223144Z.manifest",
+            "duplicity-full.20160108T223144Z.vol1.difftar.gz",
+            "duplicity-full-signatures.20160108T223144Z.sigtar.gz",
+            "duplicity-inc.20160108T223144Z.to.20160108T223159Z.manifest",
+            "duplicity-inc.20160108T223144Z.to.20160108T223159Z.vol1.difftar.gz",
+            "duplicity-new-signatures.20160108T223144Z.to.20160108T223159Z.sigtar.gz",
+        ]);
+        assert!(CleanupPlan::new(&coll).is_empty());
+    }
+
+    #[test]
+    fn interrupted() {
+        let coll = Collections::from_filenames(&[
+            "duplicity-full.20160108T223144Z.manifest",
+            "duplicity-full.20160108T223144Z.vol1.difftar.gz",
+            "duplicity-full-signatures.20160108T223144Z.sigtar.gz",
+            // volume without manifest
+            "duplicity-inc.20160108T223144Z.to.20160108T223159Z.vol1.difftar.gz",
+            // depends on the previous one
+            "duplicity-inc.20160108T223159Z.to.20160108T223217Z.manifest",
+            "duplicity-inc.20160108T223159Z.to.20160108T223217Z.vol1.difftar.gz",
+            // partial manifest and signature
+            "duplicity-full.20160108T223209Z.manifest.part",
+            "duplicity-full.20160108T223209Z.vol1.difftar.gz",
+            "duplicity-full-signatures.20160108T223209Z.sigtar.part",
+            // orphans
+            "duplicity-inc.20160101T000000Z.to.20160101T000010Z.manifest",
+            "duplicity-new-signatures.20160101T000000Z.to.20160101T000010Z.sigtar.gz",
+        ]);
+        // chains are sorted by end time
+        let plan = CleanupPlan::new(&coll);
+        assert_eq!(
+            extraneous(&plan),
+            vec![
+                (
+                    "duplicity-full.20160108T223209Z.manifest.part",
+                    Reason::Incomplete
+                ),
+                (
+                    "duplicity-full.20160108T223209Z.vol1.difftar.gz",
+                    Reason::Incomplete
+                ),
+                (
+                    "duplicity-inc.20160108T223144Z.to.20160108T223159Z.vol1.difftar.gz",
+                    Reason::Incomplete
+                ),
+                (
+                    "duplicity-inc.20160108T223159Z.to.20160108T223217Z.manifest",
+                    Reason::Orphaned
+                ),
+                (
+                    "duplicity-inc.20160108T223159Z.to.20160108T223217Z.vol1.difftar.gz",
+                    Reason::Orphaned
+                ),
+                (
+                    "duplicity-inc.20160101T000000Z.to.20160101T000010Z.manifest",
+                    Reason::Orphaned
+                ),
+                (
+                    "duplicity-full-signatures.20160108T223209Z.sigtar.part",
+                    Reason::Incomplete
+                ),
+                (
+                    "duplicity-new-signatures.20160101T000000Z.to.20160101T000010Z.sigtar.gz",
+                    Reason::Orphaned
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt::{self, Display, Formatter};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A problem found in a backup file, while building the collections.
+///
+/// Files that cannot be used are not silently dropped: a diagnostic explains why a file has been
+/// ignored, or why a set cannot be restored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Diagnostic {
+    file_name: String,
+    reason: Reason,
+    severity: Severity,
+}
+
+/// The reason of a diagnostic.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Reason {
+    /// The file name is not valid UTF-8, so it has been ignored.
+    NonUtf8Name,
+    /// The file name does not look like a backup file, so it has been ignored.
+    UnknownName,
+    /// The file name looks like a backup file, but its times or volume number are malformed, so
+    /// it has been ignored.
+    MalformedName,
+    /// Another file has been found for the same volume of a set, and this one has been ignored.
+    DuplicateVolume(usize),
+    /// Another manifest has been found for the same set, and this one has been ignored.
+    DuplicateManifest,
+    /// The set containing the file has no manifest.
+    MissingManifest,
+    /// The set containing the file misses the given volume.
+    MissingVolume(usize),
+    /// The incremental set containing the file has been replaced by a longer one, starting at the
+    /// same time.
+    ReplacedSet,
+    /// The incremental set containing the file does not belong to any backup chain.
+    OrphanedSet,
+    /// The incremental signature does not belong to any signature chain.
+    OrphanedSignature,
+    /// The set containing the file has no corresponding signature, so its files cannot be
+    /// listed.
+    MissingSignature,
+    /// The signature does not correspond to any backup set.
+    UnpairedSignature,
+}
+
+/// How serious a diagnostic is.
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Severity {
+    /// A file not related to the backup, for example an unrelated file in the same directory.
+    Info,
+    /// A backup file that has been ignored.
+    Warning,
+    /// A backup set that cannot be restored.
+    Error,
+}
+
+impl Diagnostic {
+    pub(super) fn new<S: Into<String>>(file_name: S, reason: Reason) -> Self {
+        Diagnostic {
+            file_name: file_name.into(),
+            reason,
+            severity: reason.severity(),
+        }
+    }
+
+    /// Returns the name of the file.
+    ///
+    /// Non UTF-8 names are converted lossily.
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// Returns the reason of the diagnostic.
+    pub fn reason(&self) -> Reason {
+        self.reason
+    }
+
+    /// Returns how serious the diagnostic is.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.file_name, self.reason)
+    }
+}
+
+impl Reason {
+    /// Returns the severity of diagnostics with this reason.
+    pub fn severity(self) -> Severity {
+        match self {
+            Reason::UnknownName => Severity::Info,
+            Reason::NonUtf8Name
+            | Reason::MalformedName
+            | Reason::DuplicateVolume(_)
+            | Reason::DuplicateManifest
+            | Reason::ReplacedSet
+            | Reason::OrphanedSignature
+            | Reason::MissingSignature
+            | Reason::UnpairedSignature => Severity::Warning,
+            Reason::MissingManifest | Reason::MissingVolume(_) | Reason::OrphanedSet => {
+                Severity::Error
+            }
+        }
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Reason::NonUtf8Name => write!(f, "file name is not valid UTF-8"),
+            Reason::UnknownName => write!(f, "not a backup file"),
+            Reason::MalformedName => write!(f, "malformed time or volume number"),
+            Reason::DuplicateVolume(n) => write!(f, "duplicate file for volume {}", n),
+            Reason::DuplicateManifest => write!(f, "duplicate manifest"),
+            Reason::MissingManifest => write!(f, "the backup set has no manifest"),
+            Reason::MissingVolume(n) => write!(f, "the backup set misses volume {}", n),
+            Reason::ReplacedSet => write!(f, "replaced by a longer incremental set"),
+            Reason::OrphanedSet => write!(f, "the backup set does not belong to any chain"),
+            Reason::OrphanedSignature => write!(f, "the signature does not belong to any chain"),
+            Reason::MissingSignature => write!(f, "the backup set has no signature"),
+            Reason::UnpairedSignature => write!(f, "the signature has no backup set"),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let s = match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}", s)
+    }
+}
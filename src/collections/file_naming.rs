@@ -1,11 +1,13 @@
-use regex::Regex;
+use std::str;
+
 use time::Timespec;
 
-use crate::timefmt::parse_time_str;
+use crate::timefmt::{parse_short_time_str, parse_time_str};
 
 pub struct FileNameInfo<'a> {
     pub file_name: &'a str,
     pub info: Info,
+    pub size: Option<u64>,
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -47,13 +49,120 @@ pub enum Type {
     },
 }
 
+/// The prefixes of the backup file names.
+///
+/// Duplicity allows to prepend a prefix to all the file names of a backup, with the
+/// `--file-prefix` option, and additional prefixes specific for volumes, manifests and
+/// signatures, with `--file-prefix-archive`, `--file-prefix-manifest` and
+/// `--file-prefix-signature`. The specific prefixes follow the global one, so for example a
+/// manifest is named `<prefix><manifest prefix>duplicity-full.<time>.manifest`.
+///
+/// Prefixes are matched case insensitively, like the rest of the file names.
+///
+/// # Examples
+/// ```
+/// use ruplicity::collections::{Collections, FileNaming};
+///
+/// let naming = FileNaming::new().prefix("hostA_").signature_prefix("sig_");
+/// let names = vec!["hostA_duplicity-full.20150617T182545Z.manifest",
+///                  "hostA_duplicity-full.20150617T182545Z.vol1.difftar.gz",
+///                  "hostA_sig_duplicity-full-signatures.20150617T182545Z.sigtar.gz",
+///                  "hostB_duplicity-full.20150617T182545Z.manifest"];
+/// let collections = Collections::from_filenames_with(&naming, &names);
+/// assert_eq!(collections.backup_chains().count(), 1);
+/// assert_eq!(collections.signature_chains().count(), 1);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FileNaming {
+    prefix: String,
+    archive_prefix: String,
+    manifest_prefix: String,
+    signature_prefix: String,
+}
+
+#[derive(Clone, Debug)]
 pub struct FileNameParser {
-    full_vol_re: Regex,
-    full_manifest_re: Regex,
-    inc_vol_re: Regex,
-    inc_manifest_re: Regex,
-    full_sig_re: Regex,
-    new_sig_re: Regex,
+    naming: FileNaming,
+}
+
+// why a file name could not be parsed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NameError {
+    // the name does not look like a backup file
+    Unknown,
+    // the name looks like a backup file, but its times or volume number are malformed
+    Malformed,
+}
+
+// the fields of a file name, split but not parsed yet; `short` tells if the name is in the
+// format used with `--short-filenames`, where times and volume numbers are in base 36
+struct Fields<'a> {
+    short: bool,
+    kind: Kind,
+    times: (&'a str, &'a str),
+    volume: &'a str,
+    partial: bool,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Kind {
+    Full,
+    FullManifest,
+    Inc,
+    IncManifest,
+    FullSig,
+    NewSig,
+}
+
+impl FileNaming {
+    /// Creates a naming configuration without prefixes, which is the duplicity default.
+    pub fn new() -> Self {
+        FileNaming::default()
+    }
+
+    /// Sets the prefix of all the file names, as given by `--file-prefix`.
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the prefix of the volume file names, as given by `--file-prefix-archive`.
+    pub fn archive_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.archive_prefix = prefix.into();
+        self
+    }
+
+    /// Sets the prefix of the manifest file names, as given by `--file-prefix-manifest`.
+    pub fn manifest_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.manifest_prefix = prefix.into();
+        self
+    }
+
+    /// Sets the prefix of the signature file names, as given by `--file-prefix-signature`.
+    pub fn signature_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.signature_prefix = prefix.into();
+        self
+    }
+
+    /// Returns the prefix of all the file names.
+    pub fn file_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns the prefix of the volume file names, following the global one.
+    pub fn file_prefix_archive(&self) -> &str {
+        &self.archive_prefix
+    }
+
+    /// Returns the prefix of the manifest file names, following the global one.
+    pub fn file_prefix_manifest(&self) -> &str {
+        &self.manifest_prefix
+    }
+
+    /// Returns the prefix of the signature file names, following the global one.
+    pub fn file_prefix_signature(&self) -> &str {
+        &self.signature_prefix
+    }
 }
 
 impl<'a> FileNameInfo<'a> {
@@ -61,15 +170,32 @@ impl<'a> FileNameInfo<'a> {
         FileNameInfo {
             file_name: &name,
             info: info,
+            size: None,
         }
     }
 
+    pub fn with_size(mut self, size: Option<u64>) -> Self {
+        self.size = size;
+        self
+    }
+
     pub fn start_time(&self) -> Timespec {
         self.info.tp.time_range().0
     }
 }
 
 impl Type {
+    // partial manifests and signatures are the ones written by a backup still in progress
+    pub fn is_partial(&self) -> bool {
+        match *self {
+            Type::FullManifest { partial, .. }
+            | Type::IncManifest { partial, .. }
+            | Type::FullSig { partial, .. }
+            | Type::NewSig { partial, .. } => partial,
+            Type::Full { .. } | Type::Inc { .. } => false,
+        }
+    }
+
     pub fn time_range(&self) -> (Timespec, Timespec) {
         match *self {
             Type::Full { time, .. }
@@ -96,102 +222,217 @@ impl Type {
 
 impl FileNameParser {
     pub fn new() -> Self {
+        Self::with_naming(&FileNaming::default())
+    }
+
+    pub fn with_naming(naming: &FileNaming) -> Self {
         FileNameParser {
-            full_vol_re: Regex::new(r"^duplicity-full\.(?P<time>.*?)\.vol(?P<num>[0-9]+)\.difftar(?P<partial>(\.part))?($|\.)").unwrap(),
-            full_manifest_re: Regex::new(r"^duplicity-full\.(?P<time>.*?)\.manifest(?P<partial>(\.part))?($|\.)").unwrap(),
-            inc_vol_re: Regex::new(r"^duplicity-inc\.(?P<start_time>.*?)\.to\.(?P<end_time>.*?)\.vol(?P<num>[0-9]+)\.difftar($|\.)").unwrap(),
-            inc_manifest_re: Regex::new(r"^duplicity-inc\.(?P<start_time>.*?)\.to\.(?P<end_time>.*?)\.manifest(?P<partial>(\.part))?(\.|$)").unwrap(),
-            full_sig_re: Regex::new(r"^duplicity-full-signatures\.(?P<time>.*?)\.sigtar(?P<partial>(\.part))?(\.|$)").unwrap(),
-            new_sig_re: Regex::new(r"^duplicity-new-signatures\.(?P<start_time>.*?)\.to\.(?P<end_time>.*?)\.sigtar(?P<partial>(\.part))?(\.|$)").unwrap(),
+            naming: naming.clone(),
         }
     }
 
     pub fn parse(&self, filename: &str) -> Option<Info> {
-        let lower_fname = filename.to_ascii_lowercase();
-        let opt_type = self
-            .check_full(&lower_fname)
-            .or(self.check_inc(&lower_fname))
-            .or(self.check_sig(&lower_fname));
-        opt_type.map(|t| Info {
-            tp: t,
-            compressed: is_compressed(lower_fname.as_ref()),
-            encrypted: is_encrypted(lower_fname.as_ref()),
-        })
+        self.parse_checked(filename).ok()
     }
 
-    fn check_full(&self, filename: &str) -> Option<Type> {
-        if let Some(captures) = self.full_vol_re.captures(filename) {
-            let time = parse_time_str(captures.name("time").unwrap().as_str())?;
-            let vol_num = get_vol_num(captures.name("num").unwrap().as_str())?;
-            Some(Type::Full {
-                time: time,
-                volume_number: vol_num,
-            })
-        } else if let Some(captures) = self.full_manifest_re.captures(filename) {
-            let time = parse_time_str(captures.name("time").unwrap().as_str())?;
-            Some(Type::FullManifest {
-                time: time,
-                partial: captures.name("partial").is_some(),
-            })
+    // parses the file name in a single pass, telling apart the names that do not look like
+    // backup files from the ones with malformed times or volume numbers
+    pub fn parse_checked(&self, filename: &str) -> Result<Info, NameError> {
+        let name = strip_prefix(filename, &self.naming.prefix).ok_or(NameError::Unknown)?;
+        let specific = [
+            &self.naming.archive_prefix,
+            &self.naming.manifest_prefix,
+            &self.naming.signature_prefix,
+        ];
+        for (i, prefix) in specific.iter().enumerate() {
+            // the specific prefixes are usually the same, so try each of them only once
+            if specific[..i].contains(prefix) {
+                continue;
+            }
+            let fields = match strip_prefix(name, prefix).and_then(Fields::split) {
+                Some(fields) => fields,
+                None => continue,
+            };
+            if *specific[fields.kind.prefix_index()] != **prefix {
+                continue;
+            }
+            let tp = fields.parse().ok_or(NameError::Malformed)?;
+            return Ok(Info {
+                tp,
+                compressed: is_compressed(filename),
+                encrypted: is_encrypted(filename),
+            });
+        }
+        Err(NameError::Unknown)
+    }
+}
+
+impl<'a> Fields<'a> {
+    // splits a file name without prefixes, if it looks like a backup file
+    fn split(name: &'a str) -> Option<Self> {
+        let mut tokens = [""; 8];
+        for (slot, token) in tokens.iter_mut().zip(name.split('.')) {
+            *slot = token;
+        }
+        let is = |i: usize, keyword: &str| tokens[i].eq_ignore_ascii_case(keyword);
+        // the format, whether the name has a single time and whether it is a signature
+        let (short, full, sig) = if is(0, "duplicity-full") {
+            (false, true, false)
+        } else if is(0, "duplicity-inc") {
+            (false, false, false)
+        } else if is(0, "duplicity-full-signatures") {
+            (false, true, true)
+        } else if is(0, "duplicity-new-signatures") {
+            (false, false, true)
+        } else if is(0, "df") {
+            (true, true, false)
+        } else if is(0, "di") {
+            (true, false, false)
+        } else if is(0, "dfs") {
+            (true, true, true)
+        } else if is(0, "dns") {
+            (true, false, true)
         } else {
-            None
+            return None;
+        };
+        // the start and end times, and the position of the token following them
+        let (times, next) = if full {
+            ((tokens[1], tokens[1]), 2)
+        } else if short {
+            ((tokens[1], tokens[2]), 3)
+        } else if is(2, "to") {
+            ((tokens[1], tokens[3]), 4)
+        } else {
+            return None;
+        };
+        let (volume_keyword, manifest_keyword, sig_keyword, partial_keyword) = if short {
+            ("dt", "m", "st", "p")
+        } else {
+            ("difftar", "manifest", "sigtar", "part")
+        };
+        let mut fields = Fields {
+            short,
+            kind: Kind::Full,
+            times,
+            volume: "",
+            partial: is(next + 1, partial_keyword),
+        };
+        if sig {
+            if !is(next, sig_keyword) {
+                return None;
+            }
+            fields.kind = if full { Kind::FullSig } else { Kind::NewSig };
+        } else if is(next + 1, volume_keyword) {
+            fields.volume = if short {
+                tokens[next]
+            } else {
+                strip_prefix(tokens[next], "vol")?
+            };
+            fields.kind = if full { Kind::Full } else { Kind::Inc };
+        } else if is(next, manifest_keyword) {
+            fields.kind = if full {
+                Kind::FullManifest
+            } else {
+                Kind::IncManifest
+            };
+        } else {
+            return None;
         }
+        Some(fields)
     }
 
-    fn check_inc(&self, filename: &str) -> Option<Type> {
-        if let Some(captures) = self.inc_vol_re.captures(filename) {
-            let start_time = parse_time_str(captures.name("start_time").unwrap().as_str())?;
-            let end_time = parse_time_str(captures.name("end_time").unwrap().as_str())?;
-            let vol_num = get_vol_num(captures.name("num").unwrap().as_str())?;
-            Some(Type::Inc {
-                start_time: start_time,
-                end_time: end_time,
-                volume_number: vol_num,
-            })
-        } else if let Some(captures) = self.inc_manifest_re.captures(filename) {
-            let start_time = parse_time_str(captures.name("start_time").unwrap().as_str())?;
-            let end_time = parse_time_str(captures.name("end_time").unwrap().as_str())?;
-            Some(Type::IncManifest {
-                start_time: start_time,
-                end_time: end_time,
-                partial: captures.name("partial").is_some(),
-            })
+    // parses the times and the volume number
+    fn parse(&self) -> Option<Type> {
+        let start_time = self.time(self.times.0)?;
+        let end_time = self.time(self.times.1)?;
+        let tp = match self.kind {
+            Kind::Full => Type::Full {
+                time: start_time,
+                volume_number: self.volume_number()?,
+            },
+            Kind::FullManifest => Type::FullManifest {
+                time: start_time,
+                partial: self.partial,
+            },
+            Kind::Inc => Type::Inc {
+                start_time,
+                end_time,
+                volume_number: self.volume_number()?,
+            },
+            Kind::IncManifest => Type::IncManifest {
+                start_time,
+                end_time,
+                partial: self.partial,
+            },
+            Kind::FullSig => Type::FullSig {
+                time: start_time,
+                partial: self.partial,
+            },
+            Kind::NewSig => Type::NewSig {
+                start_time,
+                end_time,
+                partial: self.partial,
+            },
+        };
+        Some(tp)
+    }
+
+    fn time(&self, s: &str) -> Option<Timespec> {
+        if self.short {
+            parse_short_time_str(s)
         } else {
-            None
+            // times are parsed in lowercase, but without allocating
+            let mut buf = [0u8; 32];
+            let buf = buf.get_mut(..s.len())?;
+            buf.copy_from_slice(s.as_bytes());
+            buf.make_ascii_lowercase();
+            parse_time_str(str::from_utf8(buf).ok()?)
         }
     }
 
-    fn check_sig(&self, filename: &str) -> Option<Type> {
-        if let Some(captures) = self.full_sig_re.captures(filename) {
-            let time = parse_time_str(captures.name("time").unwrap().as_str())?;
-            Some(Type::FullSig {
-                time: time,
-                partial: captures.name("partial").is_some(),
-            })
-        } else if let Some(captures) = self.new_sig_re.captures(filename) {
-            let start_time = parse_time_str(captures.name("start_time").unwrap().as_str())?;
-            let end_time = parse_time_str(captures.name("end_time").unwrap().as_str())?;
-            Some(Type::NewSig {
-                start_time: start_time,
-                end_time: end_time,
-                partial: captures.name("partial").is_some(),
-            })
+    fn volume_number(&self) -> Option<usize> {
+        let radix = if self.short { 36 } else { 10 };
+        if self.volume.bytes().all(|b| (b as char).is_digit(radix)) {
+            usize::from_str_radix(self.volume, radix).ok()
         } else {
             None
         }
     }
 }
 
-fn get_vol_num(s: &str) -> Option<usize> {
-    s.parse::<usize>().ok()
+impl Kind {
+    // the index of the specific prefix of the kind: archive, manifest or signature
+    fn prefix_index(self) -> usize {
+        match self {
+            Kind::Full | Kind::Inc => 0,
+            Kind::FullManifest | Kind::IncManifest => 1,
+            Kind::FullSig | Kind::NewSig => 2,
+        }
+    }
+}
+
+// strips the given prefix from the name, ignoring case
+fn strip_prefix<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = name.get(..prefix.len())?;
+    if head.eq_ignore_ascii_case(prefix) {
+        Some(&name[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+fn extension(s: &str) -> &str {
+    s.rsplit('.').next().unwrap_or("")
 }
 
 fn is_encrypted(s: &str) -> bool {
-    s.ends_with(".gpg") || s.ends_with(".g")
+    let ext = extension(s);
+    ext.eq_ignore_ascii_case("gpg") || ext.eq_ignore_ascii_case("g")
 }
 
 fn is_compressed(s: &str) -> bool {
-    s.ends_with(".gz") || s.ends_with(".z")
+    let ext = extension(s);
+    ext.eq_ignore_ascii_case("gz") || ext.eq_ignore_ascii_case("z")
 }
 
 #[cfg(test)]
@@ -280,4 +521,185 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn name_errors() {
+        let parser = FileNameParser::new();
+        let check = |name| parser.parse_checked(name).err();
+        assert_eq!(
+            check("DUPLICITY-FULL.20150617T182545Z.VOL1.DIFFTAR.GZ"),
+            None
+        );
+        assert_eq!(check("README"), Some(NameError::Unknown));
+        assert_eq!(
+            check("duplicity-full.20150617T182545Z.index"),
+            Some(NameError::Unknown)
+        );
+        assert_eq!(
+            check("duplicity-inc.20150617T182545Z.20150617T182629Z.manifest"),
+            Some(NameError::Unknown)
+        );
+        assert_eq!(
+            check("duplicity-full.2015O617T182545Z.manifest"),
+            Some(NameError::Malformed)
+        );
+        assert_eq!(
+            check("duplicity-full.20150617T182545Z.volx.difftar.gz"),
+            Some(NameError::Malformed)
+        );
+        assert_eq!(
+            check("duplicity-new-signatures.20150617T182545Z.to.x.sigtar.gz"),
+            Some(NameError::Malformed)
+        );
+        assert_eq!(check("dns.9vy59c.9vy-5ac.st"), Some(NameError::Malformed));
+    }
+
+    #[test]
+    fn prefixes() {
+        let naming = FileNaming::new()
+            .prefix("Host.A_")
+            .archive_prefix("arch_")
+            .manifest_prefix("man_")
+            .signature_prefix("sig_");
+        let parser = FileNameParser::with_naming(&naming);
+        let time = parse_time_str("20150617t182545z").unwrap();
+        let parse = |name| parser.parse(name).map(|info| info.tp);
+        assert_eq!(
+            parse("host.a_arch_duplicity-full.20150617T182545Z.vol1.difftar.gz"),
+            Some(Type::Full {
+                time,
+                volume_number: 1,
+            })
+        );
+        assert_eq!(
+            parse("Host.A_man_duplicity-full.20150617T182545Z.manifest"),
+            Some(Type::FullManifest {
+                time,
+                partial: false,
+            })
+        );
+        assert_eq!(
+            parse("Host.A_sig_duplicity-full-signatures.20150617T182545Z.sigtar.gz"),
+            Some(Type::FullSig {
+                time,
+                partial: false,
+            })
+        );
+        // wrong or missing prefixes
+        assert_eq!(
+            parse("Host.A_duplicity-full.20150617T182545Z.manifest"),
+            None
+        );
+        assert_eq!(
+            parse("Host.A_arch_duplicity-full.20150617T182545Z.manifest"),
+            None
+        );
+        assert_eq!(
+            parse("HostxA_man_duplicity-full.20150617T182545Z.manifest"),
+            None
+        );
+        assert_eq!(parse("duplicity-full.20150617T182545Z.manifest"), None);
+        assert_eq!(
+            FileNameParser::new().parse("Host.A_man_duplicity-full.20150617T182545Z.manifest"),
+            None
+        );
+    }
+
+    #[test]
+    fn short_names() {
+        let parser = FileNameParser::new();
+        let start = parse_time_str("19881211t152000z").unwrap();
+        let end = Timespec::new(start.sec + 36, 0);
+        let info = |tp, compressed, encrypted| {
+            Some(Info {
+                tp,
+                compressed,
+                encrypted,
+            })
+        };
+        assert_eq!(
+            parser.parse("df.9vy59c.1a.dt.g"),
+            info(
+                Type::Full {
+                    time: start,
+                    volume_number: 46,
+                },
+                false,
+                true
+            )
+        );
+        assert_eq!(
+            parser.parse("DF.9VY59C.m"),
+            info(
+                Type::FullManifest {
+                    time: start,
+                    partial: false,
+                },
+                false,
+                false
+            )
+        );
+        assert_eq!(
+            parser.parse("di.9vy59c.9vy5ac.2.dt.z"),
+            info(
+                Type::Inc {
+                    start_time: start,
+                    end_time: end,
+                    volume_number: 2,
+                },
+                true,
+                false
+            )
+        );
+        assert_eq!(
+            parser.parse("di.9vy59c.9vy5ac.m.p"),
+            info(
+                Type::IncManifest {
+                    start_time: start,
+                    end_time: end,
+                    partial: true,
+                },
+                false,
+                false
+            )
+        );
+        assert_eq!(
+            parser.parse("dfs.9vy59c.st.g"),
+            info(
+                Type::FullSig {
+                    time: start,
+                    partial: false,
+                },
+                false,
+                true
+            )
+        );
+        assert_eq!(
+            parser.parse("dns.9vy59c.9vy5ac.st.p.z"),
+            info(
+                Type::NewSig {
+                    start_time: start,
+                    end_time: end,
+                    partial: true,
+                },
+                true,
+                false
+            )
+        );
+        // a volume number looking like a manifest
+        assert_eq!(
+            parser.parse("df.9vy59c.m.dt").map(|info| info.tp),
+            Some(Type::Full {
+                time: start,
+                volume_number: 22,
+            })
+        );
+        // invalid base 36 strings
+        assert_eq!(parser.parse("df.9vy-59c.m"), None);
+        assert_eq!(parser.parse("dfx.9vy59c.st"), None);
+        // prefixes apply to short names too
+        let parser = FileNameParser::with_naming(&FileNaming::new().prefix("a_"));
+        assert!(parser.parse("a_df.9vy59c.m").is_some());
+        assert!(parser.parse("df.9vy59c.m").is_none());
+    }
 }
@@ -3,15 +3,24 @@
 //! This sub-module provides information about a backup, by looking at the files present in a
 //! backup directory.
 
-mod file_naming;
+mod diagnostics;
+pub(crate) mod file_naming;
 
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Display, Error, Formatter};
 use std::path::Path;
 use std::slice;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use time::Timespec;
 
+pub use self::diagnostics::{Diagnostic, Reason, Severity};
 use self::file_naming as fnm;
-use self::file_naming::{FileNameInfo, FileNameParser};
+pub use self::file_naming::FileNaming;
+use self::file_naming::{FileNameInfo, FileNameParser, NameError};
+use crate::backend::FileInfo;
 use crate::timefmt::TimeDisplay;
 
 /// General information about a backup.
@@ -20,9 +29,16 @@ use crate::timefmt::TimeDisplay;
 /// backup archive is opened in this process. Thanks to that, performances are great; however no
 /// validation is performed on backup files.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Collections {
     backup_chains: Vec<BackupChain>,
+    #[cfg_attr(feature = "serde", serde(rename = "signature_chains"))]
     sig_chains: Vec<SignatureChain>,
+    orphaned_sets: Vec<BackupSet>,
+    #[cfg_attr(feature = "serde", serde(rename = "orphaned_signatures"))]
+    orphaned_sigs: Vec<SignatureFile>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    diagnostics: Vec<Diagnostic>,
 }
 
 /// Contains information about a backup chain.
@@ -30,10 +46,15 @@ pub struct Collections {
 /// A backup chain is composed by one full and all the incremental backup snapshots before the
 /// next full one.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BackupChain {
+    #[cfg_attr(feature = "serde", serde(rename = "full_set"))]
     fullset: BackupSet,
+    #[cfg_attr(feature = "serde", serde(rename = "inc_sets"))]
     incsets: Vec<BackupSet>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::timefmt::serde_secs"))]
     start_time: Timespec,
+    #[cfg_attr(feature = "serde", serde(with = "crate::timefmt::serde_secs"))]
     end_time: Timespec,
 }
 
@@ -41,8 +62,11 @@ pub struct BackupChain {
 ///
 /// See the docs for [`BackupChain`](struct.BackupChain.html).
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SignatureChain {
+    #[cfg_attr(feature = "serde", serde(rename = "full_signature"))]
     fullsig: SignatureFile,
+    #[cfg_attr(feature = "serde", serde(rename = "inc_signatures"))]
     incsigs: Vec<SignatureFile>,
 }
 
@@ -50,26 +74,36 @@ pub struct SignatureChain {
 ///
 /// This struct contains paths for the manifest and volumes, and the backup format.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BackupSet {
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
     tp: Type,
     compressed: bool,
     encrypted: bool,
     partial: bool,
     manifest_path: String,
+    manifest_size: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(rename = "volume_paths"))]
     volumes_paths: Vec<Option<String>>,
+    #[cfg_attr(feature = "serde", serde(rename = "volume_sizes"))]
+    volumes_sizes: Vec<Option<u64>>,
 }
 
 /// Information about a signature file.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SignatureFile {
     /// The file name of the signature file.
     pub file_name: String,
     /// The snapshot time.
+    #[cfg_attr(feature = "serde", serde(with = "crate::timefmt::serde_secs"))]
     pub time: Timespec,
     /// Determine if the signature is gzipped or not.
     pub compressed: bool,
     /// Determine if the signature is encrypted or not.
     pub encrypted: bool,
+    /// Determine if the signature has been only partially written.
+    pub partial: bool,
 }
 
 /// Iterator over some kind of chain.
@@ -81,13 +115,24 @@ pub type BackupSetIter<'a> = slice::Iter<'a, BackupSet>;
 /// Iterator over `SignatureFile`s.
 pub type SignatureFileIter<'a> = slice::Iter<'a, SignatureFile>;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// Iterator over `Diagnostic`s.
+pub type DiagnosticIter<'a> = slice::Iter<'a, Diagnostic>;
+
+#[derive(Copy, Clone, Eq, Hash, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "kind", rename_all = "lowercase")
+)]
 enum Type {
     Full {
+        #[cfg_attr(feature = "serde", serde(with = "crate::timefmt::serde_secs"))]
         time: Timespec,
     },
     Inc {
+        #[cfg_attr(feature = "serde", serde(with = "crate::timefmt::serde_secs"))]
         start_time: Timespec,
+        #[cfg_attr(feature = "serde", serde(with = "crate::timefmt::serde_secs"))]
         end_time: Timespec,
     },
 }
@@ -147,11 +192,50 @@ impl BackupSet {
             .and_then(|v| v.as_ref().map(AsRef::as_ref))
     }
 
+    /// Returns the names of all the files in the set.
+    ///
+    /// The manifest comes first, if present, followed by the volumes in order.
+    pub fn file_names(&self) -> impl Iterator<Item = &str> {
+        Some(self.manifest_path())
+            .filter(|m| !m.is_empty())
+            .into_iter()
+            .chain(self.volumes_paths.iter().filter_map(|v| v.as_deref()))
+    }
+
     /// Returns the number of volumes in the set.
     pub fn num_volumes(&self) -> usize {
         self.volumes_paths.len()
     }
 
+    /// Returns the size in bytes of the manifest file, if known.
+    pub fn manifest_size(&self) -> Option<u64> {
+        self.manifest_size
+    }
+
+    /// Returns the size in bytes of the given volume, if known.
+    pub fn volume_size(&self, volume_num: usize) -> Option<u64> {
+        self.volumes_sizes.get(volume_num).cloned().and_then(|s| s)
+    }
+
+    /// Returns the total size in bytes of the files in the set.
+    ///
+    /// The size is known only if the backend provided the size of all the files in the set
+    /// (manifest and volumes).
+    pub fn total_size(&self) -> Option<u64> {
+        let manifest_size = if self.is_complete() {
+            self.manifest_size?
+        } else {
+            0
+        };
+        let mut total = manifest_size;
+        for (path, size) in self.volumes_paths.iter().zip(&self.volumes_sizes) {
+            if path.is_some() {
+                total += (*size)?;
+            }
+        }
+        Some(total)
+    }
+
     /// Returns whether the set is a full backup.
     pub fn is_full(&self) -> bool {
         matches!(self.tp, Type::Full{..})
@@ -164,63 +248,31 @@ impl BackupSet {
 
     /// Returns whether the given file belongs to the same backup set, by looking at timestamps.
     pub fn is_same_set(&self, pr: &fnm::Info) -> bool {
-        match self.tp {
-            Type::Full { time: my_time } => match pr.tp {
-                fnm::Type::Full { time, .. }
-                | fnm::Type::FullManifest { time, .. }
-                | fnm::Type::FullSig { time, .. } => my_time == time,
-                _ => false,
-            },
-            Type::Inc {
-                start_time: my_start,
-                end_time: my_end,
-            } => match pr.tp {
-                fnm::Type::Inc {
-                    start_time,
-                    end_time,
-                    ..
-                }
-                | fnm::Type::IncManifest {
-                    start_time,
-                    end_time,
-                    ..
-                }
-                | fnm::Type::NewSig {
-                    start_time,
-                    end_time,
-                    ..
-                } => my_start == start_time && my_end == end_time,
-                _ => false,
-            },
+        self.tp == Type::from(&pr.tp)
+    }
+
+    // returns the file that would be replaced by adding the given one to the set, if any
+    fn replaced_file(&self, file_info: &FileNameInfo) -> Option<(String, Reason)> {
+        if !self.is_same_set(&file_info.info) {
+            return None;
+        }
+        match file_info.info.tp {
+            fnm::Type::Full { volume_number, .. } | fnm::Type::Inc { volume_number, .. } => self
+                .volume_path(volume_number)
+                .map(|path| (path.to_owned(), Reason::DuplicateVolume(volume_number))),
+            fnm::Type::FullManifest { .. } | fnm::Type::IncManifest { .. }
+                if self.is_complete() =>
+            {
+                Some((self.manifest_path.clone(), Reason::DuplicateManifest))
+            }
+            _ => None,
         }
     }
 
     /// Creates a new backup set, starting from file name information.
     fn new(fname: &FileNameInfo) -> Self {
         // set type
-        let tp = match fname.info.tp {
-            fnm::Type::Full { time, .. }
-            | fnm::Type::FullManifest { time, .. }
-            | fnm::Type::FullSig { time, .. } => Type::Full { time: time },
-            fnm::Type::Inc {
-                start_time,
-                end_time,
-                ..
-            }
-            | fnm::Type::IncManifest {
-                start_time,
-                end_time,
-                ..
-            }
-            | fnm::Type::NewSig {
-                start_time,
-                end_time,
-                ..
-            } => Type::Inc {
-                start_time: start_time,
-                end_time: end_time,
-            },
-        };
+        let tp = Type::from(&fname.info.tp);
         // set partial
         let partial = {
             match fname.info.tp {
@@ -238,7 +290,9 @@ impl BackupSet {
             compressed: fname.info.compressed,
             encrypted: fname.info.encrypted,
             manifest_path: String::new(),
+            manifest_size: None,
             volumes_paths: Vec::new(),
+            volumes_sizes: Vec::new(),
         };
         result.add_filename(fname);
         result
@@ -265,11 +319,19 @@ impl BackupSet {
                         for _ in self.volumes_paths.len()..volume_number + 1 {
                             self.volumes_paths.push(None);
                         }
+                        self.volumes_sizes.resize(volume_number + 1, None);
                     }
                     self.volumes_paths[volume_number] = Some(fname.to_owned());
+                    self.volumes_sizes[volume_number] = file_info.size;
+                    // the manifest is never compressed: use the volumes flags
+                    self.compressed = pr.compressed;
+                    self.encrypted = pr.encrypted;
                 }
-                fnm::Type::FullManifest { .. } | fnm::Type::IncManifest { .. } => {
+                fnm::Type::FullManifest { partial, .. }
+                | fnm::Type::IncManifest { partial, .. } => {
                     self.manifest_path = fname.to_owned();
+                    self.manifest_size = file_info.size;
+                    self.partial = partial;
                 }
                 _ => (),
             }
@@ -293,13 +355,14 @@ impl Display for BackupSet {
         };
         write!(
             f,
-            "{:<20} {:<13} {:>12}",
+            "{:<20} {:<13} {:>12} {:>12}",
             tp,
             // FIXME: Workaround for rust <= 1.4
             // Alignment is ignored by custom formatters
             // see: https://github.com/rust-lang-deprecated/time/issues/98#issuecomment-103010106
             format!("{}", self.end_time().into_local_display()),
-            self.num_volumes()
+            self.num_volumes(),
+            SizeDisplay(self.total_size())
         )
     }
 }
@@ -373,6 +436,17 @@ impl BackupChain {
         self.start_time
     }
 
+    /// Returns the total size in bytes of the backup sets in the chain.
+    ///
+    /// The size is known only if the sizes of all the sets in the chain are known.
+    pub fn total_size(&self) -> Option<u64> {
+        let mut total = self.fullset.total_size()?;
+        for inc in &self.incsets {
+            total += inc.total_size()?;
+        }
+        Some(total)
+    }
+
     /// Returns the time of the last backup set in the chain.
     pub fn end_time(&self) -> Timespec {
         self.end_time
@@ -392,16 +466,18 @@ impl Display for BackupChain {
             "Chain start time: {}\n\
                     Chain end time: {}\n\
                     Number of contained backup sets: {}\n\
-                    Total number of contained volumes: {}\n",
+                    Total number of contained volumes: {}\n\
+                    Total size of contained sets: {}\n",
             self.start_time.into_local_display(),
             self.end_time.into_local_display(),
             self.incsets.len() + 1,
-            num_vol
+            num_vol,
+            SizeDisplay(self.total_size())
         )?;
         write!(
             f,
-            "{:<20} {:<13} {:>12}",
-            "Type of backup set:", "Time:", "Num volumes:\n"
+            "{:<20} {:<13} {:>12} {:>12}",
+            "Type of backup set:", "Time:", "Num volumes:", "Size:\n"
         )?;
         write!(f, "{}\n", self.fullset)?;
         for inc in &self.incsets {
@@ -414,10 +490,12 @@ impl Display for BackupChain {
 impl SignatureFile {
     /// Construct a signature file from a file name and infos.
     pub fn from_file_and_info(fname: &str, pr: &fnm::Info) -> Self {
-        let time = {
+        let (time, partial) = {
             match pr.tp {
-                fnm::Type::FullSig { time, .. } => time,
-                fnm::Type::NewSig { end_time, .. } => end_time,
+                fnm::Type::FullSig { time, partial } => (time, partial),
+                fnm::Type::NewSig {
+                    end_time, partial, ..
+                } => (end_time, partial),
                 _ => panic!("unexpected file given for signature"),
             }
         };
@@ -426,6 +504,7 @@ impl SignatureFile {
             time: time,
             compressed: pr.compressed,
             encrypted: pr.encrypted,
+            partial,
         }
     }
 
@@ -470,6 +549,22 @@ impl SignatureChain {
         self.incsigs.iter()
     }
 
+    /// Returns the index of the signature of the backup set ending at the given time.
+    ///
+    /// The full signature has index zero, and the incremental ones follow in order. The
+    /// signatures up to the returned index describe the set content.
+    pub fn signature_index(&self, time: Timespec) -> Option<usize> {
+        if self.fullsig.time == time {
+            return Some(0);
+        }
+        // incremental signatures are sorted by time
+        let index = self.incsigs.partition_point(|sig| sig.time < time);
+        self.incsigs
+            .get(index)
+            .filter(|sig| sig.time == time)
+            .map(|_| index + 1)
+    }
+
     /// Returns the time of the first signature in the chain.
     pub fn start_time(&self) -> Timespec {
         self.fullsig.time
@@ -505,6 +600,9 @@ impl Collections {
         Collections {
             backup_chains: Vec::new(),
             sig_chains: Vec::new(),
+            orphaned_sets: Vec::new(),
+            orphaned_sigs: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
@@ -528,34 +626,220 @@ impl Collections {
     /// assert_eq!(collections.signature_chains().count(), 1);
     /// ```
     pub fn from_filenames<I>(filenames: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        Self::from_filenames_with(&FileNaming::default(), filenames)
+    }
+
+    /// Creates a collection from a list of file names, written with the given file prefixes.
+    ///
+    /// Files not matching the prefixes are ignored.
+    pub fn from_filenames_with<I>(naming: &FileNaming, filenames: I) -> Self
     where
         I: IntoIterator,
         I::Item: AsRef<Path>,
     {
         let fnames_vec: Vec<_> = filenames.into_iter().collect();
-        let infos = compute_filename_infos(&fnames_vec);
+        let mut diagnostics = Vec::new();
+        let infos = compute_filename_infos(
+            naming,
+            fnames_vec.iter().map(|f| (f.as_ref(), None)),
+            &mut diagnostics,
+        );
+        Self::from_filename_infos(&infos, diagnostics)
+    }
+
+    /// Creates a collection, starting from a list of file infos.
+    ///
+    /// Like `from_filenames`, but uses also the file sizes provided by the backend, if any.
+    ///
+    /// # Examples
+    /// ```
+    /// use ruplicity::backend::FileInfo;
+    /// use ruplicity::collections::Collections;
+    ///
+    /// let mut manifest = FileInfo::from_name("duplicity-full.20150617T182545Z.manifest");
+    /// manifest.size = Some(150);
+    /// let mut volume = FileInfo::from_name("duplicity-full.20150617T182545Z.vol1.difftar.gz");
+    /// volume.size = Some(1024);
+    /// let collections = Collections::from_file_infos(vec![manifest, volume]);
+    /// let chain = collections.backup_chains().next().unwrap();
+    /// assert_eq!(chain.total_size(), Some(1174));
+    /// ```
+    pub fn from_file_infos<I, N>(infos: I) -> Self
+    where
+        I: IntoIterator<Item = FileInfo<N>>,
+        N: AsRef<Path>,
+    {
+        Self::from_file_infos_with(&FileNaming::default(), infos)
+    }
+
+    /// Creates a collection from a list of file infos, written with the given file prefixes.
+    ///
+    /// Files not matching the prefixes are ignored.
+    pub fn from_file_infos_with<I, N>(naming: &FileNaming, infos: I) -> Self
+    where
+        I: IntoIterator<Item = FileInfo<N>>,
+        N: AsRef<Path>,
+    {
+        let infos_vec: Vec<_> = infos.into_iter().collect();
+        let mut diagnostics = Vec::new();
+        let infos = compute_filename_infos(
+            naming,
+            infos_vec.iter().map(|f| (f.name.as_ref(), f.size)),
+            &mut diagnostics,
+        );
+        Self::from_filename_infos(&infos, diagnostics)
+    }
+
+    /// Finds all the backups stored in the given list of files, one for each distinct file
+    /// prefix.
+    ///
+    /// The prefix of a file is the part of its name preceding a valid duplicity file name, in the
+    /// default or in the short format. Every backup is returned with the naming configuration
+    /// used to read it, sorted by prefix. Note that files
+    /// with specific prefixes for volumes, manifests or signatures are detected as different
+    /// backups, because those prefixes cannot be told apart from the global one.
+    ///
+    /// # Examples
+    /// ```
+    /// use ruplicity::backend::FileInfo;
+    /// use ruplicity::collections::Collections;
+    ///
+    /// let names = vec!["hostA_duplicity-full.20150617T182545Z.manifest",
+    ///                  "hostA_duplicity-full.20150617T182545Z.vol1.difftar.gz",
+    ///                  "hostB_duplicity-full.20150618T182545Z.manifest",
+    ///                  "hostB_duplicity-full.20150618T182545Z.vol1.difftar.gz",
+    ///                  "unrelated.txt"];
+    /// let backups = Collections::discover(names.into_iter().map(FileInfo::from_name));
+    /// let prefixes: Vec<_> = backups.iter().map(|(n, _)| n.file_prefix()).collect();
+    /// assert_eq!(prefixes, vec!["hostA_", "hostB_"]);
+    /// assert!(backups.iter().all(|(_, c)| c.backup_chains().count() == 1));
+    /// ```
+    pub fn discover<I, N>(infos: I) -> Vec<(FileNaming, Collections)>
+    where
+        I: IntoIterator<Item = FileInfo<N>>,
+        N: AsRef<Path>,
+    {
+        let parser = FileNameParser::new();
+        let mut by_prefix: BTreeMap<String, Vec<FileInfo<N>>> = BTreeMap::new();
+        for info in infos {
+            let name = info.name.as_ref().to_str();
+            let prefix = match name.and_then(|name| file_prefix(&parser, name)) {
+                Some(prefix) => prefix.to_owned(),
+                None => continue,
+            };
+            by_prefix.entry(prefix).or_default().push(info);
+        }
+        by_prefix
+            .into_iter()
+            .map(|(prefix, infos)| {
+                let naming = FileNaming::new().prefix(prefix);
+                let collections = Self::from_file_infos_with(&naming, infos);
+                (naming, collections)
+            })
+            .filter(|(_, c)| !c.is_empty())
+            .collect()
+    }
+
+    fn from_filename_infos(infos: &[FileNameInfo], mut diagnostics: Vec<Diagnostic>) -> Self {
+        let (backup_chains, orphaned_sets) = compute_backup_chains(infos, &mut diagnostics);
+        let (sig_chains, orphaned_sigs) = compute_signature_chains(infos, &mut diagnostics);
+        check_pairing(&backup_chains, &sig_chains, &mut diagnostics);
+        diagnostics.sort_by(|a, b| a.file_name().cmp(b.file_name()));
         Collections {
-            backup_chains: compute_backup_chains(&infos),
-            sig_chains: compute_signature_chains(&infos),
+            backup_chains,
+            sig_chains,
+            orphaned_sets,
+            orphaned_sigs,
+            diagnostics,
         }
     }
 
     /// Returns the backup chains.
     ///
-    /// Each backup chain should be coupled with a signature chain. They can be matched because
-    /// they are both in chronological order.
+    /// Each backup chain should be coupled with a signature chain, starting at the same time.
+    /// Use `signature_chain_for` to find it.
     pub fn backup_chains(&self) -> ChainIter<BackupChain> {
         self.backup_chains.iter()
     }
 
     /// Returns the signature chains.
     ///
-    /// Each signature chain should be coupled with a backup chain. They can be matched because
-    /// they are both in chronological order.
+    /// Each signature chain should be coupled with a backup chain, starting at the same time.
+    /// Missing or unpaired chains are reported in the diagnostics.
     pub fn signature_chains(&self) -> ChainIter<SignatureChain> {
         self.sig_chains.iter()
     }
 
+    /// Returns the signature chain of the given backup chain, if present.
+    ///
+    /// Chains are paired by the time of their full set. The signature of each set in the chain
+    /// can then be found by end time, with `SignatureChain::signature_index`.
+    ///
+    /// # Examples
+    /// ```
+    /// use ruplicity::collections::Collections;
+    ///
+    /// let names = vec!["duplicity-full.20150617T182545Z.manifest",
+    ///                  "duplicity-full.20150617T182545Z.vol1.difftar.gz",
+    ///                  "duplicity-full.20150618T182545Z.manifest",
+    ///                  "duplicity-full.20150618T182545Z.vol1.difftar.gz",
+    ///                  "duplicity-full-signatures.20150618T182545Z.sigtar.gz"];
+    /// let collections = Collections::from_filenames(&names);
+    /// let chains: Vec<_> = collections.backup_chains().collect();
+    /// assert!(collections.signature_chain_for(chains[0]).is_none());
+    /// assert!(collections.signature_chain_for(chains[1]).is_some());
+    /// ```
+    pub fn signature_chain_for(&self, chain: &BackupChain) -> Option<&SignatureChain> {
+        signature_chain_index(&self.sig_chains, chain.start_time()).map(|i| &self.sig_chains[i])
+    }
+
+    /// Returns the incremental backup sets not belonging to any backup chain.
+    ///
+    /// These sets cannot be restored, because the sets they depend on are missing.
+    pub fn orphaned_sets(&self) -> BackupSetIter<'_> {
+        self.orphaned_sets.iter()
+    }
+
+    /// Returns the incremental signatures not belonging to any signature chain.
+    pub fn orphaned_signatures(&self) -> SignatureFileIter<'_> {
+        self.orphaned_sigs.iter()
+    }
+
+    /// Returns the problems found in the backup files, sorted by file name.
+    ///
+    /// Files which cannot be used, like unknown or malformed names and duplicates, are reported
+    /// here, together with the sets that cannot be restored.
+    ///
+    /// # Examples
+    /// ```
+    /// use ruplicity::collections::{Collections, Reason, Severity};
+    ///
+    /// let names = vec!["duplicity-full.20150617T182545Z.manifest",
+    ///                  "duplicity-full.20150617T182545Z.vol1.difftar.gz",
+    ///                  "duplicity-full-signatures.20150617T182545Z.sigtar.gz",
+    ///                  "duplicity-full.2015O617T182545Z.vol2.difftar.gz"];
+    /// let collections = Collections::from_filenames(&names);
+    /// let diagnostic = collections.diagnostics().next().unwrap();
+    /// assert_eq!(diagnostic.file_name(), "duplicity-full.2015O617T182545Z.vol2.difftar.gz");
+    /// assert_eq!(diagnostic.reason(), Reason::MalformedName);
+    /// assert_eq!(diagnostic.severity(), Severity::Warning);
+    /// ```
+    pub fn diagnostics(&self) -> DiagnosticIter<'_> {
+        self.diagnostics.iter()
+    }
+
+    // whether no backup file has been found
+    fn is_empty(&self) -> bool {
+        self.backup_chains.is_empty()
+            && self.sig_chains.is_empty()
+            && self.orphaned_sets.is_empty()
+            && self.orphaned_sigs.is_empty()
+    }
+
     /// Returns the total number of snapshots.
     pub fn num_snapshots(&self) -> usize {
         let mut i = 0;
@@ -566,61 +850,159 @@ impl Collections {
     }
 }
 
-fn compute_filename_infos<'a, I, E>(filenames: I) -> Vec<FileNameInfo<'a>>
+fn compute_filename_infos<'a, I>(
+    naming: &FileNaming,
+    files: I,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<FileNameInfo<'a>>
 where
-    I: IntoIterator<Item = &'a E>,
-    E: AsRef<Path> + 'a,
+    I: IntoIterator<Item = (&'a Path, Option<u64>)>,
 {
-    let parser = FileNameParser::new();
-    filenames
-        .into_iter()
-        .filter_map(|path| path.as_ref().to_str())
-        .filter_map(|name| match parser.parse(name) {
-            Some(info) => Some(FileNameInfo::new(name, info)),
-            None => None,
+    let parser = FileNameParser::with_naming(naming);
+    let mut result = Vec::new();
+    for (path, size) in files {
+        let name = match path.to_str() {
+            Some(name) => name,
+            None => {
+                let name = path.to_string_lossy();
+                diagnostics.push(Diagnostic::new(name, Reason::NonUtf8Name));
+                continue;
+            }
+        };
+        match parser.parse_checked(name) {
+            Ok(info) => result.push(FileNameInfo::new(name, info).with_size(size)),
+            Err(NameError::Malformed) => {
+                diagnostics.push(Diagnostic::new(name, Reason::MalformedName))
+            }
+            Err(NameError::Unknown) => diagnostics.push(Diagnostic::new(name, Reason::UnknownName)),
+        }
+    }
+    result
+}
+
+// the part of a file name preceding a valid duplicity file name, in the default or short format
+fn file_prefix<'a>(parser: &FileNameParser, name: &'a str) -> Option<&'a str> {
+    name.char_indices()
+        .map(|(pos, _)| pos)
+        .filter(|&pos| {
+            let rest = &name.as_bytes()[pos..];
+            ["duplicity-", "df.", "di.", "dfs.", "dns."]
+                .iter()
+                .any(|marker| {
+                    rest.len() >= marker.len()
+                        && rest[..marker.len()].eq_ignore_ascii_case(marker.as_bytes())
+                })
         })
-        .collect()
+        .find(|&pos| parser.parse(&name[pos..]).is_some())
+        .map(|pos| &name[..pos])
 }
 
-fn compute_backup_chains(fname_infos: &[FileNameInfo]) -> Vec<BackupChain> {
+fn compute_backup_chains(
+    fname_infos: &[FileNameInfo],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> (Vec<BackupChain>, Vec<BackupSet>) {
     let mut backup_chains: Vec<BackupChain> = Vec::new();
-    for set in compute_backup_sets(fname_infos) {
+    let mut orphaned_sets = Vec::new();
+    // the position of the chains by end time, and by start time of their last incremental set;
+    // when more chains have the same key, the first one is used, as `add_inc` would do
+    let mut by_end = HashMap::new();
+    let mut by_last_start: HashMap<Timespec, usize> = HashMap::new();
+    for set in compute_backup_sets(fname_infos, diagnostics) {
         match set.tp {
-            Type::Full { .. } => {
-                let new_chain = BackupChain::new(set);
-                backup_chains.push(new_chain);
+            Type::Full { time } => {
+                by_end.entry(time).or_insert(backup_chains.len());
+                backup_chains.push(BackupChain::new(set));
             }
-            Type::Inc { .. } => {
-                let mut rejected_set = Some(set);
-                for chain in &mut backup_chains {
-                    rejected_set = chain.add_inc(rejected_set.unwrap());
-                    if rejected_set.is_none() {
-                        break;
+            Type::Inc {
+                start_time,
+                end_time,
+            } => {
+                // the set is appended to a chain, or replaces a shorter last set
+                let appending = by_end.get(&start_time).cloned();
+                let replacing = by_last_start
+                    .get(&start_time)
+                    .cloned()
+                    .filter(|&i| end_time > backup_chains[i].end_time);
+                let index = match appending.into_iter().chain(replacing).min() {
+                    Some(index) => index,
+                    None => {
+                        if let Some(name) = set.file_names().next() {
+                            diagnostics.push(Diagnostic::new(name, Reason::OrphanedSet));
+                        }
+                        orphaned_sets.push(set);
+                        continue;
                     }
+                };
+                let chain = &mut backup_chains[index];
+                let old_end = chain.end_time;
+                let last = chain.incsets.last();
+                let old_last_start = last.map(BackupSet::start_time);
+                let last = last.and_then(|s| s.file_names().next()).map(str::to_owned);
+                let num_incs = chain.incsets.len();
+                let rejected = chain.add_inc(set);
+                debug_assert!(rejected.is_none());
+                if let (Some(last), true) = (last, chain.incsets.len() == num_incs) {
+                    diagnostics.push(Diagnostic::new(last, Reason::ReplacedSet));
                 }
-                if let Some(_) = rejected_set {
-                    // TODO: add to orphaned sets
-                }
+                reindex(&mut by_end, Some(old_end), chain.end_time, index);
+                reindex(&mut by_last_start, old_last_start, start_time, index);
             }
         }
     }
     // sort by end time
     backup_chains.sort_by(|a, b| a.end_time.cmp(&b.end_time));
-    backup_chains
+    (backup_chains, orphaned_sets)
 }
 
-fn compute_backup_sets(fname_infos: &[FileNameInfo]) -> Vec<BackupSet> {
+// moves the position of a chain from the old key to the new one, if not already taken
+fn reindex(
+    positions: &mut HashMap<Timespec, usize>,
+    old: Option<Timespec>,
+    new: Timespec,
+    index: usize,
+) {
+    if let Some(old) = old {
+        if positions.get(&old) == Some(&index) {
+            positions.remove(&old);
+        }
+    }
+    positions.entry(new).or_insert(index);
+}
+
+fn compute_backup_sets(
+    fname_infos: &[FileNameInfo],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<BackupSet> {
     let mut sets = Vec::<BackupSet>::new();
+    // the position of the sets, by their times
+    let mut positions: HashMap<Type, usize> = HashMap::new();
     for fileinfo in fname_infos {
-        let mut inserted = false;
-        for set in &mut sets {
-            if set.add_filename(&fileinfo) {
-                inserted = true;
-                break;
+        match positions.entry(Type::from(&fileinfo.info.tp)) {
+            Entry::Occupied(entry) => {
+                let set = &mut sets[*entry.get()];
+                if let Some((name, reason)) = set.replaced_file(fileinfo) {
+                    diagnostics.push(Diagnostic::new(name, reason));
+                }
+                set.add_filename(fileinfo);
             }
+            Entry::Vacant(entry) => {
+                entry.insert(sets.len());
+                sets.push(BackupSet::new(fileinfo));
+            }
+        }
+    }
+    // the sets made only by signatures have no files: signatures are paired later
+    sets.retain(|set| set.file_names().next().is_some());
+    // report the sets that cannot be restored
+    for set in &sets {
+        let name = set.file_names().next().unwrap();
+        if !set.is_complete() {
+            diagnostics.push(Diagnostic::new(name, Reason::MissingManifest));
         }
-        if !inserted {
-            sets.push(BackupSet::new(&fileinfo));
+        for (num, path) in set.volumes_paths.iter().enumerate().skip(1) {
+            if path.is_none() {
+                diagnostics.push(Diagnostic::new(name, Reason::MissingVolume(num)));
+            }
         }
     }
     // sort by time
@@ -628,7 +1010,10 @@ fn compute_backup_sets(fname_infos: &[FileNameInfo]) -> Vec<BackupSet> {
     sets
 }
 
-fn compute_signature_chains(fname_infos: &[FileNameInfo]) -> Vec<SignatureChain> {
+fn compute_signature_chains(
+    fname_infos: &[FileNameInfo],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> (Vec<SignatureChain>, Vec<SignatureFile>) {
     // collect full signatures, sort them by start time and make the chains from them
     let mut sig_chains = fname_infos
         .iter()
@@ -645,20 +1030,73 @@ fn compute_signature_chains(fname_infos: &[FileNameInfo]) -> Vec<SignatureChain>
         is.sort_by(|a, b| a.start_time().cmp(&b.start_time()));
         is
     };
-    // add inc signatures to chains
+    // add inc signatures to chains, found by end time
+    let mut by_end = HashMap::new();
+    for (index, chain) in sig_chains.iter().enumerate() {
+        by_end.entry(chain.end_time()).or_insert(index);
+    }
+    let mut orphaned_sigs = Vec::new();
     for inc in inc_sigs {
-        let mut added = false;
-        for chain in &mut sig_chains {
-            if chain.end_time() == inc.start_time() && chain.add_new_sig(inc) {
-                added = true;
-                break;
+        match by_end.get(&inc.start_time()).cloned() {
+            Some(index) if sig_chains[index].add_new_sig(inc) => {
+                let end_time = sig_chains[index].end_time();
+                reindex(&mut by_end, Some(inc.start_time()), end_time, index);
+            }
+            _ => {
+                diagnostics.push(Diagnostic::new(inc.file_name, Reason::OrphanedSignature));
+                orphaned_sigs.push(SignatureFile::from_filename_info(inc));
             }
-        }
-        if !added {
-            // TODO: add to orphaned incremental signatures
         }
     }
+    (sig_chains, orphaned_sigs)
+}
+
+// the position of the first signature chain starting at the given time, in chains sorted by
+// start time
+fn signature_chain_index(sig_chains: &[SignatureChain], time: Timespec) -> Option<usize> {
+    let index = sig_chains.partition_point(|sig| sig.start_time() < time);
     sig_chains
+        .get(index)
+        .filter(|sig| sig.start_time() == time)
+        .map(|_| index)
+}
+
+// reports the backup sets without signatures and the signatures without backup sets
+fn check_pairing(
+    backup_chains: &[BackupChain],
+    sig_chains: &[SignatureChain],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut paired_sigs = HashSet::new();
+    for chain in backup_chains {
+        let sig_index = signature_chain_index(sig_chains, chain.start_time());
+        for set in Some(&chain.fullset).into_iter().chain(&chain.incsets) {
+            let index = sig_index.and_then(|i| sig_chains[i].signature_index(set.end_time()));
+            match (sig_index, index) {
+                (Some(sig_index), Some(index)) => {
+                    paired_sigs.insert((sig_index, index));
+                }
+                _ => {
+                    if let Some(name) = set.file_names().next() {
+                        diagnostics.push(Diagnostic::new(name, Reason::MissingSignature));
+                    }
+                }
+            }
+        }
+    }
+    for (sig_index, sig_chain) in sig_chains.iter().enumerate() {
+        let sigs = Some(&sig_chain.fullsig)
+            .into_iter()
+            .chain(&sig_chain.incsigs);
+        for (index, sig) in sigs.enumerate() {
+            if !paired_sigs.contains(&(sig_index, index)) {
+                diagnostics.push(Diagnostic::new(
+                    sig.file_name.as_str(),
+                    Reason::UnpairedSignature,
+                ));
+            }
+        }
+    }
 }
 
 impl Display for Collections {
@@ -666,10 +1104,56 @@ impl Display for Collections {
         for backup_chain in &self.backup_chains {
             backup_chain.fmt(f)?;
         }
+        if !self.diagnostics.is_empty() {
+            writeln!(f, "Diagnostics:")?;
+            for diagnostic in &self.diagnostics {
+                writeln!(f, " {}", diagnostic)?;
+            }
+        }
         Ok(())
     }
 }
 
+/// Displays an optional size in bytes, or "?" if unknown.
+struct SizeDisplay(Option<u64>);
+
+impl Display for SizeDisplay {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self.0 {
+            Some(size) => size.fmt(f),
+            None => "?".fmt(f),
+        }
+    }
+}
+
+impl From<&fnm::Type> for Type {
+    fn from(tp: &fnm::Type) -> Self {
+        match *tp {
+            fnm::Type::Full { time, .. }
+            | fnm::Type::FullManifest { time, .. }
+            | fnm::Type::FullSig { time, .. } => Type::Full { time },
+            fnm::Type::Inc {
+                start_time,
+                end_time,
+                ..
+            }
+            | fnm::Type::IncManifest {
+                start_time,
+                end_time,
+                ..
+            }
+            | fnm::Type::NewSig {
+                start_time,
+                end_time,
+                ..
+            } => Type::Inc {
+                start_time,
+                end_time,
+            },
+        }
+    }
+}
+
 impl Type {
     pub fn start_time(&self) -> Timespec {
         match *self {
@@ -730,6 +1214,159 @@ mod test {
         assert_eq!(set.manifest_path, manifest1_name);
     }
 
+    #[test]
+    fn diagnostics() {
+        let names = vec![
+            "README",
+            "duplicity-full.2015O617T182545Z.manifest",
+            "duplicity-full.20150617T182545Z.manifest",
+            "duplicity-full.20150617T182545Z.vol1.difftar.gz",
+            "duplicity-full.20150617T182545Z.vol1.difftar.gpg",
+            "duplicity-full.20150617T182545Z.vol3.difftar.gpg",
+            "duplicity-full-signatures.20150617T182545Z.sigtar.gz",
+            "duplicity-inc.20150617T182545Z.to.20150617T182629Z.manifest",
+            "duplicity-inc.20150617T182545Z.to.20150617T182650Z.manifest",
+            "duplicity-inc.20150617T182545Z.to.20150617T182650Z.vol1.difftar.gz",
+            "duplicity-inc.20150617T182650Z.to.20150617T182700Z.vol1.difftar.gz",
+            "duplicity-inc.20150601T000000Z.to.20150602T000000Z.manifest",
+            "duplicity-new-signatures.20150601T000000Z.to.20150602T000000Z.sigtar.gz",
+        ];
+        let coll = Collections::from_filenames(&names);
+        let actual: Vec<_> = coll
+            .diagnostics()
+            .map(|d| (d.file_name(), d.reason()))
+            .collect();
+        assert_eq!(
+            actual,
+            vec![
+                ("README", Reason::UnknownName),
+                (
+                    "duplicity-full.20150617T182545Z.manifest",
+                    Reason::MissingVolume(2)
+                ),
+                (
+                    "duplicity-full.20150617T182545Z.vol1.difftar.gz",
+                    Reason::DuplicateVolume(1)
+                ),
+                (
+                    "duplicity-full.2015O617T182545Z.manifest",
+                    Reason::MalformedName
+                ),
+                (
+                    "duplicity-inc.20150601T000000Z.to.20150602T000000Z.manifest",
+                    Reason::OrphanedSet
+                ),
+                (
+                    "duplicity-inc.20150617T182545Z.to.20150617T182629Z.manifest",
+                    Reason::ReplacedSet
+                ),
+                (
+                    "duplicity-inc.20150617T182545Z.to.20150617T182650Z.manifest",
+                    Reason::MissingSignature
+                ),
+                (
+                    "duplicity-inc.20150617T182650Z.to.20150617T182700Z.vol1.difftar.gz",
+                    Reason::MissingManifest
+                ),
+                (
+                    "duplicity-inc.20150617T182650Z.to.20150617T182700Z.vol1.difftar.gz",
+                    Reason::MissingSignature
+                ),
+                (
+                    "duplicity-new-signatures.20150601T000000Z.to.20150602T000000Z.sigtar.gz",
+                    Reason::OrphanedSignature
+                ),
+            ]
+        );
+        assert_eq!(
+            coll.diagnostics().map(|d| d.severity()).max(),
+            Some(Severity::Error)
+        );
+        let display = coll.to_string();
+        assert!(display.contains("Diagnostics:\n info: README: not a backup file\n"));
+        assert!(display.contains(
+            " warning: duplicity-full.2015O617T182545Z.manifest: malformed time or volume number\n"
+        ));
+        assert!(display.contains(
+            " error: duplicity-full.20150617T182545Z.manifest: the backup set misses volume 2\n"
+        ));
+
+        // a complete backup has no diagnostics
+        let coll = Collections::from_filenames(get_test_filenames());
+        assert_eq!(coll.diagnostics().count(), 0);
+        assert!(!coll.to_string().contains("Diagnostics"));
+    }
+
+    #[test]
+    fn signature_pairing() {
+        let names = vec![
+            // a chain without signatures
+            "duplicity-full.20150617T182545Z.manifest",
+            "duplicity-full.20150617T182545Z.vol1.difftar.gz",
+            // a chain missing the signature of its last set
+            "duplicity-full.20150618T182545Z.manifest",
+            "duplicity-full.20150618T182545Z.vol1.difftar.gz",
+            "duplicity-full-signatures.20150618T182545Z.sigtar.gz",
+            "duplicity-inc.20150618T182545Z.to.20150619T182545Z.manifest",
+            "duplicity-inc.20150618T182545Z.to.20150619T182545Z.vol1.difftar.gz",
+            "duplicity-new-signatures.20150618T182545Z.to.20150619T182545Z.sigtar.gz",
+            "duplicity-inc.20150619T182545Z.to.20150620T182545Z.manifest",
+            "duplicity-inc.20150619T182545Z.to.20150620T182545Z.vol1.difftar.gz",
+            // signatures without backup sets
+            "duplicity-full-signatures.20150621T182545Z.sigtar.gz",
+        ];
+        let coll = Collections::from_filenames(&names);
+        let chains: Vec<_> = coll.backup_chains().collect();
+        assert_eq!(chains.len(), 2);
+        assert!(coll.signature_chain_for(chains[0]).is_none());
+        let sig_chain = coll.signature_chain_for(chains[1]).unwrap();
+        assert_eq!(sig_chain.start_time(), chains[1].start_time());
+        assert_eq!(
+            sig_chain.signature_index(chains[1].full_set().end_time()),
+            Some(0)
+        );
+        let inc_times: Vec<_> = chains[1]
+            .inc_sets()
+            .map(|set| sig_chain.signature_index(set.end_time()))
+            .collect();
+        assert_eq!(inc_times, vec![Some(1), None]);
+
+        let actual: Vec<_> = coll
+            .diagnostics()
+            .map(|d| (d.file_name(), d.reason()))
+            .collect();
+        assert_eq!(
+            actual,
+            vec![
+                (
+                    "duplicity-full-signatures.20150621T182545Z.sigtar.gz",
+                    Reason::UnpairedSignature
+                ),
+                (
+                    "duplicity-full.20150617T182545Z.manifest",
+                    Reason::MissingSignature
+                ),
+                (
+                    "duplicity-inc.20150619T182545Z.to.20150620T182545Z.manifest",
+                    Reason::MissingSignature
+                ),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_diagnostic() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let coll = Collections::from_filenames(vec![OsStr::from_bytes(b"invalid\xff")]);
+        let diagnostic = coll.diagnostics().next().unwrap();
+        assert_eq!(diagnostic.file_name(), "invalid\u{fffd}");
+        assert_eq!(diagnostic.reason(), Reason::NonUtf8Name);
+        assert_eq!(diagnostic.severity(), Severity::Warning);
+    }
+
     #[test]
     fn collection_status_display() {
         // NOTE: this is actually not a proper test
@@ -786,6 +1423,36 @@ mod test {
         }
     }
 
+    #[test]
+    fn set_sizes() {
+        let infos = get_test_filenames()
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| FileInfo {
+                name,
+                size: Some(i as u64 * 10),
+                mtime: None,
+            })
+            .collect::<Vec<_>>();
+        let collection = Collections::from_file_infos(infos);
+        let chain = collection.backup_chains().next().unwrap();
+        assert_eq!(chain.full_set().manifest_size(), Some(0));
+        assert_eq!(chain.full_set().volume_size(1), Some(10));
+        assert_eq!(chain.full_set().total_size(), Some(10));
+        let inc_sizes = chain
+            .inc_sets()
+            .map(BackupSet::total_size)
+            .collect::<Vec<_>>();
+        assert_eq!(inc_sizes, vec![Some(30 + 40), Some(50 + 60)]);
+        assert_eq!(chain.total_size(), Some(10 + 70 + 110));
+
+        // sizes are unknown if some file has no size
+        let collection = Collections::from_filenames(get_test_filenames());
+        let chain = collection.backup_chains().next().unwrap();
+        assert_eq!(chain.full_set().volume_size(1), None);
+        assert_eq!(chain.total_size(), None);
+    }
+
     #[test]
     fn multi_chain() {
         let fnames = vec![
@@ -816,4 +1483,67 @@ mod test {
         let chain = collection.signature_chains().nth(1).unwrap();
         assert_eq!(chain.inc_signatures().count(), 1);
     }
+
+    #[test]
+    fn large_collections() {
+        use crate::timefmt::format_time_str;
+
+        // many chains with many sets, where some incremental sets are replaced by longer ones
+        let mut names = Vec::new();
+        let mut time = 1_400_000_000;
+        for _ in 0..20 {
+            let full = format_time_str(Timespec::new(time, 0));
+            names.push(format!("duplicity-full.{}.manifest", full));
+            names.push(format!("duplicity-full.{}.vol1.difftar.gz", full));
+            names.push(format!("duplicity-full-signatures.{}.sigtar.gz", full));
+            let mut start = full;
+            for i in 0..50 {
+                time += 60;
+                if i % 10 == 9 {
+                    // a shorter set, replaced by the following one
+                    let end = format_time_str(Timespec::new(time - 30, 0));
+                    names.push(format!("duplicity-inc.{}.to.{}.manifest", start, end));
+                }
+                let end = format_time_str(Timespec::new(time, 0));
+                let set = format!("{}.to.{}", start, end);
+                names.push(format!("duplicity-inc.{}.manifest", set));
+                names.push(format!("duplicity-inc.{}.vol1.difftar.gz", set));
+                names.push(format!("duplicity-new-signatures.{}.sigtar.gz", set));
+                start = end;
+            }
+            time += 60;
+        }
+        let coll = Collections::from_filenames(&names);
+        assert_eq!(coll.backup_chains().count(), 20);
+        assert_eq!(coll.signature_chains().count(), 20);
+        assert!(coll.backup_chains().all(|c| c.inc_sets().count() == 50));
+        for chain in coll.backup_chains() {
+            let sig_chain = coll.signature_chain_for(chain).unwrap();
+            assert_eq!(sig_chain.inc_signatures().count(), 50);
+            assert_eq!(sig_chain.signature_index(chain.end_time()), Some(50));
+        }
+        let replaced = coll
+            .diagnostics()
+            .filter(|d| d.reason() == Reason::ReplacedSet)
+            .count();
+        assert_eq!(replaced, 100);
+        assert_eq!(coll.diagnostics().count(), replaced);
+
+        // the result does not depend on the order of the names
+        names.reverse();
+        let reversed = Collections::from_filenames(&names);
+        assert_eq!(reversed.to_string(), coll.to_string());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let coll = Collections::from_filenames(get_test_filenames());
+        let json = serde_json::to_string(&coll).unwrap();
+        assert!(json.contains(r#""type":{"kind":"full","time":1434565545}"#));
+        assert!(json.contains(r#""full_signature":{"file_name":"duplicity-full-signatures"#));
+        let deserialized: Collections = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&deserialized).unwrap(), json);
+        assert_eq!(format!("{}", deserialized), format!("{}", coll));
+    }
 }
@@ -0,0 +1,373 @@
+//! Comparison of local directories with backup snapshots.
+//!
+//! This sub-module allows to check whether a directory matches the content of a snapshot, like
+//! `duplicity verify` does. Files missing in the directory, files not present in the snapshot and
+//! files with different metadata are reported. Optionally the content of regular files can be
+//! compared too, by using the librsync signatures stored in the backup, so that no volume needs
+//! to be downloaded.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use ruplicity::Backup;
+//! use ruplicity::backend::local::LocalBackend;
+//!
+//! let backend = LocalBackend::new("/path/to/backup");
+//! let backup = Backup::new(backend).unwrap();
+//! let snapshots = backup.snapshots().unwrap();
+//! let snapshot = snapshots.into_iter().last().unwrap();
+//! let comparison = snapshot.compare_data_with("/path/to/restored").unwrap();
+//! if !comparison.is_empty() {
+//!     print!("{}", comparison);
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::slice;
+
+use crate::rawpath::RawPath;
+use crate::rsync::RsSignature;
+use crate::signatures::{Entry, EntryType, SnapshotEntries};
+use crate::writer::{cmp_index, os_bytes, walk_dir, Owners, SourceEntry};
+
+/// The result of the comparison between a local directory and a snapshot.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Comparison {
+    differences: Vec<Difference>,
+}
+
+/// A path that differs between a local directory and a snapshot.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Difference {
+    path: RawPath,
+    kind: Kind,
+}
+
+/// The kind of a difference.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// The path is present in the snapshot, but not in the local directory.
+    Missing,
+    /// The path is present in the local directory, but not in the snapshot.
+    Extra,
+    /// The path is present in both, but some of its attributes differ.
+    Changed(Vec<Attribute>),
+}
+
+/// An attribute of a file that can differ.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Attribute {
+    /// The entry type, for example a file replaced by a directory.
+    Type,
+    /// The permission bits.
+    Mode,
+    /// The owner user id.
+    Owner,
+    /// The owner group id.
+    Group,
+    /// The modification time.
+    Mtime,
+    /// The size of a regular file.
+    Size,
+    /// The target of a symbolic link.
+    LinkTarget,
+    /// The content of a regular file.
+    ///
+    /// This is checked only if requested, by using the signatures stored in the backup.
+    Content,
+}
+
+/// An iterator over the differences found in a comparison.
+#[derive(Debug)]
+pub struct Differences<'a>(slice::Iter<'a, Difference>);
+
+impl Comparison {
+    /// Compares the local directory with the given snapshot entries.
+    ///
+    /// If `signatures` is given, the content of the regular files present in both is compared
+    /// too. It must return the librsync signatures of the requested paths, as stored in the
+    /// snapshot.
+    pub(crate) fn new<F>(
+        entries: SnapshotEntries<'_>,
+        local_dir: &Path,
+        signatures: Option<F>,
+    ) -> io::Result<Self>
+    where
+        F: FnOnce(&HashSet<&[u8]>) -> io::Result<HashMap<Vec<u8>, Vec<u8>>>,
+    {
+        let mut expected: HashMap<&[u8], Entry<'_>> =
+            entries.map(|e| (e.path_bytes(), e)).collect();
+        let owners = Owners::default();
+        let mut differences = Vec::new();
+        // the regular files present in both, by path
+        let mut files: HashMap<Vec<u8>, SourceEntry> = HashMap::new();
+        walk_dir(local_dir, &mut |local| {
+            let kind = match expected.remove(&local.index[..]) {
+                None => Kind::Extra,
+                Some(entry) => {
+                    let attributes = changed_attributes(&entry, local, &owners)?;
+                    if entry.entry_type() == EntryType::File && local.meta.is_file() {
+                        files.insert(local.index.clone(), local.clone());
+                    }
+                    if attributes.is_empty() {
+                        return Ok(());
+                    }
+                    Kind::Changed(attributes)
+                }
+            };
+            differences.push(Difference::new(&local.index, kind));
+            Ok(())
+        })?;
+        differences.extend(
+            expected
+                .into_keys()
+                .map(|path| Difference::new(path, Kind::Missing)),
+        );
+
+        if let Some(signatures) = signatures {
+            let paths = files.keys().map(|p| &p[..]).collect();
+            for (path, signature) in signatures(&paths)? {
+                let signature = RsSignature::parse(&signature)?;
+                let local = &files[&path];
+                if signature
+                    .changed_blocks(File::open(&local.path)?)?
+                    .is_empty()
+                {
+                    continue;
+                }
+                match differences
+                    .iter_mut()
+                    .find(|d| d.path_bytes() == path.as_slice())
+                {
+                    Some(&mut Difference {
+                        kind: Kind::Changed(ref mut attributes),
+                        ..
+                    }) => attributes.push(Attribute::Content),
+                    _ => differences.push(Difference::new(
+                        &path,
+                        Kind::Changed(vec![Attribute::Content]),
+                    )),
+                }
+            }
+        }
+        differences.sort_by(|a, b| cmp_index(a.path_bytes(), b.path_bytes()));
+        Ok(Comparison { differences })
+    }
+
+    /// Returns an iterator over the differences found, sorted by path.
+    pub fn differences(&self) -> Differences<'_> {
+        Differences(self.differences.iter())
+    }
+
+    /// Returns whether the local directory matches the snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+impl Difference {
+    fn new(path: &[u8], kind: Kind) -> Self {
+        Difference {
+            path: RawPath::from_bytes(path.to_owned()),
+            kind,
+        }
+    }
+
+    /// Returns the path, relative to the compared directory, if representable in the current
+    /// platform.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_path()
+    }
+
+    /// Returns the path, relative to the compared directory, in bytes.
+    pub fn path_bytes(&self) -> &[u8] {
+        self.path.as_bytes()
+    }
+
+    /// Returns the kind of the difference.
+    pub fn kind(&self) -> &Kind {
+        &self.kind
+    }
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.kind {
+            Kind::Missing => write!(f, "missing {}", self.path),
+            Kind::Extra => write!(f, "extra   {}", self.path),
+            Kind::Changed(ref attributes) => {
+                write!(f, "changed {} (", self.path)?;
+                for (i, attribute) in attributes.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", attribute)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl Display for Attribute {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match *self {
+            Attribute::Type => "type",
+            Attribute::Mode => "mode",
+            Attribute::Owner => "owner",
+            Attribute::Group => "group",
+            Attribute::Mtime => "mtime",
+            Attribute::Size => "size",
+            Attribute::LinkTarget => "link target",
+            Attribute::Content => "content",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl<'a> Iterator for Differences<'a> {
+    type Item = &'a Difference;
+
+    fn next(&mut self) -> Option<&'a Difference> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+// compares the metadata of a snapshot entry with the local one
+fn changed_attributes(
+    entry: &Entry<'_>,
+    local: &SourceEntry,
+    owners: &Owners,
+) -> io::Result<Vec<Attribute>> {
+    let header = local.header(owners);
+    let entry_type = EntryType::new(header.entry_type().as_byte());
+    let mut result = Vec::new();
+    if entry_type != entry.entry_type() {
+        result.push(Attribute::Type);
+    }
+    if entry.mode().map(|m| m & 0o7777) != header.mode().ok().map(|m| m & 0o7777) {
+        result.push(Attribute::Mode);
+    }
+    if entry.userid() != header.uid().ok() {
+        result.push(Attribute::Owner);
+    }
+    if entry.groupid() != header.gid().ok() {
+        result.push(Attribute::Group);
+    }
+    if Some(entry.mtime().sec) != header.mtime().ok().map(|t| t as i64) {
+        result.push(Attribute::Mtime);
+    }
+    if entry_type == EntryType::File && entry.entry_type() == EntryType::File {
+        let size = local.meta.len() as usize;
+        match entry.size_hint() {
+            Some((min, max)) if min <= size && size <= max => (),
+            _ => result.push(Attribute::Size),
+        }
+    }
+    if entry.linked_path().map(|p| os_bytes(p.as_os_str())) != local.link()? {
+        result.push(Attribute::LinkTarget);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::memory::MemoryBackend;
+    #[cfg(unix)]
+    use crate::writer::set_mtime;
+    use crate::writer::BackupWriter;
+    use crate::Backup;
+    use std::env;
+    use std::fs;
+    #[cfg(unix)]
+    use std::os::unix::fs::MetadataExt;
+    use std::path::PathBuf;
+    use std::process;
+    use time::Timespec;
+
+    // a temporary directory, removed on drop
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn make_source(name: &str) -> TempDir {
+        let dir = env::temp_dir().join(format!("ruplicity-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("dir")).unwrap();
+        fs::write(dir.join("dir/file"), vec![1; 3000]).unwrap();
+        fs::write(dir.join("removed"), b"removed").unwrap();
+        fs::write(dir.join("same"), b"same").unwrap();
+        TempDir(dir)
+    }
+
+    // returns the differences, except the ones of the root directory, whose mtime could change
+    fn differences(comparison: &Comparison) -> Vec<String> {
+        comparison
+            .differences()
+            .filter(|d| !d.path_bytes().is_empty())
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn compare_with() {
+        let source = make_source("compare-with");
+        let backend = MemoryBackend::new();
+        BackupWriter::new(&backend)
+            .full(&source.0, Timespec::new(1_500_000_000, 0))
+            .unwrap();
+        let backup = Backup::new(backend).unwrap();
+        let snapshots = backup.snapshots().unwrap();
+        let snapshot = snapshots.into_iter().next().unwrap();
+        assert!(snapshot.compare_with(&source.0).unwrap().is_empty());
+        assert!(snapshot.compare_data_with(&source.0).unwrap().is_empty());
+
+        // same size and mtime, different content
+        let file = source.0.join("dir/file");
+        let mtime = fs::metadata(&file).unwrap().mtime();
+        let mut data = vec![1; 3000];
+        data[1000] = 2;
+        fs::write(&file, data).unwrap();
+        set_mtime(&file, Timespec::new(mtime, 0));
+        fs::remove_file(source.0.join("removed")).unwrap();
+        fs::write(source.0.join("added"), b"added").unwrap();
+
+        let comparison = snapshot.compare_with(&source.0).unwrap();
+        assert_eq!(
+            differences(&comparison),
+            vec!["extra   added", "missing removed"]
+        );
+        let comparison = snapshot.compare_data_with(&source.0).unwrap();
+        assert_eq!(
+            differences(&comparison),
+            vec![
+                "extra   added",
+                "changed dir/file (content)",
+                "missing removed"
+            ]
+        );
+    }
+}
@@ -41,32 +41,57 @@
 #![cfg_attr(feature = "lints", feature(plugin))]
 #![cfg_attr(feature = "lints", plugin(clippy))]
 
+extern crate blake2;
 extern crate byteorder;
 extern crate flate2;
+#[cfg(feature = "async")]
+extern crate futures;
+extern crate md4;
+#[cfg(test)]
 extern crate regex;
+#[cfg(feature = "serde")]
+extern crate serde;
+extern crate sha1;
 extern crate tabwriter;
 extern crate tar;
 extern crate time;
+#[cfg(unix)]
+extern crate users;
+#[cfg(feature = "zip")]
+extern crate zip;
 
 mod macros;
 mod rawpath;
 
 pub mod backend;
+pub mod cleanup;
 pub mod collections;
+pub mod compare;
+pub mod listing;
 pub mod manifest;
+pub mod prune;
+pub mod rsync;
 pub mod signatures;
 pub mod timefmt;
+pub mod writer;
 
-use std::cell::{Ref, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::ops::Deref;
 use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
+#[cfg(feature = "async")]
+use futures::future::{self, BoxFuture};
+#[cfg(feature = "async")]
+use futures::io::AsyncReadExt;
 use time::Timespec;
 
 pub use backend::Backend;
-use collections::{BackupChain, BackupSet, Collections};
+#[cfg(feature = "async")]
+use backend::Blocking;
+use collections::{BackupChain, BackupSet, Collections, FileNaming, SignatureChain};
 use manifest::Manifest;
 use signatures::Chain;
 
@@ -75,8 +100,10 @@ use signatures::Chain;
 pub struct Backup<B> {
     backend: B,
     collections: Collections,
-    signatures: Vec<RefCell<Option<Chain>>>,
-    manifests: Vec<RefCell<Option<Manifest>>>,
+    signatures: Vec<Mutex<Option<Arc<Chain>>>>,
+    manifests: Vec<Mutex<Option<Arc<Manifest>>>>,
+    loader: Loader<B>,
+    naming: FileNaming,
 }
 
 /// Represents all the snapshots in a backup.
@@ -88,7 +115,7 @@ pub struct Snapshots<'a> {
 pub struct SnapshotsIter<'a> {
     set_iter: CollectionsIter<'a>,
     chain_id: usize,
-    sig_id: usize,
+    sig_chain: Option<&'a SignatureChain>,
     man_id: usize,
     backup: &'a dyn ResourceCache,
 }
@@ -98,20 +125,56 @@ pub struct Snapshot<'a> {
     set: &'a BackupSet,
     // the number of the parent backup chain, starting from zero
     chain_id: usize,
-    sig_id: usize,
+    // the index of the signature of the set in the paired signature chain, if present
+    sig_id: Option<usize>,
     man_id: usize,
     backup: &'a dyn ResourceCache,
 }
 
 /// Contains the files present in a certain backup snapshot.
-pub struct SnapshotEntries<'a> {
-    chain: Ref<'a, Option<Chain>>,
+///
+/// The signatures are shared with the cache of the backup, so this can be kept and sent to other
+/// threads independently from the backup.
+pub struct SnapshotEntries {
+    chain: Arc<Chain>,
     sig_id: usize,
 }
 
 /// Reference to a Manifest.
+///
+/// The manifest is shared with the cache of the backup.
+#[derive(Debug)]
+pub struct ManifestRef(Arc<Manifest>);
+
+/// Functions used by a `Backup` to load signatures and manifests through its backend.
+///
+/// They are selected when the backup is opened, depending on whether the backend is synchronous
+/// or asynchronous. In this way the caching logic is shared between the two. Synchronous backends
+/// are accessed in a blocking way by the asynchronous functions, and asynchronous backends are
+/// blocked on by the synchronous ones.
 #[derive(Debug)]
-pub struct ManifestRef<'a>(Ref<'a, Option<Manifest>>);
+struct Loader<B> {
+    signatures: fn(&B, &SignatureChain) -> io::Result<Chain>,
+    manifest: fn(&B, &str) -> Result<Manifest, manifest::ParseError>,
+    rsync_signatures: RsyncSigsLoader<B>,
+    stream_entries: EntriesStreamer<B>,
+    sizes: fn(&B, &mut Chain, usize, &BackupChain) -> io::Result<()>,
+    #[cfg(feature = "async")]
+    signatures_async: for<'b> fn(&'b B, &'b SignatureChain) -> BoxFuture<'b, io::Result<Chain>>,
+    #[cfg(feature = "async")]
+    manifest_async:
+        for<'b> fn(&'b B, &'b str) -> BoxFuture<'b, Result<Manifest, manifest::ParseError>>,
+}
+
+// librsync signatures of files, by path
+type RsyncSigs = HashMap<Vec<u8>, Vec<u8>>;
+
+// loads the signatures of the given paths in a snapshot of a signature chain
+type RsyncSigsLoader<B> = fn(&B, &SignatureChain, usize, &HashSet<&[u8]>) -> io::Result<RsyncSigs>;
+
+// reads the entries of a snapshot of a signature chain, without caching them
+type EntriesStreamer<B> =
+    fn(&B, &SignatureChain, usize, &mut dyn FnMut(&signatures::Entry)) -> io::Result<()>;
 
 struct CollectionsIter<'a> {
     chain_iter: collections::ChainIter<'a, BackupChain>,
@@ -124,12 +187,33 @@ struct CollectionsIter<'a> {
 /// components.
 trait ResourceCache {
     fn _collections(&self) -> &Collections;
-    fn _signature_chain(&self, chain_id: usize) -> io::Result<Ref<Option<Chain>>>;
+    fn _signature_chain(&self, chain_id: usize) -> io::Result<Arc<Chain>>;
     fn _manifest(
         &self,
         chain_id: usize,
         manifest_path: &str,
-    ) -> Result<Ref<Option<Manifest>>, manifest::ParseError>;
+    ) -> Result<Arc<Manifest>, manifest::ParseError>;
+    fn _rsync_signatures(
+        &self,
+        chain_id: usize,
+        sig_id: usize,
+        paths: &HashSet<&[u8]>,
+    ) -> io::Result<RsyncSigs>;
+    fn _stream_entries(
+        &self,
+        chain_id: usize,
+        sig_id: usize,
+        f: &mut dyn FnMut(&signatures::Entry),
+    ) -> io::Result<()>;
+    fn _resolve_sizes(&self, chain_id: usize, sig_id: usize) -> io::Result<()>;
+    #[cfg(feature = "async")]
+    fn _signature_chain_async(&self, chain_id: usize) -> BoxFuture<'_, io::Result<Arc<Chain>>>;
+    #[cfg(feature = "async")]
+    fn _manifest_async<'a>(
+        &'a self,
+        chain_id: usize,
+        manifest_path: &'a str,
+    ) -> BoxFuture<'a, Result<Arc<Manifest>, manifest::ParseError>>;
 }
 
 impl<B: Backend> Backup<B> {
@@ -151,21 +235,186 @@ impl<B: Backend> Backup<B> {
     /// println!("Got backup with {} snapshots!", backup.snapshots().unwrap().into_iter().count());
     /// ```
     pub fn new(backend: B) -> io::Result<Self> {
-        let files = backend.file_names()?;
-        let collections = Collections::from_filenames(files);
+        Self::with_naming(backend, &FileNaming::default())
+    }
+
+    /// Opens an existing backup written with custom file prefixes, by using the given backend.
+    ///
+    /// Files not matching the prefixes are ignored, so that many backups can share the same
+    /// location. Use `Collections::discover` to find the prefixes in use.
+    ///
+    /// # Errors
+    /// Like `Backup::new`.
+    pub fn with_naming(backend: B, naming: &FileNaming) -> io::Result<Self> {
+        let files = backend.file_infos()?;
+        let collections = Collections::from_file_infos_with(naming, files);
+        let loader = Loader {
+            signatures: load_signatures::<B>,
+            manifest: load_manifest::<B>,
+            rsync_signatures: load_rsync_signatures::<B>,
+            stream_entries: |backend, sigchain, sig_id, f| {
+                signatures::stream_entries(sigchain, sig_id, backend, f)
+            },
+            sizes: |backend, sig, sig_id, chain| sig.resolve_sizes(sig_id, chain, backend),
+            #[cfg(feature = "async")]
+            signatures_async: |backend, sigchain| {
+                Box::pin(future::ready(load_signatures(backend, sigchain)))
+            },
+            #[cfg(feature = "async")]
+            manifest_async: |backend, path| Box::pin(future::ready(load_manifest(backend, path))),
+        };
+        Ok(Backup::from_parts(backend, collections, loader, naming))
+    }
+
+    /// Lists the backup files again, to update the backup with the files added or removed since
+    /// it has been opened.
+    ///
+    /// The signatures and manifests already loaded are kept, as long as they are still valid.
+    /// When new signatures have been appended to a signature chain, only the new signature files
+    /// are read and added to the loaded chain.
+    ///
+    /// # Errors
+    /// An error is returned if the backup files cannot be listed, and in that case the backup is
+    /// left unchanged. Errors reading the new signature files are not returned: the signatures
+    /// of the chain are discarded instead, and loaded again when needed.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let files = self.backend.file_infos()?;
+        self.replace_collections(Collections::from_file_infos_with(&self.naming, files));
+        for (chain, sig) in self
+            .collections
+            .backup_chains()
+            .zip(self.signatures.iter_mut())
+        {
+            let sig = sig.get_mut().unwrap_or_else(PoisonError::into_inner);
+            // signatures still in use by some entries cannot be updated
+            let valid = match (
+                sig.as_mut().and_then(Arc::get_mut),
+                self.collections.signature_chain_for(chain),
+            ) {
+                (Some(sig), Some(sigchain)) => sig.update(sigchain, &self.backend).unwrap_or(false),
+                _ => false,
+            };
+            if !valid {
+                *sig = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<B: backend::AsyncBackend> Backup<B> {
+    /// Opens an existig backup by using the given asynchronous backend.
+    ///
+    /// Signatures and manifests of the returned backup should be loaded asynchronously, by using
+    /// `Snapshot::entries_async` and `Snapshot::manifest_async`. Their synchronous counterparts
+    /// can be used as well, but they block the current thread until the backend completes, so
+    /// they must not be called from an asynchronous task, nor from a thread running an executor:
+    /// if the backend is driven by that executor (e.g. a single threaded runtime) they would
+    /// never complete.
+    ///
+    /// # Errors
+    /// This function will return an error whenever the backend returns an error in a file
+    /// operation.
+    ///
+    /// # Examples
+    /// ```
+    /// use futures::executor::block_on;
+    /// use ruplicity::Backup;
+    /// use ruplicity::backend::local::LocalBackend;
+    ///
+    /// let backend = LocalBackend::new("tests/backups/single_vol");
+    /// let backup = block_on(Backup::new_async(backend)).unwrap();
+    /// for snapshot in backup.snapshots().unwrap() {
+    ///     let entries = block_on(snapshot.entries_async()).unwrap();
+    ///     println!("{}", entries);
+    /// }
+    /// ```
+    pub async fn new_async(backend: B) -> io::Result<Self> {
+        Self::with_naming_async(backend, &FileNaming::default()).await
+    }
+
+    /// Opens an existing backup written with custom file prefixes, by using the given
+    /// asynchronous backend.
+    ///
+    /// # Errors
+    /// Like `Backup::new_async`.
+    pub async fn with_naming_async(backend: B, naming: &FileNaming) -> io::Result<Self> {
+        let files = backend.file_infos().await?;
+        let collections = Collections::from_file_infos_with(naming, files);
+        let loader = Loader {
+            signatures: |backend, sigchain| load_signatures(&Blocking(backend), sigchain),
+            manifest: |backend, path| load_manifest(&Blocking(backend), path),
+            rsync_signatures: |backend, sigchain, sig_id, paths| {
+                load_rsync_signatures(&Blocking(backend), sigchain, sig_id, paths)
+            },
+            stream_entries: |backend, sigchain, sig_id, f| {
+                signatures::stream_entries(sigchain, sig_id, &Blocking(backend), f)
+            },
+            sizes: |backend, sig, sig_id, chain| {
+                sig.resolve_sizes(sig_id, chain, &Blocking(backend))
+            },
+            signatures_async: |backend, sigchain| {
+                Box::pin(Chain::from_sigchain_async(sigchain, backend))
+            },
+            manifest_async: |backend, path| Box::pin(load_manifest_async(backend, path)),
+        };
+        Ok(Backup::from_parts(backend, collections, loader, naming))
+    }
+
+    /// Lists the backup files again, by using the asynchronous backend.
+    ///
+    /// This is the asynchronous counterpart of `refresh`.
+    pub async fn refresh_async(&mut self) -> io::Result<()> {
+        let files = self.backend.file_infos().await?;
+        self.replace_collections(Collections::from_file_infos_with(&self.naming, files));
+        for (chain, sig) in self
+            .collections
+            .backup_chains()
+            .zip(self.signatures.iter_mut())
+        {
+            let sig = sig.get_mut().unwrap_or_else(PoisonError::into_inner);
+            // signatures still in use by some entries cannot be updated
+            let valid = match (
+                sig.as_mut().and_then(Arc::get_mut),
+                self.collections.signature_chain_for(chain),
+            ) {
+                (Some(sig), Some(sigchain)) => sig
+                    .update_async(sigchain, &self.backend)
+                    .await
+                    .unwrap_or(false),
+                _ => false,
+            };
+            if !valid {
+                *sig = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<B> Backup<B> {
+    fn from_parts(
+        backend: B,
+        collections: Collections,
+        loader: Loader<B>,
+        naming: &FileNaming,
+    ) -> Self {
         let signatures = collections
-            .signature_chains()
-            .map(|_| RefCell::new(None))
+            .backup_chains()
+            .map(|_| Mutex::new(None))
             .collect();
         let manifests = (0..collections.num_snapshots())
-            .map(|_| RefCell::new(None))
+            .map(|_| Mutex::new(None))
             .collect();
-        Ok(Backup {
-            backend: backend,
-            collections: collections,
-            signatures: signatures,
-            manifests: manifests,
-        })
+        Backup {
+            backend,
+            collections,
+            signatures,
+            manifests,
+            loader,
+            naming: naming.clone(),
+        }
     }
 
     /// Constructs an iterator over the snapshots currently present in this backup.
@@ -175,6 +424,28 @@ impl<B: Backend> Backup<B> {
         Ok(Snapshots { backup: self })
     }
 
+    /// Computes which files should be removed to prune old snapshots, according to the given
+    /// policy.
+    ///
+    /// No file is deleted by this function: the returned plan can be inspected, and executed by
+    /// using a backend able to delete files. See the `prune` module for details.
+    pub fn prune(&self, policy: prune::Policy) -> prune::PrunePlan {
+        prune::PrunePlan::new(&self.collections, policy)
+    }
+
+    /// Finds the files left behind by interrupted backups.
+    ///
+    /// No file is deleted by this function: the returned plan can be inspected, and executed by
+    /// using a backend able to delete files. See the `cleanup` module for details.
+    pub fn cleanup(&self) -> cleanup::CleanupPlan {
+        cleanup::CleanupPlan::new(&self.collections)
+    }
+
+    /// Returns a reference to the inner backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
     /// Unwraps this backup and returns the inner backend.
     pub fn into_inner(self) -> B {
         self.backend
@@ -202,7 +473,7 @@ impl<'a> IntoIterator for Snapshots<'a> {
         SnapshotsIter {
             set_iter: set_iter,
             chain_id: 0,
-            sig_id: 0,
+            sig_chain: None,
             man_id: 0,
             backup: self.backup,
         }
@@ -217,12 +488,11 @@ impl<'a> Iterator for SnapshotsIter<'a> {
         if let Some(ref mut incset_iter) = self.set_iter.incset_iter {
             // we have a set iter, so return the next element if present
             if let Some(inc_set) = incset_iter.next() {
-                self.sig_id += 1;
                 self.man_id += 1;
                 return Some(Snapshot {
                     set: inc_set,
                     chain_id: self.chain_id - 1,
-                    sig_id: self.sig_id,
+                    sig_id: signature_index(self.sig_chain, inc_set),
                     man_id: self.man_id - 1,
                     backup: self.backup,
                 });
@@ -234,13 +504,13 @@ impl<'a> Iterator for SnapshotsIter<'a> {
         match self.set_iter.chain_iter.next() {
             Some(chain) => {
                 self.chain_id += 1;
-                self.sig_id = 0;
+                self.sig_chain = self.backup._collections().signature_chain_for(chain);
                 self.man_id += 1;
                 self.set_iter.incset_iter = Some(chain.inc_sets());
                 Some(Snapshot {
                     set: chain.full_set(),
                     chain_id: self.chain_id - 1,
-                    sig_id: self.sig_id,
+                    sig_id: signature_index(self.sig_chain, chain.full_set()),
                     man_id: self.man_id - 1,
                     backup: self.backup,
                 })
@@ -286,107 +556,374 @@ impl<'a> Snapshot<'a> {
     /// Be aware that using this functionality means that all the signature files in the current
     /// backup chain must be loaded, and this could take some time, depending on the file access
     /// provided by the backend and the signatures size.
+    ///
+    /// # Errors
+    /// A `NotFound` error is returned if the snapshot has no signature, because the signature
+    /// chain or the signature of this set is missing.
     pub fn entries(&self) -> io::Result<SnapshotEntries> {
+        let sig_id = self.sig_id()?;
         let sig = self.backup._signature_chain(self.chain_id)?;
-        if self.sig_id < sig.as_ref().unwrap().snapshots().len() {
-            Ok(SnapshotEntries {
-                chain: sig,
-                sig_id: self.sig_id,
-            })
-        } else {
-            Err(not_found("The signature chain is incomplete"))
-        }
+        SnapshotEntries::new(sig, sig_id)
+    }
+
+    /// Returns the files and directories present in the snapshot, with exact file sizes.
+    ///
+    /// In addition to what `entries` does, the volumes of the backup sets containing the last
+    /// version of the files are scanned, so that `signatures::Entry::size` returns the exact
+    /// size of the regular files. The sizes are cached, and the volumes of a set are read only
+    /// once.
+    ///
+    /// # Errors
+    /// Any error reading the volumes is returned. An error is returned also if the signatures of
+    /// the chain are in use, because some `SnapshotEntries` of the same chain are still alive.
+    pub fn entries_with_sizes(&self) -> io::Result<SnapshotEntries> {
+        let sig_id = self.sig_id()?;
+        self.backup._resolve_sizes(self.chain_id, sig_id)?;
+        let sig = self.backup._signature_chain(self.chain_id)?;
+        SnapshotEntries::new(sig, sig_id)
+    }
+
+    /// Calls `f` for each file and directory present in the snapshot, reading them directly from
+    /// the signature files.
+    ///
+    /// Unlike `entries`, the signatures are not cached, and only the ones up to this snapshot are
+    /// read. Memory usage does not depend on the number of files, so this is preferable for
+    /// one-shot listings of huge backups. See `signatures::stream_entries` for details.
+    ///
+    /// # Errors
+    /// Like `entries`.
+    ///
+    /// # Examples
+    /// ```
+    /// use ruplicity::Backup;
+    /// use ruplicity::backend::local::LocalBackend;
+    ///
+    /// let backup = Backup::new(LocalBackend::new("tests/backups/single_vol")).unwrap();
+    /// for snapshot in backup.snapshots().unwrap() {
+    ///     snapshot.stream_entries(|entry| println!("{}", entry)).unwrap();
+    /// }
+    /// ```
+    pub fn stream_entries<F: FnMut(&signatures::Entry)>(&self, mut f: F) -> io::Result<()> {
+        let sig_id = self.sig_id()?;
+        self.backup._stream_entries(self.chain_id, sig_id, &mut f)
     }
 
     /// Returns the manifest for this snapshot.
     ///
     /// The relative manifest file is read on demand and cached for subsequent uses.
-    pub fn manifest(&self) -> Result<ManifestRef<'a>, manifest::ParseError> {
+    pub fn manifest(&self) -> Result<ManifestRef, manifest::ParseError> {
         Ok(ManifestRef(
             self.backup
                 ._manifest(self.man_id, self.set.manifest_path())?,
         ))
     }
+
+    /// Compares a local directory with the snapshot.
+    ///
+    /// Paths missing in the directory, not present in the snapshot, or whose metadata differ are
+    /// reported. File contents are not compared; see `compare_data_with` for that. The
+    /// signatures of the snapshot are loaded as for `entries`.
+    pub fn compare_with<P: AsRef<Path>>(&self, local_dir: P) -> io::Result<compare::Comparison> {
+        let entries = self.entries()?;
+        compare::Comparison::new(
+            entries.as_signature(),
+            local_dir.as_ref(),
+            None::<fn(&HashSet<&[u8]>) -> io::Result<RsyncSigs>>,
+        )
+    }
+
+    /// Compares a local directory with the snapshot, including the content of regular files.
+    ///
+    /// In addition to what `compare_with` does, the content of every regular file is checked
+    /// against the librsync signature stored in the backup. The signature files of the chain are
+    /// read again for this, but no volume is downloaded.
+    pub fn compare_data_with<P: AsRef<Path>>(
+        &self,
+        local_dir: P,
+    ) -> io::Result<compare::Comparison> {
+        let entries = self.entries()?;
+        let sig_id = self.sig_id()?;
+        let signatures =
+            |paths: &HashSet<&[u8]>| self.backup._rsync_signatures(self.chain_id, sig_id, paths);
+        compare::Comparison::new(entries.as_signature(), local_dir.as_ref(), Some(signatures))
+    }
+
+    /// Returns the files and directories present in the snapshot, loading them asynchronously.
+    ///
+    /// See `entries` for details. This function works for backups opened with both synchronous
+    /// and asynchronous backends; in the first case the backend is accessed in a blocking way,
+    /// when this function is called. The returned future is `Send`, and can be spawned on a
+    /// multi-threaded executor.
+    #[cfg(feature = "async")]
+    pub fn entries_async(&self) -> BoxFuture<'a, io::Result<SnapshotEntries>> {
+        let sig_id = match self.sig_id() {
+            Ok(sig_id) => sig_id,
+            Err(e) => return Box::pin(future::ready(Err(e))),
+        };
+        let sig = self.backup._signature_chain_async(self.chain_id);
+        Box::pin(async move { SnapshotEntries::new(sig.await?, sig_id) })
+    }
+
+    /// Returns the manifest for this snapshot, loading it asynchronously.
+    ///
+    /// See `manifest` and `entries_async` for details.
+    #[cfg(feature = "async")]
+    pub fn manifest_async(&self) -> BoxFuture<'a, Result<ManifestRef, manifest::ParseError>> {
+        let manifest = self
+            .backup
+            ._manifest_async(self.man_id, self.set.manifest_path());
+        Box::pin(async move { Ok(ManifestRef(manifest.await?)) })
+    }
+
+    // returns the index of the signature of this snapshot, or an error explaining why it is
+    // missing
+    fn sig_id(&self) -> io::Result<usize> {
+        self.sig_id.ok_or_else(|| {
+            let collections = self.backup._collections();
+            let chain = collections.backup_chains().nth(self.chain_id).unwrap();
+            if collections.signature_chain_for(chain).is_none() {
+                not_found(&format!(
+                    "The backup chain starting at {} has no signatures",
+                    timefmt::format_rfc3339(chain.start_time())
+                ))
+            } else {
+                not_found(&format!(
+                    "The backup set ending at {} has no signature",
+                    timefmt::format_rfc3339(self.set.end_time())
+                ))
+            }
+        })
+    }
 }
 
-impl<'a> SnapshotEntries<'a> {
+impl SnapshotEntries {
+    fn new(chain: Arc<Chain>, sig_id: usize) -> io::Result<Self> {
+        if sig_id < chain.snapshots().len() {
+            Ok(SnapshotEntries { chain, sig_id })
+        } else {
+            Err(not_found("The signature chain is incomplete"))
+        }
+    }
+
     /// Returns the signatures representation for the entries.
     ///
     /// This function can be used to retrieve information about the files in the snapshot.
     pub fn as_signature(&self) -> signatures::SnapshotEntries {
-        self.chain
-            .as_ref()
-            .unwrap()
-            .snapshots()
-            .nth(self.sig_id)
-            .unwrap()
-            .files()
+        self.chain.snapshots().nth(self.sig_id).unwrap().files()
     }
 }
 
-impl<'a> Display for SnapshotEntries<'a> {
+impl Display for SnapshotEntries {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         self.as_signature().into_display().fmt(f)
     }
 }
 
-impl<'a> Deref for ManifestRef<'a> {
+impl Deref for ManifestRef {
     type Target = Manifest;
 
     fn deref(&self) -> &Manifest {
-        self.0.as_ref().unwrap()
+        &self.0
     }
 }
 
-impl<B: Backend> ResourceCache for Backup<B> {
+impl<B> ResourceCache for Backup<B> {
     fn _collections(&self) -> &Collections {
         &self.collections
     }
 
-    fn _signature_chain(&self, chain_id: usize) -> io::Result<Ref<Option<Chain>>> {
-        {
-            // check if there is a cached value
-            let mut sig = self.signatures[chain_id].borrow_mut();
-            if sig.is_none() {
-                // compute signatures now
-                if let Some(sigchain) = self.collections.signature_chains().nth(chain_id) {
-                    let new_sig = Chain::from_sigchain(sigchain, &self.backend)?;
-                    *sig = Some(new_sig);
-                } else {
-                    return Err(not_found(
-                        "The given backup snapshot does not have a \
-                                         corresponding signature",
-                    ));
-                }
-            }
+    fn _signature_chain(&self, chain_id: usize) -> io::Result<Arc<Chain>> {
+        // check if there is a cached value
+        if let Some(ref sig) = *lock(&self.signatures[chain_id]) {
+            return Ok(sig.clone());
         }
+        // compute signatures now, without holding the lock
+        let sigchain = self.signature_chain_files(chain_id)?;
+        let new_sig = (self.loader.signatures)(&self.backend, sigchain)?;
+        Ok(cache(&self.signatures[chain_id], new_sig))
+    }
 
-        // need to close previous scope to borrow again
-        // return the cached value
-        Ok(self.signatures[chain_id].borrow())
+    fn _manifest(&self, id: usize, path: &str) -> Result<Arc<Manifest>, manifest::ParseError> {
+        // check if there is a cached value
+        if let Some(ref manifest) = *lock(&self.manifests[id]) {
+            return Ok(manifest.clone());
+        }
+        // compute manifest now, without holding the lock
+        let new_manifest = (self.loader.manifest)(&self.backend, path)?;
+        Ok(cache(&self.manifests[id], new_manifest))
     }
 
-    fn _manifest(
+    fn _rsync_signatures(
+        &self,
+        chain_id: usize,
+        sig_id: usize,
+        paths: &HashSet<&[u8]>,
+    ) -> io::Result<RsyncSigs> {
+        let sigchain = self.signature_chain_files(chain_id)?;
+        (self.loader.rsync_signatures)(&self.backend, sigchain, sig_id, paths)
+    }
+
+    fn _stream_entries(
         &self,
+        chain_id: usize,
+        sig_id: usize,
+        f: &mut dyn FnMut(&signatures::Entry),
+    ) -> io::Result<()> {
+        let sigchain = self.signature_chain_files(chain_id)?;
+        (self.loader.stream_entries)(&self.backend, sigchain, sig_id, f)
+    }
+
+    fn _resolve_sizes(&self, chain_id: usize, sig_id: usize) -> io::Result<()> {
+        // make sure that the signatures are loaded
+        self._signature_chain(chain_id)?;
+        let chain = self
+            .collections
+            .backup_chains()
+            .nth(chain_id)
+            .ok_or_else(|| not_found("The given backup chain does not exist"))?;
+        let mut sig = lock(&self.signatures[chain_id]);
+        let sig = sig.as_mut().and_then(Arc::get_mut).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "The signatures of the chain are in use",
+            )
+        })?;
+        (self.loader.sizes)(&self.backend, sig, sig_id, chain)
+    }
+
+    // the returned futures must not capture the backup, that is not `Sync` in general: only the
+    // loading future (that is `Send` by construction) and the cache slot are captured
+    #[cfg(feature = "async")]
+    fn _signature_chain_async(&self, chain_id: usize) -> BoxFuture<'_, io::Result<Arc<Chain>>> {
+        if let Some(ref sig) = *lock(&self.signatures[chain_id]) {
+            return Box::pin(future::ready(Ok(sig.clone())));
+        }
+        let sigchain = match self.signature_chain_files(chain_id) {
+            Ok(sigchain) => sigchain,
+            Err(e) => return Box::pin(future::ready(Err(e))),
+        };
+        let new_sig = (self.loader.signatures_async)(&self.backend, sigchain);
+        let slot = &self.signatures[chain_id];
+        Box::pin(async move { Ok(cache(slot, new_sig.await?)) })
+    }
+
+    #[cfg(feature = "async")]
+    fn _manifest_async<'a>(
+        &'a self,
         id: usize,
-        path: &str,
-    ) -> Result<Ref<Option<Manifest>>, manifest::ParseError> {
-        {
-            // check if there is a cached value
-            let mut sig = self.manifests[id].borrow_mut();
-            if sig.is_none() {
-                // compute manifest now
-                let mut file = io::BufReader::new(self.backend.open_file(Path::new(path))?);
-                *sig = Some(Manifest::parse(&mut file)?);
-            }
+        path: &'a str,
+    ) -> BoxFuture<'a, Result<Arc<Manifest>, manifest::ParseError>> {
+        if let Some(ref manifest) = *lock(&self.manifests[id]) {
+            return Box::pin(future::ready(Ok(manifest.clone())));
         }
+        let new_manifest = (self.loader.manifest_async)(&self.backend, path);
+        let slot = &self.manifests[id];
+        Box::pin(async move { Ok(cache(slot, new_manifest.await?)) })
+    }
+}
+
+impl<B> Backup<B> {
+    // replaces the collections, keeping the cached signatures of the backup chains still present
+    // and the cached manifests of the sets still present
+    fn replace_collections(&mut self, collections: Collections) {
+        let mut signatures: HashMap<Timespec, Arc<Chain>> = self
+            .collections
+            .backup_chains()
+            .zip(self.signatures.drain(..))
+            .filter_map(|(chain, sig)| {
+                sig.into_inner()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .map(|sig| (chain.start_time(), sig))
+            })
+            .collect();
+        let mut manifests: HashMap<String, Arc<Manifest>> = snapshot_sets(&self.collections)
+            .zip(self.manifests.drain(..))
+            .filter_map(|(set, man)| {
+                man.into_inner()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .map(|man| (set.manifest_path().to_owned(), man))
+            })
+            .collect();
+        self.signatures = collections
+            .backup_chains()
+            .map(|chain| Mutex::new(signatures.remove(&chain.start_time())))
+            .collect();
+        self.manifests = snapshot_sets(&collections)
+            .map(|set| Mutex::new(manifests.remove(set.manifest_path())))
+            .collect();
+        self.collections = collections;
+    }
 
-        // need to close previous scope to borrow again
-        // return the cached value
-        Ok(self.manifests[id].borrow())
+    // the signature chain paired with the given backup chain
+    fn signature_chain_files(&self, chain_id: usize) -> io::Result<&SignatureChain> {
+        let chain = self
+            .collections
+            .backup_chains()
+            .nth(chain_id)
+            .ok_or_else(|| not_found("The given backup chain does not exist"))?;
+        self.collections.signature_chain_for(chain).ok_or_else(|| {
+            not_found(&format!(
+                "The backup chain starting at {} has no signatures",
+                timefmt::format_rfc3339(chain.start_time())
+            ))
+        })
     }
 }
 
+// the sets of the given collections, in the order of the snapshots
+fn snapshot_sets(collections: &Collections) -> impl Iterator<Item = &BackupSet> {
+    collections
+        .backup_chains()
+        .flat_map(|chain| Some(chain.full_set()).into_iter().chain(chain.inc_sets()))
+}
+
+fn signature_index(sig_chain: Option<&SignatureChain>, set: &BackupSet) -> Option<usize> {
+    sig_chain.and_then(|sig| sig.signature_index(set.end_time()))
+}
+
+fn load_signatures<B: Backend>(backend: &B, sigchain: &SignatureChain) -> io::Result<Chain> {
+    Chain::from_sigchain(sigchain, backend)
+}
+
+fn load_rsync_signatures<B: Backend>(
+    backend: &B,
+    sigchain: &SignatureChain,
+    sig_id: usize,
+    paths: &HashSet<&[u8]>,
+) -> io::Result<RsyncSigs> {
+    let sigfiles = Some(sigchain.full_signature())
+        .into_iter()
+        .chain(sigchain.inc_signatures().take(sig_id));
+    signatures::read_rsync_signatures(backend, sigfiles, paths)
+}
+
+fn load_manifest<B: Backend>(backend: &B, path: &str) -> Result<Manifest, manifest::ParseError> {
+    let mut file = io::BufReader::new(backend.open_file(Path::new(path))?);
+    Manifest::parse(&mut file)
+}
+
+#[cfg(feature = "async")]
+async fn load_manifest_async<B: backend::AsyncBackend>(
+    backend: &B,
+    path: &str,
+) -> Result<Manifest, manifest::ParseError> {
+    let mut buf = Vec::new();
+    let mut file = backend.open_file(Path::new(path)).await?;
+    file.read_to_end(&mut buf).await?;
+    Manifest::parse(&mut &buf[..])
+}
+
+// locks a cache slot: a panic while holding the lock cannot leave the slot inconsistent
+fn lock<T>(slot: &Mutex<T>) -> MutexGuard<'_, T> {
+    slot.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// stores a loaded value into a cache slot and returns it, unless another thread has loaded the
+// same value in the meantime
+fn cache<T>(slot: &Mutex<Option<Arc<T>>>, value: T) -> Arc<T> {
+    lock(slot).get_or_insert_with(|| Arc::new(value)).clone()
+}
+
 fn not_found(msg: &str) -> io::Error {
     io::Error::new(io::ErrorKind::NotFound, msg)
 }
@@ -395,15 +932,25 @@ fn not_found(msg: &str) -> io::Error {
 mod test {
     use super::*;
     use backend::local::LocalBackend;
+    use backend::memory::{fixture, MemoryBackend};
     use collections::{BackupSet, Collections};
     use manifest::Manifest;
     use rawpath::RawPath;
     use signatures::{Chain, Entry};
     use timefmt::parse_time_str;
 
-    use std::fs::File;
-    use std::io::BufReader;
-    use std::path::Path;
+    #[cfg(feature = "async")]
+    use std::future::Future;
+    #[cfg(feature = "async")]
+    use std::mem;
+    #[cfg(feature = "async")]
+    use std::path::PathBuf;
+    #[cfg(feature = "async")]
+    use std::pin::Pin;
+    #[cfg(feature = "async")]
+    use std::sync::atomic::{self, AtomicBool};
+    #[cfg(feature = "async")]
+    use std::task::{Context, Poll};
     use time::Timespec;
 
     #[derive(Debug, Eq, PartialEq)]
@@ -477,7 +1024,7 @@ mod test {
     }
 
     fn single_vol_signature_chain() -> Chain {
-        let backend = LocalBackend::new("tests/backups/single_vol");
+        let backend = fixture("single_vol");
         let filenames = backend.file_names().unwrap();
         let coll = Collections::from_filenames(filenames);
         Chain::from_sigchain(coll.signature_chains().next().unwrap(), &backend).unwrap()
@@ -511,7 +1058,7 @@ mod test {
 
     #[test]
     fn same_collections_single_vol() {
-        let backend = LocalBackend::new("tests/backups/single_vol");
+        let backend = fixture("single_vol");
         let filenames = backend.file_names().unwrap();
         let coll = Collections::from_filenames(filenames);
         let backup = Backup::new(backend).unwrap();
@@ -523,7 +1070,7 @@ mod test {
 
     #[test]
     fn same_collections_multi_chain() {
-        let backend = LocalBackend::new("tests/backups/multi_chain");
+        let backend = fixture("multi_chain");
         let filenames = backend.file_names().unwrap();
         let coll = Collections::from_filenames(filenames);
         let backup = Backup::new(backend).unwrap();
@@ -538,15 +1085,213 @@ mod test {
         let sigchain = single_vol_signature_chain();
         let expected = from_sigchain(&sigchain);
 
-        let backend = LocalBackend::new("tests/backups/single_vol");
-        let backup = Backup::new(backend).unwrap();
+        let backup = Backup::new(fixture("single_vol")).unwrap();
         let actual = from_backup(&backup);
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn stream_same_files() {
+        for name in &["single_vol", "multi_chain"] {
+            let backup = Backup::new(fixture(name)).unwrap();
+            let expected = from_backup(&backup);
+            let actual: Vec<Vec<_>> = backup
+                .snapshots()
+                .unwrap()
+                .into_iter()
+                .map(|snapshot| {
+                    let mut files = Vec::new();
+                    snapshot
+                        .stream_entries(|f| files.push(EntryTest::from_entry(f)))
+                        .unwrap();
+                    files
+                })
+                .collect();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn memory_backend_files() {
+        // the embedded test backups are read like the ones on disk
+        for name in &["single_vol", "multi_chain"] {
+            let dir = format!("tests/backups/{}", name);
+            let expected = from_backup(&Backup::new(LocalBackend::new(dir)).unwrap());
+            let actual = from_backup(&Backup::new(fixture(name)).unwrap());
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn unpaired_signatures() {
+        // the signatures of the first chain and of the last set are missing
+        let memory = fixture("multi_chain");
+        assert!(memory.remove("duplicity-full-signatures.20160108T223144Z.sigtar.gz"));
+        assert!(memory
+            .remove("duplicity-new-signatures.20160108T223209Z.to.20160108T223217Z.sigtar.gz"));
+        let expected = from_backup(&Backup::new(fixture("multi_chain")).unwrap());
+        let backup = Backup::new(memory).unwrap();
+        let snapshots: Vec<_> = backup.snapshots().unwrap().into_iter().collect();
+        assert_eq!(snapshots.len(), 4);
+
+        // the second chain is still paired with its signatures
+        let actual: Vec<_> = snapshots[2]
+            .entries()
+            .unwrap()
+            .as_signature()
+            .map(|f| EntryTest::from_entry(&f))
+            .collect();
+        assert_eq!(actual, expected[2]);
+
+        let check_error = |snapshot: &Snapshot, msg: String| {
+            let err = snapshot.entries().err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+            assert_eq!(err.to_string(), msg);
+        };
+        let chain_start = snapshots[0].time();
+        for snapshot in &snapshots[..2] {
+            check_error(
+                snapshot,
+                format!(
+                    "The backup chain starting at {} has no signatures",
+                    timefmt::format_rfc3339(chain_start)
+                ),
+            );
+        }
+        check_error(
+            &snapshots[3],
+            format!(
+                "The backup set ending at {} has no signature",
+                timefmt::format_rfc3339(snapshots[3].time())
+            ),
+        );
+    }
+
+    #[test]
+    fn refresh() {
+        // the last incremental set is added after the backup has been opened
+        let last_set = [
+            "duplicity-inc.20160108T223209Z.to.20160108T223217Z.manifest",
+            "duplicity-inc.20160108T223209Z.to.20160108T223217Z.vol1.difftar.gz",
+            "duplicity-new-signatures.20160108T223209Z.to.20160108T223217Z.sigtar.gz",
+        ];
+        let all_files = fixture("multi_chain");
+        let memory = fixture("multi_chain");
+        for name in &last_set {
+            memory.remove(name);
+        }
+        let expected = from_backup(&Backup::new(fixture("multi_chain")).unwrap());
+        let mut backup = Backup::new(memory).unwrap();
+        {
+            let snapshots: Vec<_> = backup.snapshots().unwrap().into_iter().collect();
+            assert_eq!(snapshots.len(), 3);
+            snapshots[0].manifest().unwrap();
+            snapshots[2].entries().unwrap();
+        }
+
+        // the cached signatures and manifests must not be read again
+        let backend = backup.backend();
+        backend.insert(
+            "duplicity-full-signatures.20160108T223209Z.sigtar.gz",
+            Vec::new(),
+        );
+        backend.insert("duplicity-full.20160108T223144Z.manifest", Vec::new());
+        for name in &last_set {
+            backend.insert(name, all_files.get(name).unwrap());
+        }
+        backup.refresh().unwrap();
+        assert_eq!(from_backup(&backup), expected);
+        let snapshots: Vec<_> = backup.snapshots().unwrap().into_iter().collect();
+        assert!(snapshots[0].manifest().is_ok());
+        assert!(snapshots[1].manifest().is_ok());
+        drop(snapshots);
+
+        // the signatures of the second chain are not valid anymore, and are read again
+        backup.backend().remove(last_set[2]);
+        backup.refresh().unwrap();
+        let snapshots: Vec<_> = backup.snapshots().unwrap().into_iter().collect();
+        assert_eq!(snapshots.len(), 4);
+        assert!(snapshots[2]
+            .entries()
+            .map(|e| e.as_signature().count() == 0)
+            .unwrap_or(true));
+    }
+
+    #[test]
+    fn prefixed_files() {
+        // the same backup, with and without prefixes, in the same location
+        let make_backend = || {
+            let memory = fixture("single_vol");
+            for name in memory.file_names().unwrap() {
+                let name = name.to_str().unwrap();
+                let prefixed = if name.contains("signatures") {
+                    format!("hostA_sig_{}", name)
+                } else {
+                    format!("hostA_{}", name)
+                };
+                memory.insert(prefixed, memory.get(name).unwrap());
+            }
+            memory
+        };
+        let expected = from_backup(&Backup::new(fixture("single_vol")).unwrap());
+        let naming = FileNaming::new().prefix("hostA_").signature_prefix("sig_");
+        let backup = Backup::with_naming(make_backend(), &naming).unwrap();
+        assert_eq!(from_backup(&backup), expected);
+        assert_eq!(from_backup(&Backup::new(make_backend()).unwrap()), expected);
+
+        // the signatures are found under a different prefix
+        let backups = Collections::discover(make_backend().file_infos().unwrap());
+        let prefixes: Vec<_> = backups.iter().map(|(n, _)| n.file_prefix()).collect();
+        assert_eq!(prefixes, vec!["", "hostA_", "hostA_sig_"]);
+        assert_eq!(backups[1].1.backup_chains().count(), 1);
+        assert_eq!(backups[1].1.signature_chains().count(), 0);
+        assert_eq!(backups[2].1.signature_chains().count(), 1);
+    }
+
+    #[test]
+    fn short_filenames() {
+        use regex::Regex;
+        use timefmt::parse_time_str;
+
+        fn base36(time: &str) -> String {
+            let mut secs = parse_time_str(&time.to_ascii_lowercase()).unwrap().sec as u64;
+            let mut result = Vec::new();
+            while secs > 0 {
+                result.push(b"0123456789abcdefghijklmnopqrstuvwxyz"[(secs % 36) as usize]);
+                secs /= 36;
+            }
+            result.reverse();
+            String::from_utf8(result).unwrap()
+        }
+
+        // the same backup, renamed as with `--short-filenames`
+        let time_re = Regex::new("[0-9]{8}T[0-9]{6}Z").unwrap();
+        let original = fixture("single_vol");
+        let memory = MemoryBackend::new();
+        for name in original.file_names().unwrap() {
+            let name = name.to_str().unwrap();
+            let short = time_re
+                .replace_all(name, |c: &regex::Captures| base36(&c[0]))
+                .replace("duplicity-full-signatures.", "dfs.")
+                .replace("duplicity-new-signatures.", "dns.")
+                .replace("duplicity-full.", "df.")
+                .replace("duplicity-inc.", "di.")
+                .replace(".to.", ".")
+                .replace(".vol1.difftar.gz", ".1.dt.z")
+                .replace(".sigtar.gz", ".st.z")
+                .replace(".manifest", ".m");
+            memory.insert(short, original.get(name).unwrap());
+        }
+        assert!(memory.contains("df.nq3pux.1.dt.z"));
+        let expected = from_backup(&Backup::new(original).unwrap());
+        let backup = Backup::new(memory).unwrap();
+        assert_eq!(backup.snapshots().unwrap().into_iter().count(), 3);
+        assert_eq!(from_backup(&backup), expected);
+    }
+
     #[test]
     fn multi_chain_files() {
-        let backend = LocalBackend::new("tests/backups/multi_chain");
+        let backend = fixture("multi_chain");
         let backup = Backup::new(backend).unwrap();
         let actual = from_backup(&backup);
         let expected = vec![
@@ -575,9 +1320,243 @@ mod test {
     }
 
     #[test]
-    fn multi_chain_manifests() {
-        let backend = LocalBackend::new("tests/backups/multi_chain");
+    fn multi_chain_sizes() {
+        let backend = fixture("multi_chain");
         let backup = Backup::new(backend).unwrap();
+        for snapshot in backup.snapshots().unwrap() {
+            let entries = snapshot.entries().unwrap();
+            // the signatures cannot be updated while in use
+            assert!(snapshot.entries_with_sizes().is_err());
+            drop(entries);
+            let entries = snapshot.entries_with_sizes().unwrap();
+            for entry in entries.as_signature() {
+                let size = entry.size().unwrap();
+                assert_eq!(entry.size_hint(), Some((size, size)));
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_same_files() {
+        use futures::executor::block_on;
+
+        let backup = Backup::new(fixture("single_vol")).unwrap();
+        let expected = from_backup(&backup);
+
+        let backup = block_on(Backup::new_async(fixture("single_vol"))).unwrap();
+        let actual = backup
+            .snapshots()
+            .unwrap()
+            .into_iter()
+            .map(|s| {
+                block_on(s.entries_async())
+                    .unwrap()
+                    .as_signature()
+                    .map(|f| EntryTest::from_entry(&f))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+
+        // synchronous accessors block on the asynchronous backend
+        let backup = block_on(Backup::new_async(fixture("single_vol"))).unwrap();
+        assert_eq!(from_backup(&backup), expected);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_send() {
+        use futures::executor::block_on;
+        use std::thread;
+
+        fn assert_send<T: Send>(value: T) -> T {
+            value
+        }
+
+        let backend = fixture("multi_chain");
+        let mut backup = block_on(assert_send(Backup::new_async(backend))).unwrap();
+        block_on(assert_send(backup.refresh_async())).unwrap();
+        for snapshot in backup.snapshots().unwrap() {
+            let entries = assert_send(snapshot.entries_async());
+            let manifest = assert_send(snapshot.manifest_async());
+            // the futures can be completed on another thread
+            let (entries, manifest) = thread::scope(|scope| {
+                scope
+                    .spawn(move || (block_on(entries).unwrap(), block_on(manifest).unwrap()))
+                    .join()
+                    .unwrap()
+            });
+            assert_eq!(entries.as_signature().count(), 2);
+            assert_eq!(manifest.hostname(), "dellxps");
+        }
+    }
+
+    // the state of an executor running on a single thread, which completes the operations of
+    // the backends driven by it
+    #[cfg(feature = "async")]
+    #[derive(Default)]
+    struct Reactor(Mutex<Vec<Arc<AtomicBool>>>);
+
+    // waits for the next turn of a reactor
+    #[cfg(feature = "async")]
+    struct Wait {
+        reactor: Arc<Reactor>,
+        ready: Option<Arc<AtomicBool>>,
+    }
+
+    // an asynchronous backend driven by a reactor, like the I/O of a single threaded runtime
+    #[cfg(feature = "async")]
+    struct DrivenBackend {
+        inner: MemoryBackend,
+        reactor: Arc<Reactor>,
+    }
+
+    #[cfg(feature = "async")]
+    struct DrivenFile<R> {
+        inner: R,
+        reactor: Arc<Reactor>,
+        wait: Option<Wait>,
+    }
+
+    #[cfg(feature = "async")]
+    impl Reactor {
+        fn wait(self: &Arc<Self>) -> Wait {
+            Wait {
+                reactor: self.clone(),
+                ready: None,
+            }
+        }
+
+        // completes the pending operations, returning false if there were none
+        fn turn(&self) -> bool {
+            let pending = mem::take(&mut *self.0.lock().unwrap());
+            pending
+                .iter()
+                .for_each(|r| r.store(true, atomic::Ordering::SeqCst));
+            !pending.is_empty()
+        }
+
+        // runs the future on the current thread, as a single threaded executor
+        fn run<F: Future>(&self, future: F) -> F::Output {
+            let mut future = Box::pin(future);
+            let waker = futures::task::noop_waker();
+            let mut cx = Context::from_waker(&waker);
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+                assert!(
+                    self.turn(),
+                    "the future waits for something else than the executor"
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl Future for Wait {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()> {
+            match self.ready {
+                Some(ref ready) if ready.load(atomic::Ordering::SeqCst) => Poll::Ready(()),
+                Some(_) => Poll::Pending,
+                None => {
+                    let ready = Arc::new(AtomicBool::new(false));
+                    self.reactor.0.lock().unwrap().push(ready.clone());
+                    self.ready = Some(ready);
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl backend::AsyncBackend for DrivenBackend {
+        type FileName = PathBuf;
+        type FileNameIter = Vec<PathBuf>;
+        type FileStream = DrivenFile<<MemoryBackend as backend::AsyncBackend>::FileStream>;
+
+        fn file_names(&self) -> BoxFuture<'_, io::Result<Self::FileNameIter>> {
+            let wait = self.reactor.wait();
+            Box::pin(async move {
+                wait.await;
+                backend::AsyncBackend::file_names(&self.inner).await
+            })
+        }
+
+        fn open_file<'a>(&'a self, name: &'a Path) -> BoxFuture<'a, io::Result<Self::FileStream>> {
+            let wait = self.reactor.wait();
+            Box::pin(async move {
+                wait.await;
+                let inner = backend::AsyncBackend::open_file(&self.inner, name).await?;
+                Ok(DrivenFile {
+                    inner,
+                    reactor: self.reactor.clone(),
+                    wait: None,
+                })
+            })
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl<R: futures::io::AsyncRead + Unpin> futures::io::AsyncRead for DrivenFile<R> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let reactor = &this.reactor;
+            let wait = this.wait.get_or_insert_with(|| reactor.wait());
+            if Pin::new(wait).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.wait = None;
+            Pin::new(&mut this.inner).poll_read(cx, buf)
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_single_thread() {
+        // the asynchronous functions never block the executor thread, otherwise the backend
+        // would never complete
+        let reactor = Arc::new(Reactor::default());
+        let backend = DrivenBackend {
+            inner: fixture("multi_chain"),
+            reactor: reactor.clone(),
+        };
+        let mut backup = reactor.run(Backup::new_async(backend)).unwrap();
+        reactor.run(backup.refresh_async()).unwrap();
+        let mut count = 0;
+        for snapshot in backup.snapshots().unwrap() {
+            let entries = reactor.run(snapshot.entries_async()).unwrap();
+            assert_eq!(entries.as_signature().count(), 2);
+            let manifest = reactor.run(snapshot.manifest_async()).unwrap();
+            assert_eq!(manifest.hostname(), "dellxps");
+            count += 1;
+        }
+        assert_eq!(count, 4);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_manifests() {
+        use futures::executor::block_on;
+
+        let backup = block_on(Backup::new_async(fixture("multi_chain"))).unwrap();
+        for snapshot in backup.snapshots().unwrap() {
+            let manifest = block_on(snapshot.manifest_async()).unwrap();
+            assert_eq!(manifest.hostname(), snapshot.manifest().unwrap().hostname());
+        }
+    }
+
+    #[test]
+    fn multi_chain_manifests() {
+        let backend = fixture("multi_chain");
+        let backup = Backup::new(fixture("multi_chain")).unwrap();
         let actual = backup
             .snapshots()
             .unwrap()
@@ -590,10 +1569,8 @@ mod test {
             "duplicity-inc.20160108T223209Z.to.20160108T223217Z.manifest",
         ];
         let expected = names.iter().map(|name| {
-            let mut path = Path::new("tests/backups/multi_chain").to_owned();
-            path.push(name);
-            let mut file = BufReader::new(File::open(path).unwrap());
-            Manifest::parse(&mut file).unwrap()
+            let content = backend.get(name).unwrap();
+            Manifest::parse(&mut &content[..]).unwrap()
         });
         for (e, a) in expected.zip(actual) {
             assert_eq!(e, *a);
@@ -0,0 +1,472 @@
+//! Machine readable listings of snapshots and collections.
+//!
+//! In addition to the `ls -l` style of `SnapshotEntriesDisplay`, and the `Display` of
+//! `Collections`, the listings can be written in formats easily processed by other programs:
+//! JSON lines, CSV and NUL separated paths. Records are written as soon as they are provided,
+//! without buffering the whole listing.
+//!
+//! Entries have the following fields: `path`, `type`, `mode` (octal), `uid`, `gid`, `user`,
+//! `group`, `size_min`, `size_max`, `mtime` (RFC 3339, UTC) and `link`. Backup sets have the
+//! fields `chain` (the index of the chain, unknown for orphaned sets), `type`, `start_time`,
+//! `end_time`, `volumes`, `size`, `compressed`, `encrypted` and `manifest`. Unknown values are
+//! `null` in JSON and empty in CSV.
+//!
+//! Paths are written as JSON strings when they are valid UTF-8, and as arrays of bytes otherwise.
+//! CSV does not allow such an encoding, so invalid sequences are replaced. NUL separated
+//! listings contain only the raw paths: entry paths for snapshots, and file names for
+//! collections.
+//!
+//! # Example
+//!
+//! ```
+//! use ruplicity::Backup;
+//! use ruplicity::backend::local::LocalBackend;
+//! use ruplicity::listing::{EntryWriter, Format};
+//!
+//! let backend = LocalBackend::new("tests/backups/single_vol");
+//! let backup = Backup::new(backend).unwrap();
+//! let snapshots = backup.snapshots().unwrap();
+//! let snapshot = snapshots.into_iter().next().unwrap();
+//! let entries = snapshot.entries().unwrap();
+//! let mut writer = EntryWriter::new(Vec::new(), Format::JsonLines);
+//! writer.write_all(entries.as_signature()).unwrap();
+//! let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+//! assert!(out.lines().all(|l| l.starts_with(r#"{"path":"#)));
+//! ```
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+use std::str::{self, FromStr};
+
+use crate::collections::{BackupSet, Collections};
+use crate::signatures::{Entry, EntryType};
+use crate::timefmt::format_rfc3339;
+
+/// An output format for listings.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// A JSON object per line.
+    JsonLines,
+    /// Comma separated values, with a header line.
+    Csv,
+    /// Paths terminated by a NUL character, like `find -print0` does.
+    Nul,
+}
+
+/// The error returned when parsing an unknown format name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnknownFormat(String);
+
+/// Writes snapshot entries in a machine readable format.
+#[derive(Debug)]
+pub struct EntryWriter<W> {
+    out: W,
+    format: Format,
+    header: bool,
+}
+
+/// Writes the backup sets of collections in a machine readable format.
+#[derive(Debug)]
+pub struct SetWriter<W> {
+    out: W,
+    format: Format,
+    header: bool,
+}
+
+// a field value of a record
+enum Value<'a> {
+    Str(&'a str),
+    Path(&'a [u8]),
+    Uint(u64),
+    Bool(bool),
+    Null,
+}
+
+const ENTRY_FIELDS: &[&str] = &[
+    "path", "type", "mode", "uid", "gid", "user", "group", "size_min", "size_max", "mtime", "link",
+];
+
+const SET_FIELDS: &[&str] = &[
+    "chain",
+    "type",
+    "start_time",
+    "end_time",
+    "volumes",
+    "size",
+    "compressed",
+    "encrypted",
+    "manifest",
+];
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+
+    /// Parses a format name: `jsonl` (or `json`), `csv` or `nul`.
+    fn from_str(s: &str) -> Result<Self, UnknownFormat> {
+        match s {
+            "jsonl" | "json" => Ok(Format::JsonLines),
+            "csv" => Ok(Format::Csv),
+            "nul" => Ok(Format::Nul),
+            _ => Err(UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+impl Display for UnknownFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "unknown listing format '{}'", self.0)
+    }
+}
+
+impl Error for UnknownFormat {}
+
+impl<W: Write> EntryWriter<W> {
+    /// Creates a writer for the given output and format.
+    pub fn new(out: W, format: Format) -> Self {
+        EntryWriter {
+            out,
+            format,
+            header: false,
+        }
+    }
+
+    /// Writes an entry.
+    pub fn write_entry(&mut self, entry: &Entry<'_>) -> io::Result<()> {
+        if self.format == Format::Nul {
+            self.out.write_all(entry.path_bytes())?;
+            return self.out.write_all(b"\0");
+        }
+        write_header(&mut self.out, self.format, &mut self.header, ENTRY_FIELDS)?;
+        let mode = entry.mode().map(|m| format!("{:04o}", m & 0o7777));
+        let mtime = format_rfc3339(entry.mtime());
+        let size = entry.size_hint();
+        let link = entry.linked_path().map(|p| p.to_string_lossy());
+        let values = [
+            Value::Path(entry.path_bytes()),
+            Value::Str(type_name(entry.entry_type())),
+            mode.as_deref().map_or(Value::Null, Value::Str),
+            entry.userid().map_or(Value::Null, Value::Uint),
+            entry.groupid().map_or(Value::Null, Value::Uint),
+            entry.username().map_or(Value::Null, Value::Str),
+            entry.groupname().map_or(Value::Null, Value::Str),
+            size.map_or(Value::Null, |s| Value::Uint(s.0 as u64)),
+            size.map_or(Value::Null, |s| Value::Uint(s.1 as u64)),
+            Value::Str(&mtime),
+            link.as_deref().map_or(Value::Null, Value::Str),
+        ];
+        write_record(&mut self.out, self.format, ENTRY_FIELDS, &values)
+    }
+
+    /// Writes all the given entries.
+    pub fn write_all<'a, I>(&mut self, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = Entry<'a>>,
+    {
+        for entry in entries {
+            self.write_entry(&entry)?;
+        }
+        Ok(())
+    }
+
+    /// Completes the listing, and returns the underlying output.
+    ///
+    /// An empty CSV listing gets its header line here.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format != Format::Nul {
+            write_header(&mut self.out, self.format, &mut self.header, ENTRY_FIELDS)?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> SetWriter<W> {
+    /// Creates a writer for the given output and format.
+    pub fn new(out: W, format: Format) -> Self {
+        SetWriter {
+            out,
+            format,
+            header: false,
+        }
+    }
+
+    /// Writes a backup set, belonging to the given chain.
+    ///
+    /// The chain is `None` for orphaned sets.
+    pub fn write_set(&mut self, chain: Option<usize>, set: &BackupSet) -> io::Result<()> {
+        if self.format == Format::Nul {
+            for name in set.file_names() {
+                self.out.write_all(name.as_bytes())?;
+                self.out.write_all(b"\0")?;
+            }
+            return Ok(());
+        }
+        write_header(&mut self.out, self.format, &mut self.header, SET_FIELDS)?;
+        let start_time = format_rfc3339(set.start_time());
+        let end_time = format_rfc3339(set.end_time());
+        let num_volumes = (0..set.num_volumes())
+            .filter(|&i| set.volume_path(i).is_some())
+            .count();
+        let values = [
+            chain.map_or(Value::Null, |c| Value::Uint(c as u64)),
+            Value::Str(if set.is_full() { "full" } else { "inc" }),
+            Value::Str(&start_time),
+            Value::Str(&end_time),
+            Value::Uint(num_volumes as u64),
+            set.total_size().map_or(Value::Null, Value::Uint),
+            Value::Bool(set.is_compressed()),
+            Value::Bool(set.is_encrypted()),
+            Some(set.manifest_path())
+                .filter(|m| !m.is_empty())
+                .map_or(Value::Null, Value::Str),
+        ];
+        write_record(&mut self.out, self.format, SET_FIELDS, &values)
+    }
+
+    /// Writes all the backup sets of the given collections.
+    ///
+    /// The sets of the backup chains come first, in order, followed by the orphaned ones.
+    pub fn write_collections(&mut self, collections: &Collections) -> io::Result<()> {
+        for (i, chain) in collections.backup_chains().enumerate() {
+            self.write_set(Some(i), chain.full_set())?;
+            for set in chain.inc_sets() {
+                self.write_set(Some(i), set)?;
+            }
+        }
+        for set in collections.orphaned_sets() {
+            self.write_set(None, set)?;
+        }
+        Ok(())
+    }
+
+    /// Completes the listing, and returns the underlying output.
+    ///
+    /// An empty CSV listing gets its header line here.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format != Format::Nul {
+            write_header(&mut self.out, self.format, &mut self.header, SET_FIELDS)?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn type_name(entry_type: EntryType) -> &'static str {
+    match entry_type {
+        EntryType::File => "file",
+        EntryType::Dir => "dir",
+        EntryType::HardLink => "hardlink",
+        EntryType::SymLink => "symlink",
+        EntryType::Fifo => "fifo",
+        EntryType::Unknown(_) => "unknown",
+    }
+}
+
+// writes the CSV header, if not already written
+fn write_header<W: Write>(
+    out: &mut W,
+    format: Format,
+    written: &mut bool,
+    fields: &[&str],
+) -> io::Result<()> {
+    if format == Format::Csv && !*written {
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    *written = true;
+    Ok(())
+}
+
+fn write_record<W: Write>(
+    out: &mut W,
+    format: Format,
+    fields: &[&str],
+    values: &[Value<'_>],
+) -> io::Result<()> {
+    for (i, (field, value)) in fields.iter().zip(values).enumerate() {
+        match format {
+            Format::JsonLines => {
+                out.write_all(if i == 0 { b"{" } else { b"," })?;
+                write_json_str(out, field)?;
+                out.write_all(b":")?;
+                write_json_value(out, value)?;
+            }
+            _ => {
+                if i > 0 {
+                    out.write_all(b",")?;
+                }
+                write_csv_value(out, value)?;
+            }
+        }
+    }
+    if format == Format::JsonLines {
+        out.write_all(b"}")?;
+    }
+    out.write_all(b"\n")
+}
+
+fn write_json_value<W: Write>(out: &mut W, value: &Value<'_>) -> io::Result<()> {
+    match *value {
+        Value::Str(s) => write_json_str(out, s),
+        Value::Path(p) => match str::from_utf8(p) {
+            Ok(s) => write_json_str(out, s),
+            Err(_) => {
+                let bytes: Vec<_> = p.iter().map(u8::to_string).collect();
+                write!(out, "[{}]", bytes.join(","))
+            }
+        },
+        Value::Uint(n) => write!(out, "{}", n),
+        Value::Bool(b) => write!(out, "{}", b),
+        Value::Null => out.write_all(b"null"),
+    }
+}
+
+fn write_json_str<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+fn write_csv_value<W: Write>(out: &mut W, value: &Value<'_>) -> io::Result<()> {
+    let s = match *value {
+        Value::Str(s) => s.into(),
+        Value::Path(p) => String::from_utf8_lossy(p),
+        Value::Uint(n) => n.to_string().into(),
+        Value::Bool(b) => b.to_string().into(),
+        Value::Null => return Ok(()),
+    };
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        write!(out, "\"{}\"", s.replace('"', "\"\""))
+    } else {
+        out.write_all(s.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::memory::fixture;
+    use crate::backend::Backend;
+    use crate::signatures::Chain;
+
+    fn single_vol() -> (Collections, Chain) {
+        let backend = fixture("single_vol");
+        let coll = Collections::from_file_infos(backend.file_infos().unwrap());
+        let chain =
+            Chain::from_sigchain(coll.signature_chains().next().unwrap(), &backend).unwrap();
+        (coll, chain)
+    }
+
+    fn entries(format: Format) -> Vec<u8> {
+        let (_, chain) = single_vol();
+        let mut writer = EntryWriter::new(Vec::new(), format);
+        writer
+            .write_all(chain.snapshots().next().unwrap().files())
+            .unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn entries_json() {
+        let out = String::from_utf8(entries(Format::JsonLines)).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 17);
+        assert_eq!(
+            lines[0],
+            r#"{"path":"","type":"dir","mode":"0755","uid":1000,"gid":1000,"user":"michele","group":"michele","size_min":0,"size_max":0,"mtime":"2002-09-28T18:30:59Z","link":null}"#
+        );
+        assert!(lines.iter().any(|l| l.contains(r#""type":"symlink""#)
+            && l.contains(r#""link":"#)
+            && !l.contains(r#""link":null"#)));
+        // a non UTF-8 path
+        assert!(lines[16].starts_with(r#"{"path":[216,171,177,"#));
+    }
+
+    #[test]
+    fn entries_csv() {
+        let out = String::from_utf8(entries(Format::Csv)).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "path,type,mode,uid,gid,user,group,size_min,size_max,mtime,link"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            ",dir,0755,1000,1000,michele,michele,0,0,2002-09-28T18:30:59Z,"
+        );
+        assert_eq!(lines.count(), 16);
+        // empty listings have the header only
+        let writer = EntryWriter::new(Vec::new(), Format::Csv);
+        assert_eq!(
+            writer.finish().unwrap(),
+            format!("{}\n", ENTRY_FIELDS.join(",")).into_bytes()
+        );
+    }
+
+    #[test]
+    fn entries_nul() {
+        let out = entries(Format::Nul);
+        let paths: Vec<_> = out.split(|&b| b == 0).collect();
+        // the last item is empty, since every path is terminated
+        assert_eq!(paths.len(), 18);
+        assert_eq!(paths[0], b"");
+        assert_eq!(paths[1], b"changeable_permission");
+        assert_eq!(paths[17], b"");
+    }
+
+    #[test]
+    fn collections() {
+        let (coll, _) = single_vol();
+        let mut writer = SetWriter::new(Vec::new(), Format::JsonLines);
+        writer.write_collections(&coll).unwrap();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(
+            r#"{"chain":0,"type":"full","start_time":"2015-06-17T18:25:45Z","end_time":"2015-06-17T18:25:45Z","volumes":1,"size":"#
+        ));
+        assert!(lines[2].ends_with(
+            r#""compressed":true,"encrypted":false,"manifest":"duplicity-inc.20150617T182629Z.to.20150617T182650Z.manifest"}"#
+        ));
+
+        let mut writer = SetWriter::new(Vec::new(), Format::Nul);
+        writer.write_collections(&coll).unwrap();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(out.split_terminator('\0').count(), 6);
+
+        let mut writer = SetWriter::new(Vec::new(), Format::Csv);
+        writer.write_collections(&coll).unwrap();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(out.lines().count(), 4);
+    }
+
+    #[test]
+    fn escaping() {
+        let mut out = Vec::new();
+        write_json_value(&mut out, &Value::Str("a\"b\\c\n\u{1}")).unwrap();
+        write_json_value(&mut out, &Value::Path(b"a\xff")).unwrap();
+        write_csv_value(&mut out, &Value::Str("a,\"b\"")).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#""a\"b\\c\n\u0001"[97,255]"a,""b""""#
+        );
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!("jsonl".parse(), Ok(Format::JsonLines));
+        assert_eq!("csv".parse(), Ok(Format::Csv));
+        assert_eq!("nul".parse(), Ok(Format::Nul));
+        assert!("xml".parse::<Format>().is_err());
+    }
+}
@@ -1,33 +1,94 @@
 //! Operations on manifest files.
+//!
+//! Manifests can be parsed from the files stored in a backup, or built and written in the
+//! duplicity format, for example to repair a damaged manifest.
+//!
+//! # Example
+//!
+//! ```
+//! use ruplicity::manifest::{ChangeKind, Manifest, ManifestBuilder, Volume};
+//!
+//! let manifest = ManifestBuilder::new()
+//!     .hostname("myhost")
+//!     .local_dir("/home/me")
+//!     .file_change(ChangeKind::New, "my file")
+//!     .volume(Volume::new(
+//!         (b".".to_vec(), None),
+//!         (b"my file".to_vec(), None),
+//!         "SHA1".to_owned(),
+//!         vec![0xab; 20],
+//!     ))
+//!     .build();
+//! let mut buf = Vec::new();
+//! manifest.write_to(&mut buf).unwrap();
+//! assert_eq!(Manifest::parse(&mut &buf[..]).unwrap(), manifest);
+//! ```
 
 use std::cmp::Ordering;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Write};
 use std::num::ParseIntError;
 use std::path::Path;
 use std::str::{self, FromStr, Utf8Error};
 use std::usize;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use crate::rawpath::RawPath;
 
 /// Manifest file info.
 #[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Manifest {
     hostname: String,
     local_dir: RawPath,
     volumes: Vec<Volume>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    file_list: Vec<FileChange>,
 }
 
 /// Volume info.
 #[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Volume {
     start_path: PathBlock,
     end_path: PathBlock,
     hash_type: String,
+    #[cfg_attr(feature = "serde", serde(with = "hex_serde"))]
     hash: Vec<u8>,
 }
 
+/// A builder for manifests.
+///
+/// Volumes are numbered in the order they are added, starting from one.
+#[derive(Debug)]
+pub struct ManifestBuilder {
+    manifest: Manifest,
+}
+
+/// A path listed in the `Filelist` section of a manifest.
+#[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileChange {
+    path: RawPath,
+    kind: ChangeKind,
+}
+
+/// How a path changed in a backup set, with respect to the previous one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ChangeKind {
+    /// The path was not present in the previous set.
+    New,
+    /// The path was present in the previous set, but it has been modified.
+    Changed,
+    /// The path has been removed.
+    Deleted,
+}
+
 /// Enumeration of parsing errors.
 #[derive(Debug)]
 pub enum ParseError {
@@ -43,6 +104,12 @@ pub enum ParseError {
     MissingPath,
     /// The list of volumes is not sorted.
     OutOfOrderVolume(usize),
+    /// An unknown change kind was found in the `Filelist` section.
+    UnknownKeyword(String),
+    /// The `Filelist` section has a different number of paths than declared.
+    ///
+    /// The declared and the actual number of paths are given.
+    FileListLength(usize, usize),
     /// An integer parsing error.
     ParseInt(ParseIntError),
     /// An error parsing an UTF-8 string.
@@ -50,6 +117,7 @@ pub enum ParseError {
 }
 
 #[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct PathBlock {
     path: RawPath,
     block: Option<usize>,
@@ -58,6 +126,8 @@ struct PathBlock {
 struct ManifestParser<R> {
     input: R,
     buf: Vec<u8>,
+    // whether the line in `buf` has been pushed back, to be returned again
+    peeked: bool,
 }
 
 struct WordIter<'a>(&'a [u8]);
@@ -69,6 +139,42 @@ impl Manifest {
         parser.parse()
     }
 
+    /// Writes the manifest in the duplicity format.
+    ///
+    /// Paths containing whitespaces, quotes or backslashes are quoted and those characters are
+    /// escaped, like duplicity does; other bytes, including non-UTF-8 sequences, are written
+    /// unchanged. Hashes are written in hexadecimal. Parsing the written manifest gives back an
+    /// equal manifest.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        if !self.hostname.is_empty() {
+            w.write_all(b"Hostname ")?;
+            write_quoted(w, self.hostname.as_bytes())?;
+            w.write_all(b"\n")?;
+        }
+        if !self.local_dir.as_bytes().is_empty() {
+            w.write_all(b"Localdir ")?;
+            write_quoted(w, self.local_dir.as_bytes())?;
+            w.write_all(b"\n")?;
+        }
+        if !self.file_list.is_empty() {
+            writeln!(w, "Filelist {}", self.file_list.len())?;
+            for change in &self.file_list {
+                write!(w, "    {:<7}  ", change.kind)?;
+                write_path(w, change.path.as_bytes())?;
+                w.write_all(b"\n")?;
+            }
+        }
+        for (i, vol) in self.volumes.iter().enumerate() {
+            writeln!(w, "Volume {}:", i + 1)?;
+            w.write_all(b"    StartingPath   ")?;
+            vol.start_path.write_to(w)?;
+            w.write_all(b"    EndingPath     ")?;
+            vol.end_path.write_to(w)?;
+            writeln!(w, "    Hash {} {}", vol.hash_type, to_hex(&vol.hash))?;
+        }
+        Ok(())
+    }
+
     /// The hostname produced the backup.
     pub fn hostname(&self) -> &str {
         &self.hostname
@@ -79,6 +185,14 @@ impl Manifest {
         self.local_dir.as_path()
     }
 
+    /// The paths changed in this backup set, as listed in the `Filelist` section.
+    ///
+    /// This allows to know what changed in a set without reading its signatures. The list is
+    /// empty for manifests written by older duplicity versions, which do not include it.
+    pub fn file_list(&self) -> &[FileChange] {
+        &self.file_list
+    }
+
     /// The index of the last volume.
     pub fn last_volume_index(&self) -> usize {
         self.volumes.len()
@@ -147,6 +261,24 @@ impl Manifest {
 }
 
 impl Volume {
+    /// Creates a volume info from the first and last paths, with their optional block numbers,
+    /// and the hash of the volume file.
+    ///
+    /// An empty path represents the backup root, which is stored as `.` in manifests.
+    pub fn new(
+        start: (Vec<u8>, Option<usize>),
+        end: (Vec<u8>, Option<usize>),
+        hash_type: String,
+        hash: Vec<u8>,
+    ) -> Self {
+        Volume {
+            start_path: PathBlock::new(start),
+            end_path: PathBlock::new(end),
+            hash_type,
+            hash,
+        }
+    }
+
     /// Returns the first path handled by this volume.
     ///
     /// Note that the path can be `None` under Windows, in case it is non-UTF8. Use
@@ -202,6 +334,106 @@ impl Volume {
     }
 }
 
+impl ManifestBuilder {
+    /// Creates a builder for an empty manifest.
+    pub fn new() -> Self {
+        ManifestBuilder {
+            manifest: Manifest {
+                hostname: String::new(),
+                local_dir: RawPath::new(),
+                volumes: Vec::new(),
+                file_list: Vec::new(),
+            },
+        }
+    }
+
+    /// Sets the hostname that produced the backup.
+    pub fn hostname<S: Into<String>>(mut self, hostname: S) -> Self {
+        self.manifest.hostname = hostname.into();
+        self
+    }
+
+    /// Sets the original backup root path, represented as a byte array.
+    pub fn local_dir<P: Into<Vec<u8>>>(mut self, local_dir: P) -> Self {
+        self.manifest.local_dir = RawPath::from_bytes(local_dir.into());
+        self
+    }
+
+    /// Appends a volume to the manifest.
+    pub fn volume(mut self, volume: Volume) -> Self {
+        self.manifest.volumes.push(volume);
+        self
+    }
+
+    /// Appends a path to the file list of the manifest.
+    pub fn file_change<P: Into<Vec<u8>>>(mut self, kind: ChangeKind, path: P) -> Self {
+        self.manifest
+            .file_list
+            .push(FileChange::new(kind, path.into()));
+        self
+    }
+
+    /// Returns the built manifest.
+    pub fn build(self) -> Manifest {
+        self.manifest
+    }
+}
+
+impl Default for ManifestBuilder {
+    fn default() -> Self {
+        ManifestBuilder::new()
+    }
+}
+
+impl FileChange {
+    /// Creates a file list item from the path and the kind of change.
+    ///
+    /// An empty path represents the backup root, which is stored as `.` in manifests.
+    pub fn new(kind: ChangeKind, path: Vec<u8>) -> Self {
+        FileChange {
+            path: RawPath::from_bytes(root_or(path)),
+            kind,
+        }
+    }
+
+    /// Returns the changed path, if representable in the current platform.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_path()
+    }
+
+    /// Returns the changed path, represented as a byte array.
+    pub fn path_bytes(&self) -> &[u8] {
+        self.path.as_bytes()
+    }
+
+    /// Returns how the path changed.
+    pub fn kind(&self) -> ChangeKind {
+        self.kind
+    }
+}
+
+impl ChangeKind {
+    fn from_bytes(s: &[u8]) -> Option<Self> {
+        match s {
+            b"new" => Some(ChangeKind::New),
+            b"changed" => Some(ChangeKind::Changed),
+            b"deleted" => Some(ChangeKind::Deleted),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ChangeKind {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let s = match *self {
+            ChangeKind::New => "new",
+            ChangeKind::Changed => "changed",
+            ChangeKind::Deleted => "deleted",
+        };
+        fmt.pad(s)
+    }
+}
+
 impl Error for ParseError {}
 
 impl Display for ParseError {
@@ -215,6 +447,12 @@ impl Display for ParseError {
             ParseError::OutOfOrderVolume(v) => {
                 write!(fmt, "volumes are not sorted around volume {}", v)
             }
+            ParseError::UnknownKeyword(ref k) => write!(fmt, "unknown keyword '{}' in manifest", k),
+            ParseError::FileListLength(expected, found) => write!(
+                fmt,
+                "the file list declares {} paths, but {} are present",
+                expected, found
+            ),
             ParseError::ParseInt(ref e) => write!(fmt, "{}", e),
             ParseError::Utf8(ref e) => write!(fmt, "{}", e),
         }
@@ -239,77 +477,157 @@ impl From<Utf8Error> for ParseError {
     }
 }
 
-macro_rules! check_eof(
-    ($e:expr) => (
-        if !$e? {
-            return Err(From::from(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                                 "file ends unexpectedly")));
+impl PathBlock {
+    fn new((path, block): (Vec<u8>, Option<usize>)) -> Self {
+        PathBlock {
+            path: RawPath::from_bytes(root_or(path)),
+            block,
+        }
+    }
+
+    // writes the path and the block number, if present, in a volume info line
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_path(w, self.path.as_bytes())?;
+        match self.block {
+            Some(block) => writeln!(w, " {}", block),
+            None => writeln!(w, "  "),
         }
-    )
-);
+    }
+}
 
 impl<R: BufRead> ManifestParser<R> {
     pub fn new(input: R) -> Self {
         ManifestParser {
-            input: input,
+            input,
             buf: vec![],
+            peeked: false,
         }
     }
 
     pub fn parse(mut self) -> Result<Manifest, ParseError> {
-        check_eof!(self.read_line());
-        let hostname = self.read_param_str("Hostname")?;
-        check_eof!(self.read_line());
-        let local_dir = RawPath::from_bytes(self.read_param_bytes("Localdir")?);
-
+        let mut hostname = String::new();
+        let mut local_dir = RawPath::new();
         let mut volumes = Vec::new();
-        while let Some((vol, i)) = self.read_volume()? {
-            // check if out of order
-            if i != volumes.len() + 1 {
-                return Err(ParseError::OutOfOrderVolume(i));
+        let mut file_list = Vec::new();
+
+        // sections can appear in any order: duplicity writes the file list before the volumes
+        while self.next_line()? {
+            match self.keyword()? {
+                "Hostname" => hostname = self.read_param_str("Hostname")?,
+                "Localdir" => local_dir = RawPath::from_bytes(self.read_param_bytes("Localdir")?),
+                "Filelist" => file_list = self.read_file_list()?,
+                "Volume" => {
+                    let (vol, i) = self.read_volume()?;
+                    // check if out of order
+                    if i != volumes.len() + 1 {
+                        return Err(ParseError::OutOfOrderVolume(i));
+                    }
+                    volumes.push(vol);
+                }
+                // skip the keywords added by newer versions of duplicity, with their values
+                _ => while self.next_indented_line()? {},
             }
-            volumes.push(vol);
         }
 
         Ok(Manifest {
-            hostname: hostname,
-            local_dir: local_dir,
-            volumes: volumes,
+            hostname,
+            local_dir,
+            volumes,
+            file_list,
         })
     }
 
-    fn read_volume(&mut self) -> Result<Option<(Volume, usize)>, ParseError> {
-        if !self.read_line()? {
-            // EOF
-            return Ok(None);
-        }
-
+    fn read_volume(&mut self) -> Result<(Volume, usize), ParseError> {
         // volume number
         let mut param = self.read_param_str("Volume")?;
         if param.ends_with(':') {
             param.pop();
         }
         let num = usize::from_str(&param)?;
-        check_eof!(self.read_line());
-        let start_path = self.read_path_block("StartingPath")?;
-        check_eof!(self.read_line());
-        let end_path = self.read_path_block("EndingPath")?;
-        check_eof!(self.read_line());
-        let (htype, h) = self.read_hash_param()?;
 
+        let mut start_path = None;
+        let mut end_path = None;
+        let mut hash = None;
+        while self.next_indented_line()? {
+            match self.keyword()? {
+                "StartingPath" => start_path = Some(self.read_path_block("StartingPath")?),
+                "EndingPath" => end_path = Some(self.read_path_block("EndingPath")?),
+                // duplicity could write more hashes, only the first one is kept
+                "Hash" if hash.is_some() => (),
+                "Hash" => hash = Some(self.read_hash_param()?),
+                _ => (),
+            }
+        }
+        let missing = |kw: &str| ParseError::MissingKeyword(kw.to_owned());
+        let (hash_type, hash) = hash.ok_or_else(|| missing("Hash"))?;
         let vol = Volume {
-            start_path: start_path,
-            end_path: end_path,
-            hash_type: htype,
-            hash: h,
+            start_path: start_path.ok_or_else(|| missing("StartingPath"))?,
+            end_path: end_path.ok_or_else(|| missing("EndingPath"))?,
+            hash_type,
+            hash,
         };
-        Ok(Some((vol, num)))
+        Ok((vol, num))
+    }
+
+    fn read_file_list(&mut self) -> Result<Vec<FileChange>, ParseError> {
+        let len = usize::from_str(&self.read_param_str("Filelist")?)?;
+        let mut result = Vec::with_capacity(len);
+        while self.next_indented_line()? {
+            let mut words = WordIter(&self.buf);
+            let kind = words.next().unwrap_or_default();
+            let kind = ChangeKind::from_bytes(kind).ok_or_else(|| {
+                ParseError::UnknownKeyword(String::from_utf8_lossy(kind).into_owned())
+            })?;
+            let path = words.next().map(unescape).ok_or(ParseError::MissingPath)?;
+            result.push(FileChange {
+                path: RawPath::from_bytes(path),
+                kind,
+            });
+        }
+        if result.len() != len {
+            return Err(ParseError::FileListLength(len, result.len()));
+        }
+        Ok(result)
+    }
+
+    // reads the next non empty line, returns false on EOF
+    fn next_line(&mut self) -> io::Result<bool> {
+        if self.peeked {
+            self.peeked = false;
+            return Ok(true);
+        }
+        loop {
+            if !self.read_line()? {
+                return Ok(false);
+            }
+            if self.buf.iter().any(|b| !b.is_ascii_whitespace()) {
+                return Ok(true);
+            }
+        }
+    }
+
+    // reads the next line if it is part of the current section, i.e. it is indented; otherwise
+    // the line is kept for the next read
+    fn next_indented_line(&mut self) -> io::Result<bool> {
+        if !self.next_line()? {
+            return Ok(false);
+        }
+        if self.buf[0] == b' ' || self.buf[0] == b'\t' {
+            Ok(true)
+        } else {
+            self.peeked = true;
+            Ok(false)
+        }
     }
 
+    // reads a line in `buf`, without the line terminator; returns false on EOF
     fn read_line(&mut self) -> io::Result<bool> {
         self.buf.clear();
         let mut len = self.input.read_until(b'\n', &mut self.buf)?;
-        if len > 0 && self.buf[len - 1] == b'\n' {
+        if len == 0 {
+            return Ok(false);
+        }
+        if self.buf[len - 1] == b'\n' {
             len -= 1;
         }
         if len > 0 && self.buf[len - 1] == b'\r' {
@@ -317,7 +635,13 @@ impl<R: BufRead> ManifestParser<R> {
         }
         self.buf.truncate(len);
 
-        Ok(!self.buf.is_empty())
+        Ok(true)
+    }
+
+    // returns the first word of the current line
+    fn keyword(&self) -> Result<&str, ParseError> {
+        let word = WordIter(&self.buf).next().unwrap_or_default();
+        Ok(str::from_utf8(word)?)
     }
 
     fn read_param_bytes(&mut self, key: &str) -> Result<Vec<u8>, ParseError> {
@@ -401,27 +725,33 @@ impl<R: BufRead> ManifestParser<R> {
 impl<'a> Iterator for WordIter<'a> {
     type Item = &'a [u8];
 
+    // words are separated by whitespaces, except when they are enclosed in double quotes
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if self.0.is_empty() {
-                return None;
-            }
-            let pos = self
-                .0
-                .iter()
-                .position(|b| *b == b' ')
-                .unwrap_or(self.0.len());
-            let (w, rest) = {
-                let (w, rest) = self.0.split_at(pos);
-                // skip all the spaces from rest
-                let pos = rest.iter().position(|b| *b != b' ').unwrap_or(rest.len());
-                (w, &rest[pos..])
-            };
-            self.0 = rest;
-            if !w.is_empty() {
-                return Some(w);
-            }
+        let start = self
+            .0
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(self.0.len());
+        let buf = &self.0[start..];
+        if buf.is_empty() {
+            self.0 = buf;
+            return None;
         }
+        let len = if buf[0] == b'"' {
+            // up to the closing quote, skipping escaped chars
+            let mut i = 1;
+            while i < buf.len() && buf[i] != b'"' {
+                i += if buf[i] == b'\\' { 2 } else { 1 };
+            }
+            (i + 1).min(buf.len())
+        } else {
+            buf.iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(buf.len())
+        };
+        let (w, rest) = buf.split_at(len);
+        self.0 = rest;
+        Some(w)
     }
 }
 
@@ -459,6 +789,68 @@ fn unescape(mut buf: &[u8]) -> Vec<u8> {
     result
 }
 
+// the backup root is represented as "." in manifests
+fn root_or(path: Vec<u8>) -> Vec<u8> {
+    if path.is_empty() {
+        b".".to_vec()
+    } else {
+        path
+    }
+}
+
+// writes a path of a volume or of the file list; an empty one is written as the root
+fn write_path<W: Write>(w: &mut W, path: &[u8]) -> io::Result<()> {
+    if path.is_empty() {
+        w.write_all(b".")
+    } else {
+        write_quoted(w, path)
+    }
+}
+
+// quotes the given path if it contains whitespaces, quotes or backslashes, in the same way
+// duplicity does
+fn write_quoted<W: Write>(w: &mut W, path: &[u8]) -> io::Result<()> {
+    fn needs_escape(b: u8) -> bool {
+        b.is_ascii_whitespace() || b == b'\x0b' || b == b'"' || b == b'\'' || b == b'\\'
+    }
+
+    if !path.iter().cloned().any(needs_escape) {
+        return w.write_all(path);
+    }
+    w.write_all(b"\"")?;
+    for &b in path {
+        if needs_escape(b) {
+            write!(w, "\\x{:02x}", b)?;
+        } else {
+            w.write_all(&[b])?;
+        }
+    }
+    w.write_all(b"\"")
+}
+
+// hashes are serialized as hexadecimal strings, like in manifest files
+#[cfg(feature = "serde")]
+mod hex_serde {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(hash: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_hex(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.len() % 2 != 0 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(de::Error::custom("invalid hexadecimal string"));
+        }
+        Ok(super::from_hex(s.as_bytes()))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 fn from_hex(s: &[u8]) -> Vec<u8> {
     let mut res = Vec::with_capacity(s.len() / 2);
     let mut buf: u8 = 0;
@@ -486,7 +878,7 @@ fn nibble(b: u8) -> u8 {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::BufReader;
     use std::path::Path;
 
@@ -603,4 +995,156 @@ mod test {
         ];
         assert_eq!(vol.hash().to_vec(), hash);
     }
+
+    #[test]
+    fn file_list() {
+        let file = File::open("tests/manifest/filelist.manifest").unwrap();
+        let manifest = Manifest::parse(&mut BufReader::new(file)).unwrap();
+        assert_eq!(manifest.hostname(), "dellxps");
+        let changes: Vec<_> = manifest
+            .file_list()
+            .iter()
+            .map(|c| (c.kind(), c.path_bytes()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (ChangeKind::New, &b"docs"[..]),
+                (ChangeKind::New, b"docs/my notes.txt"),
+                (ChangeKind::Changed, b"todo.txt"),
+                (ChangeKind::Deleted, b"old dir/file"),
+            ]
+        );
+
+        // quoted paths containing spaces, and blank lines between volumes
+        assert_eq!(manifest.last_volume_index(), 2);
+        let vol = manifest.volume(1).unwrap();
+        assert_eq!(vol.end_path_bytes(), b"docs/my notes.txt");
+        assert_eq!(vol.end_block(), Some(2));
+        let vol = manifest.volume(2).unwrap();
+        assert_eq!(vol.start_path_bytes(), b"docs/my notes.txt");
+        assert_eq!(vol.start_block(), Some(3));
+        assert_eq!(vol.end_path_bytes(), b"todo.txt");
+        assert_eq!(manifest.first_volume_of_path(b"docs/my notes.txt"), Some(1));
+        assert_eq!(manifest.last_volume_of_path(b"docs/my notes.txt"), Some(2));
+
+        // older manifests have no file list
+        assert!(full1_manifest().unwrap().file_list().is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        fn parse(s: &str) -> Result<Manifest, ParseError> {
+            Manifest::parse(&mut s.as_bytes())
+        }
+
+        let vol = "Volume 1:\n    StartingPath .\n    EndingPath a\n    Hash SHA1 00\n";
+        parse(vol).unwrap();
+        // unknown keywords are skipped with their values
+        let unknown = "Hostname h\nNewKeyword 1\n    value\nVolume 1:\n    StartingPath .\n    \
+                       NewVolumeKeyword x\n    EndingPath a\n    Hash SHA1 00\n";
+        let manifest = parse(unknown).unwrap();
+        assert_eq!(manifest.hostname(), "h");
+        assert_eq!(manifest.volume(1).unwrap().end_path_bytes(), b"a");
+        match parse("Filelist 2\n    new a\n") {
+            Err(ParseError::FileListLength(2, 1)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match parse("Filelist 1\n    moved a\n") {
+            Err(ParseError::UnknownKeyword(ref k)) if k == "moved" => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match parse("Volume 1:\n    StartingPath .\n    Hash SHA1 00\n") {
+            Err(ParseError::MissingKeyword(ref k)) if k == "EndingPath" => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match parse(&vol.replace("Volume 1", "Volume 2")) {
+            Err(ParseError::OutOfOrderVolume(2)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut fixtures: Vec<_> = fs::read_dir("tests/manifest")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        fixtures.sort();
+        assert!(fixtures.len() >= 4);
+        for path in fixtures {
+            let content = fs::read(&path).unwrap();
+            let manifest = Manifest::parse(&mut &content[..]).unwrap();
+            let mut written = Vec::new();
+            manifest.write_to(&mut written).unwrap();
+            assert_eq!(Manifest::parse(&mut &written[..]).unwrap(), manifest);
+            // manifests in the format written by duplicity are reproduced exactly, while the
+            // file list fixture also has spaces in quoted paths and blank lines
+            if !path.ends_with("filelist.manifest") {
+                assert_eq!(written, content, "{}", path.display());
+            } else {
+                let canonical = fs::read("tests/manifest/filelist_canonical.manifest").unwrap();
+                assert_eq!(written, canonical);
+            }
+        }
+    }
+
+    #[test]
+    fn build() {
+        let manifest = ManifestBuilder::new()
+            .hostname("my host")
+            .local_dir(b"/home/\xff dir".to_vec())
+            .file_change(ChangeKind::New, "new")
+            .file_change(ChangeKind::Changed, "")
+            .file_change(ChangeKind::Deleted, b"a \"quoted\"\\path\t\xfe".to_vec())
+            .volume(Volume::new(
+                (vec![], None),
+                (b"big file".to_vec(), Some(3)),
+                "SHA1".to_owned(),
+                vec![0, 1, 0xab, 0xff],
+            ))
+            .volume(Volume::new(
+                (b"big file".to_vec(), Some(4)),
+                (b"\xff\xfe".to_vec(), None),
+                "SHA1".to_owned(),
+                vec![0x12; 20],
+            ))
+            .build();
+        let mut written = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+        assert_eq!(
+            &written[..],
+            &b"Hostname \"my\\x20host\"\n\
+               Localdir \"/home/\xff\\x20dir\"\n\
+               Filelist 3\n\
+               \x20   new      new\n\
+               \x20   changed  .\n\
+               \x20   deleted  \"a\\x20\\x22quoted\\x22\\x5cpath\\x09\xfe\"\n\
+               Volume 1:\n\
+               \x20   StartingPath   .  \n\
+               \x20   EndingPath     \"big\\x20file\" 3\n\
+               \x20   Hash SHA1 0001abff\n\
+               Volume 2:\n\
+               \x20   StartingPath   \"big\\x20file\" 4\n\
+               \x20   EndingPath     \xff\xfe  \n\
+               \x20   Hash SHA1 1212121212121212121212121212121212121212\n"[..]
+        );
+        let parsed = Manifest::parse(&mut &written[..]).unwrap();
+        assert_eq!(parsed, manifest);
+        assert_eq!(parsed.hostname(), "my host");
+        assert_eq!(parsed.volume(1).unwrap().start_path_bytes(), b".");
+        assert_eq!(parsed.file_list()[0].path_bytes(), b"new");
+        assert_eq!(parsed.file_list()[1].path_bytes(), b".");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let manifest = full1_manifest().unwrap();
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json
+            .contains(r#""hash_type":"SHA1","hash":"e4a2e8e2abfba2cb24772e5ff9da4b85b3c19a0c""#));
+        let deserialized: Manifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, manifest);
+    }
 }
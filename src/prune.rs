@@ -0,0 +1,281 @@
+//! Removal of old backup chains.
+//!
+//! This sub-module implements the duplicity `remove-older-than`, `remove-all-but-n-full` and
+//! `remove-all-inc-of-but-n-full` commands. Pruning is performed in two steps: first a
+//! `PrunePlan` is computed by looking at the backup collections only, then it can be inspected
+//! and executed with a backend able to delete files.
+//!
+//! # Example
+//!
+//! ```
+//! use ruplicity::Backup;
+//! use ruplicity::backend::local::LocalBackend;
+//! use ruplicity::prune::Policy;
+//!
+//! let backup = Backup::new(LocalBackend::new("tests/backups/multi_chain")).unwrap();
+//! // keep only the last full backup chain
+//! let plan = backup.prune(Policy::AllButNFull(1));
+//! for file in plan.files() {
+//!     println!("would delete {}", file);
+//! }
+//! // `plan.execute(backup.backend())` would delete them
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::Path;
+
+use time::Timespec;
+
+use crate::backend::WritableBackend;
+use crate::collections::{BackupChain, Collections, SignatureChain};
+use crate::timefmt::TimeDisplay;
+
+/// Determines which backup chains are removed by a prune operation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Policy {
+    /// Removes the chains entirely older than the given time.
+    ///
+    /// Like duplicity `remove-older-than <time>`, a chain is removed only if all its snapshots are
+    /// older than the given time, so that the remaining snapshots do not lose any dependency. The
+    /// most recent chain is never removed.
+    OlderThan(Timespec),
+    /// Removes all the chains except the last `n` ones.
+    ///
+    /// Like duplicity `remove-all-but-n-full <n>`. If there are less than `n` full backups,
+    /// nothing is removed.
+    AllButNFull(usize),
+    /// Removes the incremental snapshots of all the chains except the last `n` ones.
+    ///
+    /// Like duplicity `remove-all-inc-of-but-n-full <n>`. The full snapshots (and their
+    /// signatures) are kept.
+    AllIncOfButNFull(usize),
+}
+
+/// The list of files to be removed by a prune operation.
+///
+/// Nothing is actually deleted until `execute` is called, so the plan can be used as a dry run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PrunePlan {
+    chains: Vec<PrunedChain>,
+}
+
+/// A backup chain affected by a prune operation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PrunedChain {
+    /// The time of the full snapshot of the chain.
+    pub start_time: Timespec,
+    /// The time of the last snapshot of the chain.
+    pub end_time: Timespec,
+    /// Whether the full snapshot is kept, and only the incremental ones are removed.
+    pub keep_full: bool,
+    /// The files to be removed, in deletion order.
+    pub files: Vec<String>,
+}
+
+impl PrunePlan {
+    /// Computes the prune plan for the given collections.
+    pub fn new(collections: &Collections, policy: Policy) -> Self {
+        let (time, keep_full) = match policy {
+            Policy::OlderThan(time) => (Some(time), false),
+            Policy::AllButNFull(n) => (nth_last_full_time(collections, n), false),
+            Policy::AllIncOfButNFull(n) => (nth_last_full_time(collections, n), true),
+        };
+        let time = match time {
+            Some(time) => time,
+            None => {
+                return PrunePlan::default();
+            }
+        };
+        let num_chains = collections.backup_chains().len();
+        let chains = collections
+            .backup_chains()
+            .take(num_chains.saturating_sub(1)) // never remove the current chain
+            .filter(|chain| chain.end_time() < time)
+            .filter(|chain| !keep_full || chain.inc_sets().len() > 0)
+            .map(|chain| {
+                let sigchain = collections.signature_chain_for(chain);
+                PrunedChain::new(chain, sigchain, keep_full)
+            })
+            .collect();
+        PrunePlan { chains }
+    }
+
+    /// Returns the chains affected by the plan, in chronological order.
+    pub fn chains(&self) -> &[PrunedChain] {
+        &self.chains
+    }
+
+    /// Returns all the files to be removed.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.chains
+            .iter()
+            .flat_map(|c| c.files.iter().map(AsRef::as_ref))
+    }
+
+    /// Returns whether there is nothing to remove.
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// Deletes the files in the plan by using the given backend.
+    ///
+    /// Files are deleted chain by chain, starting from the most recent snapshot of each chain. If
+    /// the operation is interrupted, every remaining snapshot still has all its dependencies.
+    ///
+    /// # Errors
+    /// The operation stops at the first error returned by the backend. Files already missing
+    /// are ignored.
+    pub fn execute<B: WritableBackend>(&self, backend: &B) -> io::Result<()> {
+        for file in self.files() {
+            match backend.delete_file(Path::new(file)) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                res => res?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for PrunePlan {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.chains.is_empty() {
+            return writeln!(f, "No old backup sets found, nothing deleted.");
+        }
+        for chain in &self.chains {
+            let what = if chain.keep_full {
+                "incremental sets of chain"
+            } else {
+                "backup chain"
+            };
+            writeln!(
+                f,
+                "Deleting {} from {} to {}:",
+                what,
+                chain.start_time.into_local_display(),
+                chain.end_time.into_local_display()
+            )?;
+            for file in &chain.files {
+                writeln!(f, "  {}", file)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PrunedChain {
+    fn new(chain: &BackupChain, sigchain: Option<&SignatureChain>, keep_full: bool) -> Self {
+        let mut files = Vec::new();
+        for set in chain.inc_sets().rev() {
+            files.extend(set.file_names().map(ToOwned::to_owned));
+        }
+        if let Some(sigchain) = sigchain {
+            for sig in sigchain.inc_signatures().rev() {
+                files.push(sig.file_name.clone());
+            }
+        }
+        if !keep_full {
+            files.extend(chain.full_set().file_names().map(ToOwned::to_owned));
+            if let Some(sigchain) = sigchain {
+                files.push(sigchain.full_signature().file_name.clone());
+            }
+        }
+        PrunedChain {
+            start_time: chain.start_time(),
+            end_time: chain.end_time(),
+            keep_full,
+            files,
+        }
+    }
+}
+
+// the start time of the n-th last chain, if present
+fn nth_last_full_time(collections: &Collections, n: usize) -> Option<Timespec> {
+    if n == 0 {
+        return None;
+    }
+    collections
+        .backup_chains()
+        .rev()
+        .nth(n - 1)
+        .map(BackupChain::start_time)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::timefmt::parse_time_str;
+
+    fn multi_chain() -> Collections {
+        Collections::from_filenames(&[
+            "duplicity-full.20160108T223144Z.manifest",
+            "duplicity-full.20160108T223144Z.vol1.difftar.gz",
+            "duplicity-full.20160108T223209Z.manifest",
+            "duplicity-full.20160108T223209Z.vol1.difftar.gz",
+            "duplicity-full-signatures.20160108T223144Z.sigtar.gz",
+            "duplicity-full-signatures.20160108T223209Z.sigtar.gz",
+            "duplicity-inc.20160108T223144Z.to.20160108T223159Z.manifest",
+            "duplicity-inc.20160108T223144Z.to.20160108T223159Z.vol1.difftar.gz",
+            "duplicity-inc.20160108T223209Z.to.20160108T223217Z.manifest",
+            "duplicity-inc.20160108T223209Z.to.20160108T223217Z.vol1.difftar.gz",
+            "duplicity-new-signatures.20160108T223144Z.to.20160108T223159Z.sigtar.gz",
+            "duplicity-new-signatures.20160108T223209Z.to.20160108T223217Z.sigtar.gz",
+        ])
+    }
+
+    fn first_chain_files() -> Vec<&'static str> {
+        vec![
+            "duplicity-inc.20160108T223144Z.to.20160108T223159Z.manifest",
+            "duplicity-inc.20160108T223144Z.to.20160108T223159Z.vol1.difftar.gz",
+            "duplicity-new-signatures.20160108T223144Z.to.20160108T223159Z.sigtar.gz",
+            "duplicity-full.20160108T223144Z.manifest",
+            "duplicity-full.20160108T223144Z.vol1.difftar.gz",
+            "duplicity-full-signatures.20160108T223144Z.sigtar.gz",
+        ]
+    }
+
+    #[test]
+    fn older_than() {
+        let coll = multi_chain();
+        // inside the first chain: nothing to remove
+        let plan = PrunePlan::new(
+            &coll,
+            Policy::OlderThan(parse_time_str("20160108t223150z").unwrap()),
+        );
+        assert!(plan.is_empty());
+        // after the first chain
+        let plan = PrunePlan::new(
+            &coll,
+            Policy::OlderThan(parse_time_str("20160108t223200z").unwrap()),
+        );
+        assert_eq!(plan.files().collect::<Vec<_>>(), first_chain_files());
+        // the last chain is never removed
+        let plan = PrunePlan::new(
+            &coll,
+            Policy::OlderThan(parse_time_str("20200101t000000z").unwrap()),
+        );
+        assert_eq!(plan.chains().len(), 1);
+    }
+
+    #[test]
+    fn all_but_n_full() {
+        let coll = multi_chain();
+        assert!(PrunePlan::new(&coll, Policy::AllButNFull(0)).is_empty());
+        assert!(PrunePlan::new(&coll, Policy::AllButNFull(2)).is_empty());
+        assert!(PrunePlan::new(&coll, Policy::AllButNFull(3)).is_empty());
+        let plan = PrunePlan::new(&coll, Policy::AllButNFull(1));
+        assert_eq!(plan.files().collect::<Vec<_>>(), first_chain_files());
+    }
+
+    #[test]
+    fn all_inc_of_but_n_full() {
+        let coll = multi_chain();
+        assert!(PrunePlan::new(&coll, Policy::AllIncOfButNFull(2)).is_empty());
+        let plan = PrunePlan::new(&coll, Policy::AllIncOfButNFull(1));
+        assert!(plan.chains()[0].keep_full);
+        assert_eq!(
+            plan.files().collect::<Vec<_>>(),
+            first_chain_files()[..3].to_vec()
+        );
+    }
+}
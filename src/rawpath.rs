@@ -1,8 +1,62 @@
-pub use self::os::RawPath;
+pub use self::os::{path_from_bytes, RawPath};
+
+// paths are serialized as strings if they are valid UTF-8, and as raw bytes otherwise, so that
+// no information is lost
+#[cfg(feature = "serde")]
+mod serialize {
+    use std::fmt::{self, Formatter};
+    use std::str;
+
+    use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+    use serde::ser::{Serialize, Serializer};
+
+    use super::RawPath;
+
+    impl Serialize for RawPath {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match str::from_utf8(self.as_bytes()) {
+                Ok(s) => serializer.serialize_str(s),
+                Err(_) => serializer.serialize_bytes(self.as_bytes()),
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for RawPath {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(RawPathVisitor)
+        }
+    }
+
+    struct RawPathVisitor;
+
+    impl<'de> Visitor<'de> for RawPathVisitor {
+        type Value = RawPath;
+
+        fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "a string or a sequence of bytes")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<RawPath, E> {
+            Ok(RawPath::from_bytes(v.as_bytes().to_owned()))
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<RawPath, E> {
+            Ok(RawPath::from_bytes(v.to_owned()))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<RawPath, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                bytes.push(b);
+            }
+            Ok(RawPath::from_bytes(bytes))
+        }
+    }
+}
 
 #[cfg(unix)]
 mod os {
-    use std::ffi::OsString;
+    use std::ffi::{OsStr, OsString};
     use std::fmt::{self, Display, Formatter};
     use std::os::unix::prelude::*;
     use std::path::{Path, PathBuf};
@@ -10,6 +64,10 @@ mod os {
     #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
     pub struct RawPath(PathBuf);
 
+    pub fn path_from_bytes(bytes: &[u8]) -> Option<&Path> {
+        Some(Path::new(OsStr::from_bytes(bytes)))
+    }
+
     impl RawPath {
         #[allow(dead_code)]
         pub fn new() -> Self {
@@ -56,6 +114,10 @@ mod os {
         Bytes(Vec<u8>),
     }
 
+    pub fn path_from_bytes(bytes: &[u8]) -> Option<&Path> {
+        str::from_utf8(bytes).ok().map(Path::new)
+    }
+
     impl RawPath {
         #[allow(dead_code)]
         pub fn new() -> Self {
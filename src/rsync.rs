@@ -0,0 +1,1106 @@
+//! Operations on librsync signatures.
+//!
+//! duplicity uses librsync to compute the signatures of backupped files, so that the following
+//! incremental backups can store only the differences. This sub-module implements the librsync
+//! rolling checksum, the parsing and generation of signatures and the generation of deltas,
+//! compatible with the ones produced by duplicity. For the signature file format see `Docs.md`.
+//!
+//! # Example
+//!
+//! ```
+//! use std::io::Write;
+//! use ruplicity::rsync::{block_len, SignatureBuilder};
+//!
+//! let data = b"some file content";
+//! let mut builder = SignatureBuilder::new(block_len(data.len() as u64));
+//! builder.write_all(data).unwrap();
+//! let signature = builder.finish();
+//! // header and a single block
+//! assert_eq!(signature.len(), 12 + 12);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{self, Formatter};
+use std::io::{self, Read, Write};
+
+use blake2::digest::consts::U32;
+use blake2::Blake2b;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use md4::{Digest, Md4};
+
+/// The magic number of signatures using MD4 strong checksums.
+pub const MD4_SIG_MAGIC: u32 = 0x7273_0136;
+
+/// The magic number of signatures using BLAKE2 strong checksums.
+pub const BLAKE2_SIG_MAGIC: u32 = 0x7273_0137;
+
+/// The length of the strong checksums used by duplicity, in bytes.
+pub const DEFAULT_STRONG_LEN: u32 = 8;
+
+/// The magic number of librsync deltas.
+pub const DELTA_MAGIC: u32 = 0x7273_0236;
+
+const CHAR_OFFSET: u32 = 31;
+
+// delta commands
+const OP_END: u8 = 0x00;
+const OP_LITERAL_N1: u8 = 0x41;
+const OP_COPY_N1_N1: u8 = 0x45;
+
+// the maximum length of a literal command produced
+const DELTA_CHUNK_LEN: usize = 64 * 1024;
+
+/// The librsync weak rolling checksum.
+///
+/// The checksum of a window of bytes can be updated in constant time when the window is moved
+/// by one byte, by using `rotate`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Rollsum {
+    count: u32,
+    s1: u32,
+    s2: u32,
+}
+
+/// The hash function used to compute the strong checksums of a signature.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum StrongHash {
+    /// MD4, with 16 bytes digests. Used by duplicity and by librsync before version 1.0.
+    Md4,
+    /// BLAKE2b, with 32 bytes digests. The default of librsync since version 1.0.
+    Blake2,
+}
+
+/// Computes a librsync signature for a stream of bytes.
+///
+/// The data is provided by using the `Write` trait or `update`, and the signature is returned
+/// by `finish`.
+#[derive(Debug)]
+pub struct SignatureBuilder {
+    hash: StrongHash,
+    block_len: usize,
+    strong_len: usize,
+    block: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Rollsum {
+    /// Creates a new checksum for an empty window.
+    pub fn new() -> Self {
+        Rollsum::default()
+    }
+
+    /// Adds the given bytes at the end of the window.
+    pub fn update(&mut self, buf: &[u8]) {
+        for &b in buf {
+            self.roll_in(b);
+        }
+    }
+
+    /// Adds a byte at the end of the window.
+    pub fn roll_in(&mut self, b: u8) {
+        self.s1 = self.s1.wrapping_add(u32::from(b) + CHAR_OFFSET);
+        self.s2 = self.s2.wrapping_add(self.s1);
+        self.count += 1;
+    }
+
+    /// Removes a byte from the start of the window.
+    pub fn roll_out(&mut self, b: u8) {
+        let out = u32::from(b) + CHAR_OFFSET;
+        self.s1 = self.s1.wrapping_sub(out);
+        self.s2 = self.s2.wrapping_sub(self.count.wrapping_mul(out));
+        self.count -= 1;
+    }
+
+    /// Moves the window by one byte, removing `out` from the start and adding `inb` at the end.
+    pub fn rotate(&mut self, out: u8, inb: u8) {
+        let out = u32::from(out);
+        self.s1 = self.s1.wrapping_add(u32::from(inb)).wrapping_sub(out);
+        self.s2 = self
+            .s2
+            .wrapping_add(self.s1)
+            .wrapping_sub(self.count.wrapping_mul(out + CHAR_OFFSET));
+    }
+
+    /// Returns the number of bytes in the window.
+    pub fn count(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Returns the checksum value.
+    pub fn digest(&self) -> u32 {
+        (self.s2 << 16) | (self.s1 & 0xffff)
+    }
+}
+
+impl SignatureBuilder {
+    /// Creates a builder for a signature with the given block length.
+    ///
+    /// Strong checksums are truncated to `DEFAULT_STRONG_LEN` bytes, like duplicity does.
+    pub fn new(block_len: u32) -> Self {
+        Self::with_strong_len(block_len, DEFAULT_STRONG_LEN)
+    }
+
+    /// Creates a builder for an MD4 signature with the given block and strong checksum lengths.
+    ///
+    /// # Panics
+    /// Panics if the block length is zero, or if the strong checksum length is zero or longer
+    /// than an MD4 digest (16 bytes).
+    pub fn with_strong_len(block_len: u32, strong_len: u32) -> Self {
+        Self::with_hash(StrongHash::Md4, block_len, strong_len)
+    }
+
+    /// Creates a builder for a signature using the given strong hash, block and strong checksum
+    /// lengths.
+    ///
+    /// # Panics
+    /// Panics if the block length is zero, or if the strong checksum length is zero or longer
+    /// than the digest of the hash function.
+    pub fn with_hash(hash: StrongHash, block_len: u32, strong_len: u32) -> Self {
+        assert!(block_len > 0, "block length must be positive");
+        assert!(
+            strong_len > 0 && strong_len <= hash.digest_len(),
+            "strong checksum length must be between 1 and {}",
+            hash.digest_len()
+        );
+        let mut signature = Vec::with_capacity(12);
+        // writing into a vector never fails
+        signature.write_u32::<BigEndian>(hash.magic()).unwrap();
+        signature.write_u32::<BigEndian>(block_len).unwrap();
+        signature.write_u32::<BigEndian>(strong_len).unwrap();
+        SignatureBuilder {
+            hash,
+            block_len: block_len as usize,
+            strong_len: strong_len as usize,
+            block: Vec::with_capacity(block_len as usize),
+            signature,
+        }
+    }
+
+    /// Adds the given data to the signature.
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let missing = self.block_len - self.block.len();
+            let (head, tail) = data.split_at(missing.min(data.len()));
+            self.block.extend_from_slice(head);
+            data = tail;
+            if self.block.len() == self.block_len {
+                self.add_block();
+            }
+        }
+    }
+
+    /// Returns the signature of all the data provided.
+    pub fn finish(mut self) -> Vec<u8> {
+        if !self.block.is_empty() {
+            self.add_block();
+        }
+        self.signature
+    }
+
+    fn add_block(&mut self) {
+        let mut sum = Rollsum::new();
+        sum.update(&self.block);
+        self.signature.write_u32::<BigEndian>(sum.digest()).unwrap();
+        let strong = self.hash.digest(&self.block);
+        self.signature.extend_from_slice(&strong[..self.strong_len]);
+        self.block.clear();
+    }
+}
+
+impl Write for SignatureBuilder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the block length used by duplicity for a file of the given length.
+///
+/// Bigger blocks produce bigger deltas, while smaller blocks make deltas and patches slower, so
+/// files are split in about 2000 blocks, with a block length between 512 and 2048 bytes.
+pub fn block_len(file_len: u64) -> u32 {
+    if file_len < 1_024_000 {
+        512
+    } else {
+        let len = file_len / (2000 * 512) * 512;
+        len.min(2048) as u32
+    }
+}
+
+/// Computes the length of the file produced by applying a librsync delta.
+///
+/// The delta commands are parsed incrementally, without applying them, so that neither the old
+/// file nor the new one are needed. The delta is provided by using the `Write` trait or
+/// `update`, and the length is returned by `finish`.
+///
+/// # Example
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use ruplicity::rsync::{Delta, DeltaLen, RsSignature, SignatureBuilder};
+///
+/// let old = vec![7; 2000];
+/// let mut builder = SignatureBuilder::new(512);
+/// builder.write_all(&old).unwrap();
+/// let signature = RsSignature::parse(&builder.finish()).unwrap();
+/// let mut delta = Vec::new();
+/// Delta::new(signature, &old[..1500]).read_to_end(&mut delta).unwrap();
+///
+/// let mut len = DeltaLen::new();
+/// len.write_all(&delta).unwrap();
+/// assert_eq!(len.finish().unwrap(), 1500);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DeltaLen {
+    // the bytes of the current command, or of the magic number
+    buf: Vec<u8>,
+    magic: bool,
+    // the literal bytes still to be skipped
+    skip: u64,
+    len: u64,
+    done: bool,
+}
+
+/// A parsed librsync signature.
+///
+/// Contains the rolling and the strong checksums of every block of a file, and can be used to
+/// find which blocks of a new version of the file are changed, or to compute a delta against
+/// it, without having the original file.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Write;
+/// use ruplicity::rsync::{RsSignature, SignatureBuilder};
+///
+/// let old = vec![1; 2000];
+/// let mut builder = SignatureBuilder::new(512);
+/// builder.write_all(&old).unwrap();
+/// let signature = RsSignature::parse(&builder.finish()).unwrap();
+/// assert_eq!(signature.num_blocks(), 4);
+/// assert_eq!(signature.size_hint(), (1537, 2048));
+///
+/// let mut new = old.clone();
+/// new[1000] = 2;
+/// assert_eq!(signature.changed_blocks(&new[..]).unwrap(), vec![1]);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RsSignature {
+    hash: StrongHash,
+    block_len: u32,
+    strong_len: u32,
+    weak: Vec<u32>,
+    strong: Vec<u8>,
+}
+
+/// The checksums of a block of a file, as stored in a signature.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BlockSums<'a> {
+    weak: u32,
+    strong: &'a [u8],
+}
+
+/// Computes a librsync delta between a file, described by its signature, and new data.
+///
+/// The delta is produced incrementally while it is read, so that only a bounded amount of the
+/// new data is kept in memory.
+///
+/// # Example
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use ruplicity::rsync::{Delta, RsSignature, SignatureBuilder};
+///
+/// let old: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+/// let mut builder = SignatureBuilder::new(512);
+/// builder.write_all(&old).unwrap();
+/// let signature = builder.finish();
+///
+/// let mut new = old.clone();
+/// new.extend_from_slice(b"appended");
+/// let mut delta = Vec::new();
+/// let signature = RsSignature::parse(&signature).unwrap();
+/// Delta::new(signature, &new[..]).read_to_end(&mut delta).unwrap();
+/// // the unchanged blocks are copied from the old file
+/// assert!(delta.len() < 32);
+/// ```
+pub struct Delta<R> {
+    input: R,
+    signature: RsSignature,
+    // the blocks of the signature, indexed by weak checksum
+    blocks: HashMap<u32, Vec<usize>>,
+    // new data not yet encoded: `buf[start..pos]` is the pending literal, and the rolling
+    // checksum window starts at `pos`
+    buf: Vec<u8>,
+    start: usize,
+    pos: usize,
+    sum: Option<Rollsum>,
+    eof: bool,
+    // a copy command not yet written, since it could be merged with the following ones
+    copy: Option<(u64, u64)>,
+    out: Vec<u8>,
+    out_pos: usize,
+    done: bool,
+}
+
+impl<R: Read> Delta<R> {
+    /// Creates a delta between the file with the given signature, and the new data.
+    pub fn new(signature: RsSignature, input: R) -> Self {
+        let mut out = Vec::with_capacity(DELTA_CHUNK_LEN);
+        out.write_u32::<BigEndian>(DELTA_MAGIC).unwrap();
+        let mut blocks: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, &weak) in signature.weak.iter().enumerate() {
+            blocks.entry(weak).or_default().push(i);
+        }
+        Delta {
+            input,
+            signature,
+            blocks,
+            buf: Vec::new(),
+            start: 0,
+            pos: 0,
+            sum: None,
+            eof: false,
+            copy: None,
+            out,
+            out_pos: 0,
+            done: false,
+        }
+    }
+
+    /// Unwraps the delta, returning the input stream.
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    // encodes the data until some output is available, or the delta is complete
+    fn fill_out(&mut self) -> io::Result<()> {
+        let block_len = self.signature.block_len as usize;
+        while self.out.len() == self.out_pos && !self.done {
+            self.out.clear();
+            self.out_pos = 0;
+            // we need a byte more than a block to roll the checksum
+            if !self.eof && self.buf.len() - self.pos <= block_len {
+                self.read_input()?;
+                continue;
+            }
+            let avail = self.buf.len() - self.pos;
+            if avail >= block_len {
+                let window = self.pos..self.pos + block_len;
+                let sum = match self.sum {
+                    Some(sum) => sum,
+                    None => {
+                        let mut sum = Rollsum::new();
+                        sum.update(&self.buf[window.clone()]);
+                        sum
+                    }
+                };
+                if let Some(block) = self.find(sum.digest(), &self.buf[window]) {
+                    self.flush_literal();
+                    self.add_copy(block, block_len);
+                    self.pos += block_len;
+                    self.start = self.pos;
+                    self.sum = None;
+                } else if avail > block_len {
+                    let mut sum = sum;
+                    sum.rotate(self.buf[self.pos], self.buf[self.pos + block_len]);
+                    self.sum = Some(sum);
+                    self.pos += 1;
+                } else {
+                    // last block of the input: nothing more to roll
+                    self.pos = self.buf.len();
+                }
+                if self.pos - self.start >= DELTA_CHUNK_LEN {
+                    self.flush_literal();
+                }
+            } else {
+                // the tail of the input could match the last (short) block of the signature
+                if avail > 0 {
+                    let mut sum = Rollsum::new();
+                    sum.update(&self.buf[self.pos..]);
+                    if let Some(block) = self.find(sum.digest(), &self.buf[self.pos..]) {
+                        self.flush_literal();
+                        self.add_copy(block, avail);
+                        self.start = self.buf.len();
+                    }
+                }
+                self.pos = self.buf.len();
+                self.flush_literal();
+                self.flush_copy();
+                self.out.push(OP_END);
+                self.done = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_input(&mut self) -> io::Result<()> {
+        // drop the data already encoded
+        self.buf.drain(..self.start);
+        self.pos -= self.start;
+        self.start = 0;
+        let len = self.buf.len();
+        let read = (&mut self.input)
+            .take(DELTA_CHUNK_LEN as u64)
+            .read_to_end(&mut self.buf)?;
+        if read == 0 {
+            self.eof = true;
+        }
+        debug_assert!(self.buf.len() == len + read);
+        Ok(())
+    }
+
+    fn add_copy(&mut self, block: usize, len: usize) {
+        let offset = block as u64 * u64::from(self.signature.block_len);
+        let len = len as u64;
+        match self.copy {
+            Some((start, ref mut copy_len)) if start + *copy_len == offset => *copy_len += len,
+            _ => {
+                self.flush_copy();
+                self.copy = Some((offset, len));
+            }
+        }
+    }
+
+    fn flush_copy(&mut self) {
+        if let Some((offset, len)) = self.copy.take() {
+            let (offset_width, offset_code) = int_width(offset);
+            let (len_width, len_code) = int_width(len);
+            self.out.push(OP_COPY_N1_N1 + offset_code * 4 + len_code);
+            write_int(&mut self.out, offset, offset_width);
+            write_int(&mut self.out, len, len_width);
+        }
+    }
+
+    // returns the index of a block with the given content, if present
+    fn find(&self, weak: u32, data: &[u8]) -> Option<usize> {
+        let candidates = self.blocks.get(&weak)?;
+        let strong = self.signature.strong_sum(data);
+        candidates
+            .iter()
+            .cloned()
+            .find(|&i| self.signature.strong(i) == &strong[..])
+    }
+
+    fn flush_literal(&mut self) {
+        if self.start == self.pos {
+            return;
+        }
+        self.flush_copy();
+        let len = self.pos - self.start;
+        if len <= 64 {
+            self.out.push(len as u8);
+        } else {
+            let (width, code) = int_width(len as u64);
+            self.out.push(OP_LITERAL_N1 + code);
+            write_int(&mut self.out, len as u64, width);
+        }
+        self.out.extend_from_slice(&self.buf[self.start..self.pos]);
+        self.start = self.pos;
+    }
+}
+
+impl<R: Read> Read for Delta<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_out()?;
+        let len = buf.len().min(self.out.len() - self.out_pos);
+        buf[..len].copy_from_slice(&self.out[self.out_pos..self.out_pos + len]);
+        self.out_pos += len;
+        Ok(len)
+    }
+}
+
+impl<R> fmt::Debug for Delta<R> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Delta")
+            .field("block_len", &self.signature.block_len)
+            .field("num_blocks", &self.signature.num_blocks())
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl StrongHash {
+    /// Returns the hash function of signatures with the given magic number, if supported.
+    pub fn from_magic(magic: u32) -> Option<Self> {
+        match magic {
+            MD4_SIG_MAGIC => Some(StrongHash::Md4),
+            BLAKE2_SIG_MAGIC => Some(StrongHash::Blake2),
+            _ => None,
+        }
+    }
+
+    /// Returns the magic number of signatures using this hash function.
+    pub fn magic(self) -> u32 {
+        match self {
+            StrongHash::Md4 => MD4_SIG_MAGIC,
+            StrongHash::Blake2 => BLAKE2_SIG_MAGIC,
+        }
+    }
+
+    /// Returns the length of the digests, in bytes.
+    pub fn digest_len(self) -> u32 {
+        match self {
+            StrongHash::Md4 => 16,
+            StrongHash::Blake2 => 32,
+        }
+    }
+
+    /// Computes the digest of the given data.
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            StrongHash::Md4 => Md4::digest(data).to_vec(),
+            StrongHash::Blake2 => Blake2b::<U32>::digest(data).to_vec(),
+        }
+    }
+}
+
+impl RsSignature {
+    /// Parses a signature.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if the signature type is not supported or the signature is
+    /// malformed.
+    pub fn parse(mut signature: &[u8]) -> io::Result<Self> {
+        let magic = signature.read_u32::<BigEndian>()?;
+        let hash = StrongHash::from_magic(magic)
+            .ok_or_else(|| invalid_data("unsupported signature type"))?;
+        let block_len = signature.read_u32::<BigEndian>()?;
+        let strong_len = signature.read_u32::<BigEndian>()?;
+        if block_len == 0 || strong_len == 0 || strong_len > hash.digest_len() {
+            return Err(invalid_data("invalid signature header"));
+        }
+        let ss_len = strong_len as usize;
+        let num_blocks = signature.len() / (4 + ss_len);
+        let mut weak = Vec::with_capacity(num_blocks);
+        let mut strong = Vec::with_capacity(num_blocks * ss_len);
+        while !signature.is_empty() {
+            weak.push(signature.read_u32::<BigEndian>()?);
+            if signature.len() < ss_len {
+                return Err(invalid_data("truncated signature"));
+            }
+            let (block_strong, rest) = signature.split_at(ss_len);
+            strong.extend_from_slice(block_strong);
+            signature = rest;
+        }
+        Ok(RsSignature {
+            hash,
+            block_len,
+            strong_len,
+            weak,
+            strong,
+        })
+    }
+
+    /// Reads and parses a signature from the given stream.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Self::parse(&buf)
+    }
+
+    /// Returns the hash function used for the strong checksums.
+    pub fn hash(&self) -> StrongHash {
+        self.hash
+    }
+
+    /// Returns the length of the blocks, in bytes.
+    pub fn block_len(&self) -> u32 {
+        self.block_len
+    }
+
+    /// Returns the length of the strong checksums, in bytes.
+    pub fn strong_len(&self) -> u32 {
+        self.strong_len
+    }
+
+    /// Returns the number of blocks in the signature.
+    pub fn num_blocks(&self) -> usize {
+        self.weak.len()
+    }
+
+    /// Returns the checksums of the given block, if present.
+    pub fn block(&self, index: usize) -> Option<BlockSums<'_>> {
+        self.weak.get(index).map(|&weak| BlockSums {
+            weak,
+            strong: self.strong(index),
+        })
+    }
+
+    /// Returns an iterator over the checksums of all the blocks, in file order.
+    pub fn blocks(&self) -> impl Iterator<Item = BlockSums<'_>> {
+        (0..self.num_blocks()).map(move |i| BlockSums {
+            weak: self.weak[i],
+            strong: self.strong(i),
+        })
+    }
+
+    /// Returns the minimum and maximum size of the file, in bytes.
+    ///
+    /// Only the last block of a file can be shorter than the block length.
+    pub fn size_hint(&self) -> (usize, usize) {
+        let block_len = self.block_len as usize;
+        let max = block_len * self.num_blocks();
+        if max > 0 {
+            (max - block_len + 1, max)
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// Computes the strong checksum of the given data, as stored in the signature.
+    pub fn strong_sum(&self, data: &[u8]) -> Vec<u8> {
+        let mut digest = self.hash.digest(data);
+        digest.truncate(self.strong_len as usize);
+        digest
+    }
+
+    /// Returns the indexes of the blocks of the given data that differ from the ones in the
+    /// signature.
+    ///
+    /// Blocks present only in the data or only in the signature are reported as changed, so
+    /// that an empty result means that the data has the same content as the original file
+    /// (unless checksums collide).
+    pub fn changed_blocks<R: Read>(&self, mut data: R) -> io::Result<Vec<usize>> {
+        let mut result = Vec::new();
+        let mut block = Vec::with_capacity(self.block_len as usize);
+        let mut index = 0;
+        loop {
+            block.clear();
+            (&mut data)
+                .take(u64::from(self.block_len))
+                .read_to_end(&mut block)?;
+            if block.is_empty() {
+                break;
+            }
+            let unchanged = match self.block(index) {
+                Some(sums) => {
+                    let mut sum = Rollsum::new();
+                    sum.update(&block);
+                    sum.digest() == sums.weak && self.strong_sum(&block) == sums.strong
+                }
+                None => false,
+            };
+            if !unchanged {
+                result.push(index);
+            }
+            index += 1;
+        }
+        result.extend(index..self.num_blocks());
+        Ok(result)
+    }
+
+    /// Encodes the signature in the librsync format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(12 + self.weak.len() * (4 + self.strong_len as usize));
+        result.write_u32::<BigEndian>(self.hash.magic()).unwrap();
+        result.write_u32::<BigEndian>(self.block_len).unwrap();
+        result.write_u32::<BigEndian>(self.strong_len).unwrap();
+        for block in self.blocks() {
+            result.write_u32::<BigEndian>(block.weak).unwrap();
+            result.extend_from_slice(block.strong);
+        }
+        result
+    }
+
+    fn strong(&self, index: usize) -> &[u8] {
+        let len = self.strong_len as usize;
+        &self.strong[index * len..(index + 1) * len]
+    }
+}
+
+impl<'a> BlockSums<'a> {
+    /// Returns the rolling checksum of the block.
+    pub fn weak(&self) -> u32 {
+        self.weak
+    }
+
+    /// Returns the (truncated) strong checksum of the block.
+    pub fn strong(&self) -> &'a [u8] {
+        self.strong
+    }
+}
+
+impl DeltaLen {
+    /// Creates a parser for an empty delta.
+    pub fn new() -> Self {
+        DeltaLen::default()
+    }
+
+    /// Parses the given part of the delta.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if the delta is malformed.
+    pub fn update(&mut self, mut data: &[u8]) -> io::Result<()> {
+        loop {
+            let skip = self.skip.min(data.len() as u64);
+            data = &data[skip as usize..];
+            self.skip -= skip;
+            let (&b, rest) = match data.split_first() {
+                Some(split) => split,
+                None => return Ok(()),
+            };
+            if self.done {
+                return Err(invalid_data("data after the end of the delta"));
+            }
+            data = rest;
+            self.buf.push(b);
+            if !self.magic {
+                if self.buf.len() == 4 {
+                    if self.buf[..] != DELTA_MAGIC.to_be_bytes() {
+                        return Err(invalid_data("invalid delta magic number"));
+                    }
+                    self.magic = true;
+                    self.buf.clear();
+                }
+            } else {
+                self.parse_command()?;
+            }
+        }
+    }
+
+    /// Returns the length of the new file.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if the delta is incomplete.
+    pub fn finish(self) -> io::Result<u64> {
+        if self.done {
+            Ok(self.len)
+        } else {
+            Err(invalid_data("truncated delta"))
+        }
+    }
+
+    // consumes the command in the buffer, if complete
+    fn parse_command(&mut self) -> io::Result<()> {
+        let op = self.buf[0];
+        let (widths, literal) = match op {
+            OP_END => {
+                self.done = true;
+                self.buf.clear();
+                return Ok(());
+            }
+            0x01..=0x40 => {
+                self.len += u64::from(op);
+                self.skip = u64::from(op);
+                self.buf.clear();
+                return Ok(());
+            }
+            0x41..=0x44 => ([1 << (op - OP_LITERAL_N1), 0], true),
+            0x45..=0x54 => {
+                let code = op - OP_COPY_N1_N1;
+                ([1 << (code / 4), 1 << (code % 4)], false)
+            }
+            _ => return Err(invalid_data("unsupported delta command")),
+        };
+        if self.buf.len() < 1 + widths[0] + widths[1] {
+            return Ok(());
+        }
+        let mut args = &self.buf[1..];
+        let first = args.read_uint::<BigEndian>(widths[0])?;
+        if literal {
+            self.len += first;
+            self.skip = first;
+        } else {
+            self.len += args.read_uint::<BigEndian>(widths[1])?;
+        }
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl Write for DeltaLen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// returns the number of bytes needed to encode the given integer, and the corresponding code
+// used in command opcodes
+fn int_width(n: u64) -> (usize, u8) {
+    if n <= 0xff {
+        (1, 0)
+    } else if n <= 0xffff {
+        (2, 1)
+    } else if n <= 0xffff_ffff {
+        (4, 2)
+    } else {
+        (8, 3)
+    }
+}
+
+fn write_int(out: &mut Vec<u8>, n: u64, width: usize) {
+    out.extend_from_slice(&n.to_be_bytes()[8 - width..]);
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::memory::fixture;
+    use flate2::read::GzDecoder;
+    use tar;
+
+    // reads the files in a tar archive of the single_vol backup, by joining multivol blocks
+    fn read_tar_files(name: &str, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        let data = fixture("single_vol").get(name).unwrap();
+        let mut tar = tar::Archive::new(GzDecoder::new(&data[..]));
+        let mut result: Vec<(String, Vec<u8>)> = Vec::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+            let name = if let Some(name) = path.strip_prefix(prefix) {
+                name.to_owned()
+            } else if let Some(name) = path.strip_prefix("multivol_snapshot/") {
+                name[..name.rfind('/').unwrap()].to_owned()
+            } else {
+                continue;
+            };
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            match result.last_mut() {
+                Some(&mut (ref last, ref mut last_data)) if *last == name => {
+                    last_data.extend_from_slice(&data)
+                }
+                _ => result.push((name, data)),
+            }
+        }
+        result
+    }
+
+    // applies a delta to the old data
+    fn patch(old: &[u8], mut delta: &[u8]) -> Vec<u8> {
+        fn read_int(delta: &mut &[u8], code: u8) -> usize {
+            delta.read_uint::<BigEndian>(1 << code).unwrap() as usize
+        }
+
+        assert_eq!(delta.read_u32::<BigEndian>().unwrap(), DELTA_MAGIC);
+        let mut result = Vec::new();
+        loop {
+            let op = delta.read_u8().unwrap();
+            match op {
+                OP_END => break,
+                0x01..=0x44 => {
+                    let len = if op <= 0x40 {
+                        op as usize
+                    } else {
+                        read_int(&mut delta, op - OP_LITERAL_N1)
+                    };
+                    result.extend_from_slice(&delta[..len]);
+                    delta = &delta[len..];
+                }
+                0x45..=0x54 => {
+                    let code = op - OP_COPY_N1_N1;
+                    let offset = read_int(&mut delta, code / 4);
+                    let len = read_int(&mut delta, code % 4);
+                    result.extend_from_slice(&old[offset..offset + len]);
+                }
+                _ => panic!("unexpected opcode {}", op),
+            }
+        }
+        assert!(delta.is_empty());
+        result
+    }
+
+    fn delta(old: &[u8], new: &[u8], block_len: u32) -> Vec<u8> {
+        let mut builder = SignatureBuilder::new(block_len);
+        builder.write_all(old).unwrap();
+        delta_with(RsSignature::parse(&builder.finish()).unwrap(), new)
+    }
+
+    fn delta_with(signature: RsSignature, new: &[u8]) -> Vec<u8> {
+        let mut delta = Vec::new();
+        // read in small chunks, to test the incremental output
+        let mut reader = Delta::new(signature, new);
+        let mut buf = [0; 100];
+        loop {
+            let len = reader.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            delta.extend_from_slice(&buf[..len]);
+        }
+        delta
+    }
+
+    // pseudo random data
+    fn random_data(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn delta_patch() {
+        let old = random_data(300_000, 1);
+        // unchanged
+        let d = delta(&old, &old, 512);
+        assert_eq!(patch(&old, &d), old);
+        assert!(d.len() < 20);
+        // insertions, deletions and changes
+        let mut new = old.clone();
+        new.splice(1000..1000, b"inserted data".iter().cloned());
+        new.drain(50_000..51_000);
+        for b in &mut new[200_000..200_100] {
+            *b = !*b;
+        }
+        new.extend_from_slice(&random_data(100_000, 2));
+        let d = delta(&old, &new, 512);
+        assert_eq!(patch(&old, &d), new);
+        assert!(d.len() < 110_000);
+        // completely different, and empty data
+        let new = random_data(70_000, 3);
+        assert_eq!(patch(&old, &delta(&old, &new, 1536)), new);
+        assert_eq!(patch(&old, &delta(&old, &[], 512)), b"");
+        assert_eq!(patch(&[], &delta(&[], &new, 512)), new);
+        // the last short block matches
+        let short = &old[..1000];
+        let d = delta(short, short, 512);
+        assert_eq!(patch(short, &d), short);
+        assert!(d.len() < 20);
+    }
+
+    #[test]
+    fn delta_len() {
+        let old = random_data(100_000, 6);
+        let mut new = random_data(3000, 7);
+        new.extend_from_slice(&old[5000..90_000]);
+        new.extend_from_slice(&random_data(70_000, 8));
+        for &(old, new) in &[(&old[..], &new[..]), (&[], &new[..]), (&old[..], &[])] {
+            let d = delta(old, new, 512);
+            // feed the delta in small chunks
+            let mut len = DeltaLen::new();
+            for chunk in d.chunks(7) {
+                len.update(chunk).unwrap();
+            }
+            assert_eq!(len.finish().unwrap(), new.len() as u64);
+        }
+        let d = delta(&old, &new, 512);
+        let mut len = DeltaLen::new();
+        len.update(&d[..d.len() - 1]).unwrap();
+        assert!(len.finish().is_err());
+        assert!(DeltaLen::new().update(b"rs\x016").is_err());
+    }
+
+    #[test]
+    fn rollsum() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        let mut sum = Rollsum::new();
+        sum.update(&data[..16]);
+        for i in 16..data.len() {
+            sum.rotate(data[i - 16], data[i]);
+            let mut expected = Rollsum::new();
+            expected.update(&data[i - 15..i + 1]);
+            assert_eq!(sum.digest(), expected.digest());
+        }
+        sum.roll_out(data[data.len() - 16]);
+        assert_eq!(sum.count(), 15);
+        let mut expected = Rollsum::new();
+        expected.update(&data[data.len() - 15..]);
+        assert_eq!(sum, expected);
+    }
+
+    #[test]
+    fn parse_signature() {
+        let old = random_data(5000, 4);
+        for &hash in &[StrongHash::Md4, StrongHash::Blake2] {
+            let mut builder = SignatureBuilder::with_hash(hash, 1024, hash.digest_len());
+            builder.write_all(&old).unwrap();
+            let bytes = builder.finish();
+            let signature = RsSignature::parse(&bytes).unwrap();
+            assert_eq!(signature.hash(), hash);
+            assert_eq!(signature.num_blocks(), 5);
+            assert_eq!(signature.size_hint(), (4097, 5120));
+            assert_eq!(signature.to_bytes(), bytes);
+            let last = signature.block(4).unwrap();
+            assert_eq!(last.strong(), &hash.digest(&old[4096..])[..]);
+            assert!(signature.block(5).is_none());
+
+            assert!(signature.changed_blocks(&old[..]).unwrap().is_empty());
+            let mut new = old.clone();
+            new[2000] ^= 1;
+            new.truncate(4096);
+            assert_eq!(signature.changed_blocks(&new[..]).unwrap(), vec![1, 4]);
+            new.extend_from_slice(&random_data(2000, 5));
+            assert_eq!(signature.changed_blocks(&new[..]).unwrap(), vec![1, 4, 5]);
+            let d = delta_with(signature, &new);
+            assert_eq!(patch(&old, &d), new);
+        }
+        // errors
+        assert!(RsSignature::parse(b"").is_err());
+        assert!(RsSignature::parse(&[0x72, 0x73, 0x01, 0x46, 0, 0, 2, 0, 0, 0, 0, 8]).is_err());
+        assert!(RsSignature::parse(&[0x72, 0x73, 0x01, 0x36, 0, 0, 2, 0, 0, 0, 0, 17]).is_err());
+        assert!(RsSignature::parse(&[0x72, 0x73, 0x01, 0x36, 0, 0, 2, 0, 0, 0, 0, 8, 1]).is_err());
+        let empty = RsSignature::parse(&[0x72, 0x73, 0x01, 0x37, 0, 0, 2, 0, 0, 0, 0, 8]).unwrap();
+        assert_eq!(empty.size_hint(), (0, 0));
+        assert_eq!(empty.changed_blocks(&b"x"[..]).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn parse_duplicity_signatures() {
+        let signatures = read_tar_files(
+            "duplicity-full-signatures.20150617T182545Z.sigtar.gz",
+            "signature/",
+        );
+        let files = read_tar_files(
+            "duplicity-full.20150617T182545Z.vol1.difftar.gz",
+            "snapshot/",
+        );
+        for (name, bytes) in signatures {
+            let signature = RsSignature::parse(&bytes).unwrap();
+            assert_eq!(signature.hash(), StrongHash::Md4);
+            assert_eq!(signature.strong_len(), DEFAULT_STRONG_LEN);
+            let data = &files.iter().find(|f| f.0 == name).unwrap().1;
+            let (min, max) = signature.size_hint();
+            assert!(min <= data.len() && data.len() <= max, "size of {}", name);
+            assert!(signature.changed_blocks(&data[..]).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn block_lengths() {
+        assert_eq!(block_len(0), 512);
+        assert_eq!(block_len(75650), 512);
+        assert_eq!(block_len(3_500_000), 1536);
+        assert_eq!(block_len(1 << 40), 2048);
+    }
+
+    #[test]
+    fn same_as_duplicity() {
+        // compute the signatures of the files contained in a full backup, and compare them
+        // with the ones produced by duplicity
+        let files = read_tar_files(
+            "duplicity-full.20150617T182545Z.vol1.difftar.gz",
+            "snapshot/",
+        );
+        let signatures = read_tar_files(
+            "duplicity-full-signatures.20150617T182545Z.sigtar.gz",
+            "signature/",
+        );
+        let mut checked = 0;
+        for (name, expected) in signatures {
+            let data = &files.iter().find(|f| f.0 == name).unwrap().1;
+            let mut builder = SignatureBuilder::new(block_len(data.len() as u64));
+            builder.write_all(data).unwrap();
+            assert_eq!(builder.finish(), expected, "signature of {}", name);
+            checked += 1;
+        }
+        assert!(checked > 10);
+    }
+}
@@ -3,30 +3,53 @@
 //! This sub-module exposes types to deal with duplicity signatures. It can be used to get
 //! information about files backupped in a backup chain.
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt::{self, Display, Formatter};
 use std::io::{self, Read};
 use std::iter::Iterator;
+use std::mem;
 use std::path::Path;
 use std::slice;
+use std::str;
 
 use flate2::read::GzDecoder;
+#[cfg(feature = "async")]
+use futures::io::AsyncReadExt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use tar;
 use time::Timespec;
 
 use crate::backend::Backend;
-use crate::collections::{SignatureChain, SignatureFile};
-use crate::rawpath::RawPath;
+use crate::collections::{BackupChain, BackupSet, SignatureChain, SignatureFile};
+use crate::not_found;
+use crate::rawpath::{self, RawPath};
+use crate::rsync::{DeltaLen, StrongHash};
 use crate::timefmt::TimeDisplay;
 
 /// Stores information about paths in a backup chain.
 ///
-/// The information is reused among different snapshots if possible.
+/// The information is reused among different snapshots if possible. Paths are stored one after
+/// the other in a single buffer, and the snapshots of all the paths in a single list, so that
+/// chains with millions of files do not need an allocation for each of them.
 #[derive(Debug)]
 pub struct Chain {
     num_snapshots: u8,
+    // the bytes of all the paths, including the targets of symbolic links
+    paths: Vec<u8>,
+    // the paths in the chain, sorted by components
     files: Vec<PathSnapshots>,
+    // the snapshots of all the paths, each one linked to the previous one of the same path
+    snapshots: Vec<PathSnapshot>,
+    // the information about the paths, shared by consecutive snapshots if unchanged
+    infos: Vec<PathInfo>,
     ug_map: UserGroupMap,
+    // the time of each snapshot, used to find its backup set
+    times: Vec<Timespec>,
+    // the snapshots whose volumes have already been scanned for file sizes
+    sized_snapshots: Vec<u8>,
 }
 
 /// Signatures for backup snapshots, in creation order.
@@ -61,13 +84,41 @@ pub struct SnapshotEntriesDisplay<'a>(SnapshotEntries<'a>);
 /// This could be a file, a directory, a link, etc.
 #[derive(Debug)]
 pub struct Entry<'a> {
-    path: &'a RawPath,
+    path: &'a [u8],
+    link: Option<&'a [u8]>,
     info: &'a PathInfo,
     ug_map: &'a UserGroupMap,
 }
 
+/// An owned version of `Entry`.
+///
+/// Contains a copy of all the information about the entry, so that it can be stored
+/// independently from the signature chain. It can be obtained by using `From<&Entry>`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OwnedEntry {
+    path: RawPath,
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    entry_type: EntryType,
+    mode: Option<u32>,
+    uid: Option<u64>,
+    gid: Option<u64>,
+    username: Option<String>,
+    groupname: Option<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::timefmt::serde_secs"))]
+    mtime: Timespec,
+    size_hint: Option<(usize, usize)>,
+    size: Option<usize>,
+    link: Option<RawPath>,
+}
+
 /// Type of entry in a backup snapshot.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum EntryType {
     /// A regular file.
     File,
@@ -92,31 +143,76 @@ enum DiffType {
     Deleted,
 }
 
-#[derive(Debug)]
+// a path in the paths buffer, with the position of its last snapshot
+#[derive(Copy, Clone, Debug)]
 struct PathSnapshots {
-    // the directory or file path
-    path: RawPath,
-    // all the snapshots for this path
-    snapshots: Vec<PathSnapshot>,
+    start: usize,
+    len: u32,
+    last: u32,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 struct PathSnapshot {
-    // info are None if the snapshot has deleted this path
-    info: Option<PathInfo>,
+    // the position of the path info, or `NONE` if the snapshot has deleted this path
+    info: u32,
+    // the position of the previous snapshot of the same path, or `NONE` for the first one
+    prev: u32,
     // the index of the snapshot in the chain
     index: u8,
 }
 
-#[derive(Debug)]
+// a missing position, used instead of `Option<u32>` to save memory
+const NONE: u32 = u32::MAX;
+
+// the optional fields of `PathInfo` are present only if the corresponding flag is set
+const HAS_UID: u8 = 1;
+const HAS_GID: u8 = 1 << 1;
+const HAS_MODE: u8 = 1 << 2;
+const HAS_SIZE_HINT: u8 = 1 << 3;
+const HAS_SIZE: u8 = 1 << 4;
+const HAS_LINK: u8 = 1 << 5;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 struct PathInfo {
-    mtime: Timespec,
-    uid: Option<u64>,
-    gid: Option<u64>,
-    mode: Option<u32>,
+    mtime: i64,
+    uid: u64,
+    gid: u64,
+    size_hint: (usize, usize),
+    // the exact size, if known
+    size: usize,
+    // the target of a symbolic link, in the paths buffer
+    link_start: usize,
+    link_len: u32,
+    mode: u32,
     entry_type: u8,
-    size_hint: Option<(usize, usize)>,
-    link: Option<RawPath>,
+    flags: u8,
+}
+
+// a signature file read by `stream_entries`, with the entry to be merged and the following one
+struct StreamSource<'a, R: Read> {
+    entries: tar::Entries<'a, R>,
+    file_name: &'a str,
+    current: StreamEntry,
+    next: StreamEntry,
+}
+
+// an entry read by `stream_entries`, with buffers reused for the following entries
+struct StreamEntry {
+    path: Vec<u8>,
+    link: Vec<u8>,
+    // info are None if the entry marks a deleted path
+    info: Option<PathInfo>,
+    // false if the signature file has ended
+    valid: bool,
+}
+
+// a path info being read from a signature file, before being added to the chain
+struct NewSnapshot {
+    // the path, in the buffer of the new paths
+    start: usize,
+    len: u32,
+    // info are None if the snapshot has deleted this path
+    info: Option<PathInfo>,
 }
 
 #[derive(Debug)]
@@ -128,13 +224,21 @@ struct UserGroupMap {
 #[derive(Debug)]
 struct ModeDisplay(Option<u32>);
 
+// displays a path in bytes, or "." for the root
+struct PathDisplay<'a>(&'a [u8]);
+
 impl Chain {
     /// Builds a new empty signature chain.
     pub fn new() -> Self {
         Chain {
             num_snapshots: 0,
+            paths: Vec::new(),
             files: Vec::new(),
+            snapshots: Vec::new(),
+            infos: Vec::new(),
             ug_map: UserGroupMap::new(),
+            times: Vec::new(),
+            sized_snapshots: Vec::new(),
         }
     }
 
@@ -157,6 +261,76 @@ impl Chain {
         Ok(chain)
     }
 
+    /// Opens a signature chain from signature chain files, by using an asynchronous backend.
+    ///
+    /// This is the asynchronous counterpart of `from_sigchain`. Each signature file is
+    /// downloaded in memory before being parsed, so the parsing logic is shared between the two.
+    #[cfg(feature = "async")]
+    pub async fn from_sigchain_async<B: crate::backend::AsyncBackend>(
+        coll: &SignatureChain,
+        backend: &B,
+    ) -> io::Result<Self> {
+        let mut chain = Chain::new();
+        let mut buf = Vec::new();
+        let sigfiles = Some(coll.full_signature())
+            .into_iter()
+            .chain(coll.inc_signatures());
+        for sigfile in sigfiles {
+            buf.clear();
+            let mut file = backend.open_file(sigfile.file_name.as_ref()).await?;
+            file.read_to_end(&mut buf).await?;
+            chain.add_sigfile(&buf[..], sigfile)?;
+        }
+        Ok(chain)
+    }
+
+    /// Adds to the chain the signatures appended to a signature chain since it has been opened.
+    ///
+    /// The chain must have been opened from an older version of `coll`. Only the new signature
+    /// files are read, so this is much faster than opening the chain again. If the signatures
+    /// already loaded are not the first ones of `coll` anymore, because some of them have been
+    /// replaced or removed, the chain is left unchanged and `false` is returned: in that case it
+    /// must be opened again.
+    ///
+    /// # Errors
+    /// Any error reading the new signature files is returned. The signatures added before the
+    /// error are kept.
+    pub fn update<B: Backend>(&mut self, coll: &SignatureChain, backend: &B) -> io::Result<bool> {
+        let sigfiles = match self.new_signatures(coll) {
+            Some(sigfiles) => sigfiles,
+            None => return Ok(false),
+        };
+        for sigfile in sigfiles {
+            let file = backend.open_file(sigfile.file_name.as_ref())?;
+            self.add_sigfile(file, sigfile)?;
+        }
+        Ok(true)
+    }
+
+    /// Adds to the chain the signatures appended to a signature chain, by using an asynchronous
+    /// backend.
+    ///
+    /// This is the asynchronous counterpart of `update`.
+    #[cfg(feature = "async")]
+    pub async fn update_async<B: crate::backend::AsyncBackend>(
+        &mut self,
+        coll: &SignatureChain,
+        backend: &B,
+    ) -> io::Result<bool> {
+        let sigfiles = match self.new_signatures(coll) {
+            Some(sigfiles) => sigfiles,
+            None => return Ok(false),
+        };
+        let mut buf = Vec::new();
+        for sigfile in sigfiles {
+            buf.clear();
+            let mut file = backend.open_file(sigfile.file_name.as_ref()).await?;
+            file.read_to_end(&mut buf).await?;
+            self.add_sigfile(&buf[..], sigfile)?;
+        }
+        Ok(true)
+    }
+
     /// Returns the snapshots present in the signature chain.
     pub fn snapshots(&self) -> Snapshots {
         Snapshots {
@@ -165,6 +339,107 @@ impl Chain {
         }
     }
 
+    /// Resolves the exact sizes of the regular files in the given snapshot.
+    ///
+    /// Signatures provide only a range for file sizes (see `Entry::size_hint`). This function
+    /// scans the volumes of the backup sets in which the files have been changed last, and
+    /// stores their exact sizes in the chain, so that they are returned by `Entry::size`. Only
+    /// tar headers and deltas are parsed: sizes of files stored as deltas are obtained from the
+    /// delta commands. The volumes of a set are scanned only once.
+    ///
+    /// The backup chain must be the one corresponding to this signature chain: each signature is
+    /// paired with the backup set ending at the same time.
+    ///
+    /// # Errors
+    /// An error is returned if a needed volume is missing, encrypted, or cannot be read. An
+    /// `InvalidInput` error is returned if the snapshot index is too big for a chain.
+    pub fn resolve_sizes<B: Backend>(
+        &mut self,
+        snapshot: usize,
+        chain: &BackupChain,
+        backend: &B,
+    ) -> io::Result<()> {
+        let snapshot = u8::try_from(snapshot).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "snapshot index out of range")
+        })?;
+        // find the sets needed
+        let mut needed = Vec::new();
+        for file in &self.files {
+            let last = match self.snapshot_at(file, snapshot) {
+                Some(last) if last.info != NONE => last,
+                _ => continue,
+            };
+            let info = &self.infos[last.info as usize];
+            if info.size().is_none()
+                && EntryType::new(info.entry_type) == EntryType::File
+                && !needed.contains(&last.index)
+                && !self.sized_snapshots.contains(&last.index)
+            {
+                needed.push(last.index);
+            }
+        }
+        needed.sort_unstable();
+        for index in needed {
+            let time = self.times[index as usize];
+            let set = Some(chain.full_set())
+                .into_iter()
+                .chain(chain.inc_sets())
+                .find(|set| set.end_time() == time)
+                .ok_or_else(|| not_found("missing backup set for signatures"))?;
+            let sizes = read_volume_sizes(set, backend)?;
+            for i in 0..self.files.len() {
+                let file = self.files[i];
+                let size = match sizes.get(self.path_bytes(file.start, file.len)) {
+                    Some(&size) => size,
+                    None => continue,
+                };
+                // the info of the following snapshot of the path
+                let mut next_info = NONE;
+                let mut pos = file.last;
+                while pos != NONE {
+                    let path_snapshot = self.snapshots[pos as usize];
+                    let mut info = path_snapshot.info;
+                    if path_snapshot.index == index
+                        && info != NONE
+                        && self.infos[info as usize].size().is_none()
+                    {
+                        let prev_info = self.snapshot_info(path_snapshot.prev);
+                        if next_info == info || prev_info == info {
+                            // the info is shared with other snapshots: copy it before changing
+                            self.infos.push(self.infos[info as usize].clone());
+                            info = to_u32(self.infos.len() - 1)?;
+                            self.snapshots[pos as usize].info = info;
+                        }
+                        self.infos[info as usize].set_size(size);
+                    }
+                    next_info = path_snapshot.info;
+                    pos = path_snapshot.prev;
+                }
+            }
+            self.sized_snapshots.push(index);
+        }
+        Ok(())
+    }
+
+    // the signatures of the given chain not loaded yet, or None if the ones already loaded are
+    // not the first ones of the chain
+    fn new_signatures<'a>(
+        &self,
+        coll: &'a SignatureChain,
+    ) -> Option<impl Iterator<Item = &'a SignatureFile> + 'a> {
+        let sigfiles = Some(coll.full_signature())
+            .into_iter()
+            .chain(coll.inc_signatures());
+        let loaded = sigfiles
+            .clone()
+            .map(|sigfile| sigfile.time)
+            .take(self.times.len());
+        if loaded.ne(self.times.iter().cloned()) {
+            return None;
+        }
+        Some(sigfiles.skip(self.times.len()))
+    }
+
     fn add_sigfile<R: Read>(&mut self, file: R, sigfile: &SignatureFile) -> io::Result<()> {
         let result = {
             let snapshot_id = self.num_snapshots;
@@ -180,6 +455,7 @@ impl Chain {
             // we do not need to cleanup the chain if someting went wrong, because if the
             // number of signatures is not updated, the change is not observable
             self.num_snapshots += 1;
+            self.times.push(sigfile.time);
         }
         result
     }
@@ -189,94 +465,176 @@ impl Chain {
         mut tar: tar::Archive<R>,
         snapshot_id: u8,
     ) -> io::Result<()> {
-        let mut new_files: Vec<PathSnapshots> = Vec::new();
-        {
-            let mut old_snapshots = self.files.iter_mut().peekable();
-            for tarfile in tar.entries()? {
-                // we can ignore paths with errors
-                // the only problem here is that we miss some change in the chain, but it is
-                // better than abort the whole signature
-                let mut tarfile = unwrap_or_continue!(tarfile);
-                let size_hint = compute_size_hint(&mut tarfile);
-                let path = &tarfile.path_bytes();
-                let (difftype, path) = unwrap_opt_or_continue!(parse_snapshot_path(&path));
-                let info = match difftype {
-                    DiffType::Signature | DiffType::Snapshot => {
-                        let header = tarfile.header();
-                        let time = Timespec::new(header.mtime().unwrap_or(0) as i64, 0);
-                        if let (Ok(uid), Ok(Some(name))) = (header.uid(), header.username()) {
-                            self.ug_map.add_user(uid, name.to_owned());
-                        }
-                        if let (Ok(gid), Ok(Some(name))) = (header.gid(), header.groupname()) {
-                            self.ug_map.add_group(gid, name.to_owned());
-                        }
-                        let link = tarfile
-                            .link_name_bytes()
-                            .map(|b| RawPath::from_bytes(b.into_owned()));
-                        Some(PathInfo {
-                            mtime: time,
-                            uid: header.uid().ok(),
-                            gid: header.gid().ok(),
-                            mode: header.mode().ok(),
-                            size_hint: size_hint,
-                            entry_type: tarfile.header().entry_type().as_byte(),
-                            link: link,
-                        })
-                    }
-                    _ => None,
-                };
-                let new_snapshot = PathSnapshot {
-                    info: info,
-                    index: snapshot_id,
-                };
-                // find the current path in the old snapshots
-                // note: they are ordered
-                let position = {
-                    let mut position: Option<&mut PathSnapshots> = None;
-                    loop {
-                        let mut found = false;
-                        if let Some(path_snapshots) = old_snapshots.peek() {
-                            let old_path = path_snapshots.path.as_bytes();
-                            if old_path == path {
-                                // this path is already present in old snapshots: update them
-                                found = true;
-                            } else if old_path > path {
-                                // we've already reached the first item next to the current path
-                                // so, the path is not present in old snapshots
-                                break;
-                            }
-                        }
-                        if found {
-                            let path_snapshots = old_snapshots.next().unwrap();
-                            position = Some(path_snapshots);
-                        } else if !old_snapshots.next().is_some() {
-                            // we have not found the element, so 'old_path < path' or there are no
-                            // more paths to check:
-                            // continue the loop if there are more elements
-                            break;
-                        }
-                    }
-                    position
-                };
-                if let Some(path_snapshots) = position {
-                    path_snapshots.snapshots.push(new_snapshot);
+        // read the new snapshots, with their paths and link targets in separate buffers
+        let mut new_paths = Vec::new();
+        let mut new_links = Vec::new();
+        let mut new_snapshots = Vec::new();
+        for tarfile in tar.entries()? {
+            // we can ignore paths with errors
+            // the only problem here is that we miss some change in the chain, but it is
+            // better than abort the whole signature
+            let mut tarfile = unwrap_or_continue!(tarfile);
+            let start = new_paths.len();
+            let info = unwrap_opt_or_continue!(read_sig_entry(
+                &mut tarfile,
+                &mut self.ug_map,
+                &mut new_paths,
+                &mut new_links,
+                None,
+            ));
+            new_snapshots.push(NewSnapshot {
+                start,
+                len: to_u32(new_paths.len() - start)?,
+                info,
+            });
+        }
+        // signatures are usually sorted already
+        let new_path = |s: &NewSnapshot| &new_paths[s.start..s.start + s.len as usize];
+        let sorted = new_snapshots
+            .windows(2)
+            .all(|w| cmp_paths(new_path(&w[0]), new_path(&w[1])) != Ordering::Greater);
+        if !sorted {
+            new_snapshots.sort_by(|a, b| cmp_paths(new_path(a), new_path(b)));
+        }
+
+        // add the snapshots to the paths already present, and collect the new paths
+        let mut added = Vec::new();
+        let mut pos = 0;
+        let mut new_snapshots = new_snapshots.into_iter().peekable();
+        while let Some(new_snapshot) = new_snapshots.next() {
+            let path = new_path(&new_snapshot);
+            if let Some(next) = new_snapshots.peek() {
+                if new_path(next) == path {
+                    // the same path is present twice: the last one wins
+                    continue;
+                }
+            }
+            // the paths are sorted, so we can continue from the previous position
+            while pos < self.files.len() && cmp_paths(self.file_path(pos), path) == Ordering::Less {
+                pos += 1;
+            }
+            if pos < self.files.len() && self.file_path(pos) == path {
+                let last = self.files[pos].last;
+                self.files[pos].last =
+                    self.add_snapshot(new_snapshot.info, &new_links, last, snapshot_id)?;
+            } else {
+                let start = self.paths.len();
+                self.paths.extend_from_slice(path);
+                let last = self.add_snapshot(new_snapshot.info, &new_links, NONE, snapshot_id)?;
+                added.push(PathSnapshots {
+                    start,
+                    len: new_snapshot.len,
+                    last,
+                });
+            }
+        }
+        self.merge_files(&added);
+        Ok(())
+    }
+
+    // adds a snapshot following the given one, and returns its position
+    //
+    // The link target of the info is in the given buffer, and it is copied into the paths only if
+    // the info is not shared with the previous snapshot.
+    fn add_snapshot(
+        &mut self,
+        info: Option<PathInfo>,
+        new_links: &[u8],
+        prev: u32,
+        index: u8,
+    ) -> io::Result<u32> {
+        let info = match info {
+            Some(mut info) => {
+                let link = info
+                    .link()
+                    .map(|(start, len)| &new_links[start..start + len as usize]);
+                // reuse the info of the previous snapshot, if it did not change
+                let prev_info = self.snapshot_info(prev);
+                if prev_info != NONE && self.same_info(&self.infos[prev_info as usize], &info, link)
+                {
+                    prev_info
                 } else {
-                    // the path is not present in the old snapshots: add to new list
-                    new_files.push(PathSnapshots {
-                        path: RawPath::from_bytes(path.to_owned()),
-                        snapshots: vec![new_snapshot],
-                    });
+                    if let Some(link) = link {
+                        let start = self.paths.len();
+                        self.paths.extend_from_slice(link);
+                        info.set_link(start, to_u32(link.len())?);
+                    }
+                    self.infos.push(info);
+                    to_u32(self.infos.len() - 1)?
                 }
             }
+            None => NONE,
+        };
+        self.snapshots.push(PathSnapshot { info, prev, index });
+        to_u32(self.snapshots.len() - 1)
+    }
+
+    // compares an info of the chain with a new one, whose link target is given separately
+    fn same_info(&self, info: &PathInfo, new_info: &PathInfo, new_link: Option<&[u8]>) -> bool {
+        let link = info.link().map(|(start, len)| self.path_bytes(start, len));
+        link == new_link
+            && PathInfo {
+                link_start: 0,
+                ..info.clone()
+            } == PathInfo {
+                link_start: 0,
+                ..new_info.clone()
+            }
+    }
+
+    // merges the sorted new paths into the sorted paths of the chain, in place and in linear
+    // time, starting from the end
+    fn merge_files(&mut self, added: &[PathSnapshots]) {
+        let paths = &self.paths;
+        let path = |f: &PathSnapshots| &paths[f.start..f.start + f.len as usize];
+        let files = &mut self.files;
+        let mut old = files.len();
+        let mut new = added.len();
+        files.extend_from_slice(added);
+        let mut dest = files.len();
+        while new > 0 {
+            dest -= 1;
+            if old > 0
+                && cmp_paths(path(&files[old - 1]), path(&added[new - 1])) == Ordering::Greater
+            {
+                old -= 1;
+                files[dest] = files[old];
+            } else {
+                new -= 1;
+                files[dest] = added[new];
+            }
+        }
+    }
+
+    // the last snapshot of the path, up to the given snapshot index
+    fn snapshot_at(&self, file: &PathSnapshots, index: u8) -> Option<&PathSnapshot> {
+        let mut pos = file.last;
+        while pos != NONE {
+            let path_snapshot = &self.snapshots[pos as usize];
+            if path_snapshot.index <= index {
+                return Some(path_snapshot);
+            }
+            pos = path_snapshot.prev;
         }
-        // merge the new files with old snapshots
-        if !new_files.is_empty() {
-            // TODO: Performance hurt here: we have two sorted arrays to merge,
-            // better to use this algorithm: http://stackoverflow.com/a/4553321/1667955
-            self.files.extend(new_files.into_iter());
-            self.files.sort_by(|a, b| a.path.cmp(&b.path));
+        None
+    }
+
+    // the info of the snapshot at the given position, if any
+    fn snapshot_info(&self, pos: u32) -> u32 {
+        if pos == NONE {
+            NONE
+        } else {
+            self.snapshots[pos as usize].info
         }
-        Ok(())
+    }
+
+    fn file_path(&self, pos: usize) -> &[u8] {
+        let file = &self.files[pos];
+        self.path_bytes(file.start, file.len)
+    }
+
+    fn path_bytes(&self, start: usize, len: u32) -> &[u8] {
+        &self.paths[start..start + len as usize]
     }
 }
 
@@ -297,13 +655,10 @@ impl<'a> Iterator for Snapshots<'a> {
     }
 
     fn nth(&mut self, n: usize) -> Option<Snapshot<'a>> {
-        use std::u8;
-
         // check for u8 overflow to be fool-proof
-        if n + self.snapshot_id as usize >= u8::MAX as usize {
-            return None;
-        }
-        let id = self.snapshot_id + n as u8;
+        let id = u8::try_from(n)
+            .ok()
+            .and_then(|n| self.snapshot_id.checked_add(n))?;
         if id < self.chain.num_snapshots {
             self.snapshot_id = id + 1;
             Some(Snapshot {
@@ -353,24 +708,20 @@ impl<'a> Iterator for SnapshotEntries<'a> {
     type Item = Entry<'a>;
 
     fn next(&mut self) -> Option<Entry<'a>> {
-        let index = self.index; // prevents borrow checker complains
-        for path_snapshots in &mut self.iter {
-            if let Some(s) = path_snapshots
-                .snapshots
-                .iter()
-                .rev()
-                .find(|s| s.index <= index)
-            {
-                // now we have a path info present in this snapshot
-                // if it is not deleted return it
-                if let Some(ref info) = s.info {
-                    return Some(Entry {
-                        path: &path_snapshots.path,
-                        info: info,
-                        ug_map: &self.chain.ug_map,
-                    });
-                }
-            }
+        let chain = self.chain;
+        for file in &mut self.iter {
+            // now we have a path info present in this snapshot
+            // if it is not deleted return it
+            let info = match chain.snapshot_at(file, self.index) {
+                Some(s) if s.info != NONE => &chain.infos[s.info as usize],
+                _ => continue,
+            };
+            return Some(Entry {
+                path: chain.path_bytes(file.start, file.len),
+                link: info.link().map(|(start, len)| chain.path_bytes(start, len)),
+                info,
+                ug_map: &chain.ug_map,
+            });
         }
         None
     }
@@ -397,52 +748,66 @@ impl<'a> Entry<'a> {
     /// The path could be `None` if it is not UTF-8 representable under Windows. In that case use
     /// the byte representation with `path_bytes`.
     pub fn path(&self) -> Option<&'a Path> {
-        self.path.as_path()
+        rawpath::path_from_bytes(self.path)
     }
 
     /// Returns the full path of the entry in bytes.
     pub fn path_bytes(&self) -> &'a [u8] {
-        self.path.as_bytes()
+        self.path
     }
 
     /// Returns the value of the owner's user ID field.
     pub fn userid(&self) -> Option<u64> {
-        self.info.uid
+        self.info.uid()
     }
 
     /// Returns the value of the group's user ID field.
     pub fn groupid(&self) -> Option<u64> {
-        self.info.gid
+        self.info.gid()
     }
 
     /// Returns the mode bits for this file.
     pub fn mode(&self) -> Option<u32> {
-        self.info.mode
+        self.info.mode()
     }
 
     /// Returns the name of the owner user.
     pub fn username(&self) -> Option<&'a str> {
-        self.info.uid.and_then(|uid| self.ug_map.get_user_name(uid))
+        self.info
+            .uid()
+            .and_then(|uid| self.ug_map.get_user_name(uid))
     }
 
     /// Returns the name of the group.
     pub fn groupname(&self) -> Option<&'a str> {
         self.info
-            .gid
+            .gid()
             .and_then(|gid| self.ug_map.get_group_name(gid))
     }
 
     /// Returns the last modification time.
     pub fn mtime(&self) -> Timespec {
-        self.info.mtime
+        Timespec::new(self.info.mtime, 0)
     }
 
     /// Returns a lower and upper bound in bytes on the entry size.
     ///
     /// Note that for directories, this returns a size of zero, even if on Linux directories are
-    /// often considered to have a 4096 bytes size.
+    /// often considered to have a 4096 bytes size. If the exact size is known (see `size`), both
+    /// bounds are equal to it.
     pub fn size_hint(&self) -> Option<(usize, usize)> {
-        self.info.size_hint
+        match self.info.size() {
+            Some(size) => Some((size, size)),
+            None => self.info.size_hint(),
+        }
+    }
+
+    /// Returns the exact size in bytes of the entry, if known.
+    ///
+    /// The size of regular files is known only after `Chain::resolve_sizes` has been called,
+    /// unless the file is stored entirely in the signatures.
+    pub fn size(&self) -> Option<usize> {
+        self.info.size()
     }
 
     /// Returns the type of the entry.
@@ -454,7 +819,7 @@ impl<'a> Entry<'a> {
     ///
     /// This will return some path only if this entry is a symbolic link.
     pub fn linked_path(&self) -> Option<&'a Path> {
-        self.info.link.as_ref().and_then(|p| p.as_path())
+        self.link.and_then(rawpath::path_from_bytes)
     }
 }
 
@@ -472,11 +837,100 @@ impl<'a> Display for Entry<'a> {
             self.mtime().into_local_display(),
             // handle special case for the root:
             // the path is empty, return "." instead
-            self.path
+            PathDisplay(self.path)
         )
     }
 }
 
+impl OwnedEntry {
+    /// Returns the full path of the entry.
+    ///
+    /// The path could be `None` if it is not UTF-8 representable under Windows. In that case use
+    /// the byte representation with `path_bytes`.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_path()
+    }
+
+    /// Returns the full path of the entry in bytes.
+    pub fn path_bytes(&self) -> &[u8] {
+        self.path.as_bytes()
+    }
+
+    /// Returns the value of the owner's user ID field.
+    pub fn userid(&self) -> Option<u64> {
+        self.uid
+    }
+
+    /// Returns the value of the group's user ID field.
+    pub fn groupid(&self) -> Option<u64> {
+        self.gid
+    }
+
+    /// Returns the mode bits for this file.
+    pub fn mode(&self) -> Option<u32> {
+        self.mode
+    }
+
+    /// Returns the name of the owner user.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// Returns the name of the group.
+    pub fn groupname(&self) -> Option<&str> {
+        self.groupname.as_deref()
+    }
+
+    /// Returns the last modification time.
+    pub fn mtime(&self) -> Timespec {
+        self.mtime
+    }
+
+    /// Returns a lower and upper bound in bytes on the entry size.
+    ///
+    /// See `Entry::size_hint`.
+    pub fn size_hint(&self) -> Option<(usize, usize)> {
+        self.size_hint
+    }
+
+    /// Returns the exact size in bytes of the entry, if known.
+    ///
+    /// See `Entry::size`.
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    /// Returns the type of the entry.
+    pub fn entry_type(&self) -> EntryType {
+        self.entry_type
+    }
+
+    /// Returns the path that this entry points to.
+    ///
+    /// This will return some path only if this entry is a symbolic link.
+    pub fn linked_path(&self) -> Option<&Path> {
+        self.link.as_ref().and_then(|p| p.as_path())
+    }
+}
+
+impl<'a, 'b> From<&'b Entry<'a>> for OwnedEntry {
+    fn from(entry: &'b Entry<'a>) -> Self {
+        OwnedEntry {
+            path: RawPath::from_bytes(entry.path.to_owned()),
+            entry_type: entry.entry_type(),
+            mode: entry.mode(),
+            uid: entry.userid(),
+            gid: entry.groupid(),
+            username: entry.username().map(str::to_owned),
+            groupname: entry.groupname().map(str::to_owned),
+            mtime: entry.mtime(),
+            size_hint: entry.size_hint(),
+            size: entry.size(),
+            link: entry.link.map(|link| RawPath::from_bytes(link.to_owned())),
+        }
+    }
+}
+
 impl EntryType {
     /// Creates a new entry type from a raw byte.
     ///
@@ -510,6 +964,91 @@ impl Display for EntryType {
     }
 }
 
+impl PathInfo {
+    fn new(mtime: i64, entry_type: u8) -> Self {
+        PathInfo {
+            mtime,
+            uid: 0,
+            gid: 0,
+            size_hint: (0, 0),
+            size: 0,
+            link_start: 0,
+            link_len: 0,
+            mode: 0,
+            entry_type,
+            flags: 0,
+        }
+    }
+
+    fn uid(&self) -> Option<u64> {
+        self.get(HAS_UID, self.uid)
+    }
+
+    fn gid(&self) -> Option<u64> {
+        self.get(HAS_GID, self.gid)
+    }
+
+    fn mode(&self) -> Option<u32> {
+        self.get(HAS_MODE, self.mode)
+    }
+
+    fn size_hint(&self) -> Option<(usize, usize)> {
+        self.get(HAS_SIZE_HINT, self.size_hint)
+    }
+
+    fn size(&self) -> Option<usize> {
+        self.get(HAS_SIZE, self.size)
+    }
+
+    fn link(&self) -> Option<(usize, u32)> {
+        self.get(HAS_LINK, (self.link_start, self.link_len))
+    }
+
+    fn set_owner(&mut self, uid: Option<u64>, gid: Option<u64>) {
+        if let Some(uid) = uid {
+            self.uid = uid;
+            self.flags |= HAS_UID;
+        }
+        if let Some(gid) = gid {
+            self.gid = gid;
+            self.flags |= HAS_GID;
+        }
+    }
+
+    fn set_mode(&mut self, mode: Option<u32>) {
+        if let Some(mode) = mode {
+            self.mode = mode;
+            self.flags |= HAS_MODE;
+        }
+    }
+
+    fn set_size_hint(&mut self, size_hint: Option<(usize, usize)>) {
+        if let Some(size_hint) = size_hint {
+            self.size_hint = size_hint;
+            self.flags |= HAS_SIZE_HINT;
+        }
+    }
+
+    fn set_size(&mut self, size: usize) {
+        self.size = size;
+        self.flags |= HAS_SIZE;
+    }
+
+    fn set_link(&mut self, start: usize, len: u32) {
+        self.link_start = start;
+        self.link_len = len;
+        self.flags |= HAS_LINK;
+    }
+
+    fn get<T>(&self, flag: u8, value: T) -> Option<T> {
+        if self.flags & flag != 0 {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
 impl UserGroupMap {
     pub fn new() -> Self {
         UserGroupMap {
@@ -535,6 +1074,19 @@ impl UserGroupMap {
     }
 }
 
+impl<'a> Display for PathDisplay<'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        if self.0.is_empty() {
+            write!(f, ".")
+        } else {
+            match str::from_utf8(self.0) {
+                Ok(s) => write!(f, "{}", s),
+                Err(_) => write!(f, "?"),
+            }
+        }
+    }
+}
+
 impl Display for ModeDisplay {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         // from octal permissions to rwx ls style
@@ -573,6 +1125,428 @@ impl Display for ModeDisplay {
     }
 }
 
+/// Reads the entries of a snapshot directly from the signature files of a chain.
+///
+/// Unlike `Chain`, which keeps all the snapshots of a chain in memory, this function merges the
+/// full signature with the incremental signatures up to the given snapshot while reading them,
+/// and calls `f` for each entry present in the snapshot, in the same order used by
+/// `SnapshotEntries`. Only the current entry of each signature file is kept in memory, and the
+/// signatures following the snapshot are not read at all. This is convenient for one-shot
+/// listings of huge chains.
+///
+/// # Errors
+/// Errors opening the signature files are returned, as well as a `NotFound` error if the
+/// snapshot is not in the chain. Signature files must be sorted by path, as duplicity writes
+/// them: an `InvalidData` error is returned otherwise.
+pub fn stream_entries<B, F>(
+    sigchain: &SignatureChain,
+    snapshot: usize,
+    backend: &B,
+    mut f: F,
+) -> io::Result<()>
+where
+    B: Backend,
+    F: FnMut(&Entry),
+{
+    let sigfiles: Vec<_> = Some(sigchain.full_signature())
+        .into_iter()
+        .chain(sigchain.inc_signatures().take(snapshot))
+        .collect();
+    if sigfiles.len() <= snapshot {
+        return Err(not_found("The signature chain is incomplete"));
+    }
+    let mut archives = Vec::with_capacity(sigfiles.len());
+    for sigfile in &sigfiles {
+        if sigfile.encrypted {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "encrypted signatures are not supported",
+            ));
+        }
+        let file = backend.open_file(Path::new(&sigfile.file_name))?;
+        let file: Box<dyn Read> = if sigfile.compressed {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        archives.push(tar::Archive::new(file));
+    }
+    let mut ug_map = UserGroupMap::new();
+    let mut sources = Vec::with_capacity(archives.len());
+    for (archive, sigfile) in archives.iter_mut().zip(&sigfiles) {
+        let mut source = StreamSource {
+            entries: archive.entries()?,
+            file_name: &sigfile.file_name,
+            current: StreamEntry::new(),
+            next: StreamEntry::new(),
+        };
+        source.next.read(&mut source.entries, &mut ug_map);
+        source.advance(&mut ug_map)?;
+        sources.push(source);
+    }
+
+    let mut path = Vec::new();
+    loop {
+        // find the smallest path: if more signatures contain it, the latest one wins
+        let mut latest: Option<usize> = None;
+        for (i, source) in sources.iter().enumerate() {
+            if !source.current.valid {
+                continue;
+            }
+            latest = match latest {
+                Some(l)
+                    if cmp_paths(&source.current.path, &sources[l].current.path)
+                        == Ordering::Greater =>
+                {
+                    Some(l)
+                }
+                _ => Some(i),
+            };
+        }
+        let latest = match latest {
+            Some(latest) => &sources[latest].current,
+            None => return Ok(()),
+        };
+        // skip the path if the latest signature has deleted it
+        if let Some(ref info) = latest.info {
+            f(&Entry {
+                path: &latest.path,
+                link: info
+                    .link()
+                    .map(|(start, len)| &latest.link[start..start + len as usize]),
+                info,
+                ug_map: &ug_map,
+            });
+        }
+        path.clear();
+        path.extend_from_slice(&latest.path);
+        for source in &mut sources {
+            if source.current.valid && source.current.path == path {
+                source.advance(&mut ug_map)?;
+            }
+        }
+    }
+}
+
+// reads the librsync signatures of the given paths from a sequence of signature files
+//
+// The signatures of the last snapshot in the sequence are returned; deleted paths are omitted.
+pub(crate) fn read_rsync_signatures<'a, B, I>(
+    backend: &B,
+    sigfiles: I,
+    paths: &HashSet<&[u8]>,
+) -> io::Result<HashMap<Vec<u8>, Vec<u8>>>
+where
+    B: Backend,
+    I: IntoIterator<Item = &'a SignatureFile>,
+{
+    let mut result = HashMap::new();
+    if paths.is_empty() {
+        return Ok(result);
+    }
+    for sigfile in sigfiles {
+        if sigfile.encrypted {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "encrypted signatures are not supported",
+            ));
+        }
+        let file = backend.open_file(Path::new(&sigfile.file_name))?;
+        let file: Box<dyn Read> = if sigfile.compressed {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut tar = tar::Archive::new(file);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let name = entry.path_bytes().into_owned();
+            let (difftype, path) = match parse_snapshot_path(&name) {
+                Some((difftype, path)) if paths.contains(path) => (difftype, path),
+                _ => continue,
+            };
+            if let DiffType::Signature = difftype {
+                let mut signature = Vec::new();
+                entry.read_to_end(&mut signature)?;
+                result.insert(path.to_owned(), signature);
+            } else {
+                result.remove(path);
+            }
+        }
+    }
+    Ok(result)
+}
+
+// reads the entries of a sequence of signature files, in order, with their librsync signatures
+//
+// The given function receives each path with its entry and signature (empty if the entry has
+// none), or `None` if the entry marks the path as deleted. Paths can be repeated, the last
+// occurrence giving their state in the last snapshot. Invalid entries are skipped, like when
+// loading a `Chain`.
+pub(crate) fn read_signature_entries<'a, B, I, F>(
+    backend: &B,
+    sigfiles: I,
+    mut f: F,
+) -> io::Result<()>
+where
+    B: Backend,
+    I: IntoIterator<Item = &'a SignatureFile>,
+    F: FnMut(&[u8], Option<(&Entry, &[u8])>) -> io::Result<()>,
+{
+    let mut ug_map = UserGroupMap::new();
+    let mut path = Vec::new();
+    let mut link = Vec::new();
+    let mut signature = Vec::new();
+    for sigfile in sigfiles {
+        if sigfile.encrypted {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "encrypted signatures are not supported",
+            ));
+        }
+        let file = backend.open_file(Path::new(&sigfile.file_name))?;
+        let file: Box<dyn Read> = if sigfile.compressed {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut tar = tar::Archive::new(file);
+        for tarfile in tar.entries()? {
+            let mut tarfile = tarfile?;
+            path.clear();
+            link.clear();
+            let info = unwrap_opt_or_continue!(read_sig_entry(
+                &mut tarfile,
+                &mut ug_map,
+                &mut path,
+                &mut link,
+                Some(&mut signature),
+            ));
+            match info {
+                Some(ref info) => {
+                    let entry = Entry {
+                        path: &path,
+                        link: info
+                            .link()
+                            .map(|(start, len)| &link[start..start + len as usize]),
+                        info,
+                        ug_map: &ug_map,
+                    };
+                    f(&path, Some((&entry, &signature)))?;
+                }
+                None => f(&path, None)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+// reads the sizes of the files stored in the volumes of a backup set
+//
+// Files stored entirely have the size of their content; the size of files stored as deltas is
+// computed from the delta commands.
+fn read_volume_sizes<B: Backend>(
+    set: &BackupSet,
+    backend: &B,
+) -> io::Result<HashMap<Vec<u8>, usize>> {
+    enum Content {
+        Snapshot(u64),
+        Diff(DeltaLen),
+    }
+
+    if set.is_encrypted() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "encrypted volumes are not supported",
+        ));
+    }
+    let mut files: HashMap<Vec<u8>, Content> = HashMap::new();
+    // volume paths start from one
+    for num in 1..set.num_volumes() {
+        let name = set
+            .volume_path(num)
+            .ok_or_else(|| not_found("missing backup volume"))?;
+        let file = backend.open_file(Path::new(name))?;
+        let file: Box<dyn Read> = if set.is_compressed() {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut tar = tar::Archive::new(file);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let name = entry.path_bytes().into_owned();
+            let (kind, path) = match parse_volume_path(&name) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            let content = files.entry(path.to_owned()).or_insert_with(|| match kind {
+                b"snapshot" => Content::Snapshot(0),
+                _ => Content::Diff(DeltaLen::new()),
+            });
+            match *content {
+                Content::Snapshot(ref mut size) => *size += entry.header().size()?,
+                Content::Diff(ref mut len) => {
+                    io::copy(&mut entry, len)?;
+                }
+            }
+        }
+    }
+    files
+        .into_iter()
+        .map(|(path, content)| {
+            let size = match content {
+                Content::Snapshot(size) => size,
+                Content::Diff(len) => len.finish()?,
+            };
+            Ok((path, size as usize))
+        })
+        .collect()
+}
+
+// parses the path of a file in a volume, returning the kind of content ("snapshot" or "diff")
+// and the path of the file; multivolume blocks are joined to their file
+fn parse_volume_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    let pos = path.iter().position(|&b| b == b'/')?;
+    let (kind, path) = (&path[..pos], &path[pos + 1..]);
+    match kind {
+        b"snapshot" | b"diff" => Some((kind, path.strip_suffix(b"/").unwrap_or(path))),
+        b"multivol_snapshot" | b"multivol_diff" => {
+            let pos = path.iter().rposition(|&b| b == b'/')?;
+            Some((&kind[b"multivol_".len()..], &path[..pos]))
+        }
+        _ => None,
+    }
+}
+
+// converts a position or a length to the compact form stored in the chain
+fn to_u32(n: usize) -> io::Result<u32> {
+    u32::try_from(n)
+        .ok()
+        .filter(|&n| n != NONE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "too many paths in the chain"))
+}
+
+// reads the path and the information of an entry in a signature file
+//
+// The path and the target of a symbolic link are appended to the given buffers. If a signature
+// buffer is given, it receives the librsync signature of the entry, or it is left empty if the
+// entry has none. Returns `None` if the entry is not valid, or `Some(None)` if the entry marks a
+// deleted path.
+fn read_sig_entry<R: Read>(
+    tarfile: &mut tar::Entry<R>,
+    ug_map: &mut UserGroupMap,
+    paths: &mut Vec<u8>,
+    links: &mut Vec<u8>,
+    signature: Option<&mut Vec<u8>>,
+) -> Option<Option<PathInfo>> {
+    let size_hint = match signature {
+        Some(signature) => {
+            signature.clear();
+            let (difftype, _) = parse_snapshot_path(&tarfile.path_bytes())?;
+            if matches!(difftype, DiffType::Signature) {
+                tarfile.read_to_end(signature).ok()?;
+                compute_size_hint_signature(&mut &signature[..], signature.len())
+            } else {
+                compute_size_hint(tarfile)
+            }
+        }
+        None => compute_size_hint(tarfile),
+    };
+    let path = &tarfile.path_bytes();
+    let (difftype, path) = parse_snapshot_path(path)?;
+    paths.extend_from_slice(path);
+    let info = match difftype {
+        DiffType::Signature | DiffType::Snapshot => {
+            let header = tarfile.header();
+            if let (Ok(uid), Ok(Some(name))) = (header.uid(), header.username()) {
+                ug_map.add_user(uid, name.to_owned());
+            }
+            if let (Ok(gid), Ok(Some(name))) = (header.gid(), header.groupname()) {
+                ug_map.add_group(gid, name.to_owned());
+            }
+            let mut info = PathInfo::new(
+                header.mtime().unwrap_or(0) as i64,
+                header.entry_type().as_byte(),
+            );
+            info.set_owner(header.uid().ok(), header.gid().ok());
+            info.set_mode(header.mode().ok());
+            info.set_size_hint(size_hint);
+            if let (DiffType::Snapshot, Some(hint)) = (difftype, size_hint) {
+                info.set_size(hint.0);
+            }
+            if let Some(link) = tarfile.link_name_bytes() {
+                let len = u32::try_from(link.len()).ok()?;
+                let start = links.len();
+                links.extend_from_slice(&link);
+                info.set_link(start, len);
+            }
+            Some(info)
+        }
+        DiffType::Deleted => None,
+    };
+    Some(info)
+}
+
+impl<'a, R: Read> StreamSource<'a, R> {
+    // moves to the following path in the signature file
+    fn advance(&mut self, ug_map: &mut UserGroupMap) -> io::Result<()> {
+        mem::swap(&mut self.current, &mut self.next);
+        self.next.read(&mut self.entries, ug_map);
+        // the same path is present twice: the last one wins
+        while self.next.valid && self.next.path == self.current.path {
+            mem::swap(&mut self.current, &mut self.next);
+            self.next.read(&mut self.entries, ug_map);
+        }
+        if self.current.valid
+            && self.next.valid
+            && cmp_paths(&self.current.path, &self.next.path) == Ordering::Greater
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The signature file {} is not sorted", self.file_name),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl StreamEntry {
+    fn new() -> Self {
+        StreamEntry {
+            path: Vec::new(),
+            link: Vec::new(),
+            info: None,
+            valid: false,
+        }
+    }
+
+    // reads the next valid entry of the signature file
+    fn read<R: Read>(&mut self, entries: &mut tar::Entries<R>, ug_map: &mut UserGroupMap) {
+        self.valid = false;
+        for tarfile in entries {
+            // entries with errors are ignored, as `Chain` does
+            let mut tarfile = unwrap_or_continue!(tarfile);
+            self.path.clear();
+            self.link.clear();
+            self.info = unwrap_opt_or_continue!(read_sig_entry(
+                &mut tarfile,
+                ug_map,
+                &mut self.path,
+                &mut self.link,
+                None,
+            ));
+            self.valid = true;
+            return;
+        }
+    }
+}
+
+// compares two paths by their components, which is the order used by duplicity
+fn cmp_paths(a: &[u8], b: &[u8]) -> Ordering {
+    a.split(|&c| c == b'/').cmp(b.split(|&c| c == b'/'))
+}
+
 fn parse_snapshot_path(path: &[u8]) -> Option<(DiffType, &[u8])> {
     // split the path in (first directory, the remaining path)
     // the first is the type, the remaining is the real path
@@ -600,7 +1574,10 @@ fn compute_size_hint<R: Read>(file: &mut tar::Entry<R>) -> Option<(usize, usize)
         difftype
     };
     match difftype {
-        DiffType::Signature => compute_size_hint_signature(file),
+        DiffType::Signature => {
+            let file_size = file.header().size().ok()? as usize;
+            compute_size_hint_signature(file, file_size)
+        }
         DiffType::Snapshot => compute_size_hint_snapshot(file),
         _ => None,
     }
@@ -610,12 +1587,12 @@ fn compute_size_hint<R: Read>(file: &mut tar::Entry<R>) -> Option<(usize, usize)
 ///
 /// This function returns the lower and upper bound of the file size in bytes. On error returns
 /// `None`.
-fn compute_size_hint_signature<R: Read>(file: &mut tar::Entry<R>) -> Option<(usize, usize)> {
+fn compute_size_hint_signature<R: Read>(file: &mut R, file_size: usize) -> Option<(usize, usize)> {
     use byteorder::{BigEndian, ReadBytesExt};
 
     // for signature file format see Docs.md
     let magic = file.read_u32::<BigEndian>().ok()?;
-    if magic != 0x72730136 {
+    if StrongHash::from_magic(magic).is_none() {
         None
     } else {
         // read the header
@@ -623,7 +1600,6 @@ fn compute_size_hint_signature<R: Read>(file: &mut tar::Entry<R>) -> Option<(usi
         let ss_len = file.read_u32::<BigEndian>().ok()? as usize;
         let sign_block_len_bytes = 4 + ss_len;
         // the remaining part of the file are blocks
-        let file_size = file.header().size().ok()? as usize;
         let num_blocks = (file_size - 8) / sign_block_len_bytes;
 
         let max_file_len = file_block_len_bytes * num_blocks;
@@ -644,7 +1620,7 @@ fn compute_size_hint_snapshot<R: Read>(file: &mut tar::Entry<R>) -> Option<(usiz
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::backend::local::LocalBackend;
+    use crate::backend::memory::fixture;
     use crate::backend::Backend;
     use crate::collections::Collections;
     use crate::timefmt::parse_time_str;
@@ -825,7 +1801,7 @@ mod test {
     }
 
     fn single_vol_files() -> Chain {
-        let backend = LocalBackend::new("tests/backups/single_vol");
+        let backend = fixture("single_vol");
         let filenames = backend.file_names().unwrap();
         let coll = Collections::from_filenames(filenames);
         Chain::from_sigchain(coll.signature_chains().next().unwrap(), &backend).unwrap()
@@ -891,6 +1867,175 @@ mod test {
         }
     }
 
+    #[test]
+    fn exact_sizes() {
+        let backend = fixture("single_vol");
+        let coll = Collections::from_filenames(backend.file_names().unwrap());
+        let chain = coll.backup_chains().next().unwrap();
+        let mut files = single_vol_files();
+        assert!(files
+            .snapshots()
+            .next()
+            .unwrap()
+            .files()
+            .all(|f| { f.size().is_none() || f.entry_type() != EntryType::File }));
+        for (i, expected) in single_vol_sizes().into_iter().enumerate() {
+            files.resolve_sizes(i, chain, &backend).unwrap();
+            let snapshot = files.snapshots().nth(i).unwrap();
+            let actual: Vec<_> = snapshot.files().map(|f| f.size().unwrap()).collect();
+            assert_eq!(actual, expected);
+            let hints: Vec<_> = snapshot.files().map(|f| f.size_hint().unwrap().1).collect();
+            assert_eq!(hints, expected);
+        }
+        // snapshot indexes are not truncated
+        let err = files.resolve_sizes(256, chain, &backend).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(files.snapshots().nth(256).is_none());
+        assert!(files.snapshots().skip(1).nth(usize::MAX).is_none());
+    }
+
+    // builds an uncompressed signature with the given paths and modification times
+    fn make_sigtar(entries: &[(&str, u64)]) -> Vec<u8> {
+        let mut tar = tar::Builder::new(Vec::new());
+        for &(path, mtime) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_path(path).unwrap();
+            header.set_size(0);
+            header.set_mtime(mtime);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+            tar.append(&header, &[][..]).unwrap();
+        }
+        tar.into_inner().unwrap()
+    }
+
+    #[test]
+    fn merge_signatures() {
+        let sigfile = |time| SignatureFile {
+            file_name: String::new(),
+            time: Timespec::new(time, 0),
+            compressed: false,
+            encrypted: false,
+            partial: false,
+        };
+        let mut chain = Chain::new();
+        // unsorted, with a repeated path
+        let full = make_sigtar(&[
+            ("snapshot/b", 1),
+            ("snapshot/a-b", 1),
+            ("snapshot/a/b", 1),
+            ("snapshot/a", 1),
+            ("snapshot/a", 2),
+        ]);
+        chain.add_sigfile(&full[..], &sigfile(10)).unwrap();
+        let inc = make_sigtar(&[
+            ("deleted/a-b", 0),
+            ("snapshot/a", 2),
+            ("snapshot/a/a", 3),
+            ("snapshot/b", 3),
+            ("snapshot/c", 3),
+        ]);
+        chain.add_sigfile(&inc[..], &sigfile(20)).unwrap();
+
+        let files: Vec<Vec<_>> = chain
+            .snapshots()
+            .map(|s| {
+                s.files()
+                    .map(|f| (str::from_utf8(f.path_bytes()).unwrap(), f.mtime().sec))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                vec![("a", 2), ("a/b", 1), ("a-b", 1), ("b", 1)],
+                vec![("a", 2), ("a/a", 3), ("a/b", 1), ("b", 3), ("c", 3)],
+            ]
+        );
+        // the unchanged info of "a" is shared between the snapshots
+        assert_eq!(chain.infos.len(), 7);
+    }
+
+    #[test]
+    fn share_links() {
+        let sigtar = |links: &[(&str, &str)]| {
+            let mut tar = tar::Builder::new(Vec::new());
+            for &(path, target) in links {
+                let mut header = tar::Header::new_gnu();
+                header.set_path(path).unwrap();
+                header.set_link_name(target).unwrap();
+                header.set_size(0);
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_cksum();
+                tar.append(&header, &[][..]).unwrap();
+            }
+            tar.into_inner().unwrap()
+        };
+        let sigfile = |time| SignatureFile {
+            file_name: String::new(),
+            time: Timespec::new(time, 0),
+            compressed: false,
+            encrypted: false,
+            partial: false,
+        };
+        let mut chain = Chain::new();
+        let full = sigtar(&[("snapshot/a", "target"), ("snapshot/b", "x")]);
+        chain.add_sigfile(&full[..], &sigfile(10)).unwrap();
+        let paths_len = chain.paths.len();
+        let inc = sigtar(&[("snapshot/a", "target"), ("snapshot/b", "y")]);
+        chain.add_sigfile(&inc[..], &sigfile(20)).unwrap();
+
+        let links: Vec<Vec<_>> = chain
+            .snapshots()
+            .map(|s| {
+                s.files()
+                    .map(|f| f.linked_path().unwrap().to_owned())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                vec![Path::new("target"), Path::new("x")],
+                vec![Path::new("target"), Path::new("y")],
+            ]
+        );
+        // the unchanged link of "a" is shared, and its target stored once
+        assert_eq!(chain.infos.len(), 3);
+        assert_eq!(chain.paths.len(), paths_len + 1);
+    }
+
+    #[test]
+    fn stream_unsorted() {
+        use crate::backend::memory::MemoryBackend;
+
+        let backend = MemoryBackend::new();
+        // repeated paths are fine, but unsorted ones cannot be merged
+        backend.insert(
+            "duplicity-full-signatures.20160108T223144Z.sigtar",
+            make_sigtar(&[("snapshot/a", 1), ("snapshot/a", 2), ("snapshot/b", 1)]),
+        );
+        backend.insert(
+            "duplicity-new-signatures.20160108T223144Z.to.20160108T223153Z.sigtar",
+            make_sigtar(&[("snapshot/b", 2), ("snapshot/a", 2)]),
+        );
+        let coll = Collections::from_filenames(backend.file_names().unwrap());
+        let sigchain = coll.signature_chains().next().unwrap();
+        let stream = |snapshot| {
+            let mut files = Vec::new();
+            stream_entries(sigchain, snapshot, &backend, |f| {
+                files.push((f.path_bytes().to_owned(), f.mtime().sec))
+            })
+            .map(|_| files)
+        };
+        assert_eq!(
+            stream(0).unwrap(),
+            vec![(b"a".to_vec(), 2), (b"b".to_vec(), 1)]
+        );
+        assert_eq!(stream(1).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(stream(2).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
     #[test]
     fn display() {
         // NOTE: this is actually not a proper test
@@ -925,4 +2070,32 @@ mod test {
         assert_eq!(mode_display(Some(0o7000)), "--S--S--T");
         assert_eq!(mode_display(Some(0o7111)), "--s--s--t");
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let files = single_vol_files();
+        let entries: Vec<OwnedEntry> = files
+            .snapshots()
+            .last()
+            .unwrap()
+            .files()
+            .map(|e| OwnedEntry::from(&e))
+            .collect();
+        let json = serde_json::to_string(&entries).unwrap();
+        let deserialized: Vec<OwnedEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, entries);
+
+        // non UTF-8 paths are encoded as bytes
+        let mut entry = entries[1].clone();
+        entry.path = RawPath::from_bytes(vec![b'a', 0xff]);
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(
+            json.starts_with(r#"{"path":[97,255],"type":"file""#),
+            "{}",
+            json
+        );
+        let deserialized: OwnedEntry = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.path_bytes(), b"a\xff");
+    }
 }
@@ -49,6 +49,33 @@ pub fn parse_time_str(s: &str) -> Option<Timespec> {
         .map(|tm| tm.to_timespec())
 }
 
+/// Parse a string representing a duplicity timestamp in the short format and returns a `Timespec`
+/// if all goes well.
+///
+/// The short format is used in file names by duplicity's `--short-filenames` option, and it is
+/// the number of seconds since the epoch in base 36. For example "9vy59c" represents the date
+/// `1988-12-11T15:20:00Z`.
+pub fn parse_short_time_str(s: &str) -> Option<Timespec> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return None;
+    }
+    i64::from_str_radix(s, 36)
+        .ok()
+        .map(|secs| Timespec::new(secs, 0))
+}
+
+/// Formats a timestamp as a duplicity time string, as used in backup file names.
+///
+/// The time is represented in the UTC time zone, e.g. "19881211T152000Z".
+pub fn format_time_str(time: Timespec) -> String {
+    time::strftime("%Y%m%dT%H%M%SZ", &time::at_utc(time)).unwrap()
+}
+
+/// Formats a timestamp as an RFC 3339 string in the UTC time zone, e.g. "1988-12-11T15:20:00Z".
+pub fn format_rfc3339(time: Timespec) -> String {
+    time::strftime("%Y-%m-%dT%H:%M:%SZ", &time::at_utc(time)).unwrap()
+}
+
 impl TimeDisplay for Timespec {
     type D = PrettyDisplay;
 
@@ -74,6 +101,23 @@ impl Display for PrettyDisplay {
     }
 }
 
+/// Serialization of times as seconds since the Unix epoch.
+///
+/// Duplicity times have a precision of one second. To be used with `#[serde(with = ...)]`.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use time::Timespec;
+
+    pub fn serialize<S: Serializer>(time: &Timespec, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(time.sec)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Timespec, D::Error> {
+        i64::deserialize(deserializer).map(|sec| Timespec::new(sec, 0))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -123,6 +167,23 @@ mod test {
         assert_eq!(tm.tm_sec, 0);
     }
 
+    #[test]
+    fn parse_short() {
+        let time = parse_short_time_str("9vy59c").unwrap();
+        assert_eq!(Some(time), parse_time_str("19881211t152000z"));
+        assert_eq!(parse_short_time_str("9VY59C"), Some(time));
+        assert_eq!(parse_short_time_str(""), None);
+        assert_eq!(parse_short_time_str("+9vy59c"), None);
+        assert_eq!(parse_short_time_str("9vy.59c"), None);
+    }
+
+    #[test]
+    fn format() {
+        let time = parse_time_str("19881211t152000z").unwrap();
+        assert_eq!(format_time_str(time), "19881211T152000Z");
+        assert_eq!(format_rfc3339(time), "1988-12-11T15:20:00Z");
+    }
+
     #[test]
     fn display_utc() {
         let time = move_to_this_year(time(1988, 12, 11, 15, 20, 0));
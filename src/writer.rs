@@ -0,0 +1,1381 @@
+//! Creation of new backups.
+//!
+//! This sub-module allows to write duplicity compatible backups through a `WritableBackend`.
+//! Backups are written compressed and not encrypted, as duplicity does with the
+//! `--no-encryption` option.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use ruplicity::Backup;
+//! use ruplicity::backend::local::LocalBackend;
+//! use ruplicity::writer::BackupWriter;
+//!
+//! let backend = LocalBackend::new("/path/to/backup");
+//! let writer = BackupWriter::new(&backend).volume_size(50 * 1024 * 1024);
+//! writer.full("/path/to/source", time::get_time()).unwrap();
+//! // later on, store only the changes
+//! writer.incremental("/path/to/source", time::get_time()).unwrap();
+//! let backup = Backup::new(backend).unwrap();
+//! assert_eq!(backup.snapshots().unwrap().into_iter().count(), 2);
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use tar::{EntryType, Header, HeaderMode};
+use time::Timespec;
+#[cfg(unix)]
+use users::{Groups, Users, UsersCache};
+
+use crate::backend::{FileWriter, WritableBackend};
+use crate::collections::{Collections, FileNaming};
+use crate::manifest::{Manifest, ManifestBuilder, Volume};
+use crate::rsync::{self, Delta, RsSignature, SignatureBuilder};
+use crate::signatures::{read_signature_entries, Entry as SigEntry, EntryType as SigEntryType};
+use crate::timefmt::format_time_str;
+
+/// The maximum size of a file block inside a volume.
+///
+/// Bigger files are split in multiple blocks, that can span different volumes.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// The size of the blocks of tar archives.
+const TAR_BLOCK_SIZE: u64 = 512;
+
+/// The size of the records written by Python tar files, used by duplicity for signatures.
+const TAR_RECORD_SIZE: u64 = 20 * TAR_BLOCK_SIZE;
+
+/// Writes backups by using a backend.
+#[derive(Debug)]
+pub struct BackupWriter<'a, B> {
+    backend: &'a B,
+    hostname: String,
+    volume_size: u64,
+    naming: FileNaming,
+}
+
+// a tar archive being written through a backend
+//
+// Blocks are written directly rather than through `tar::Builder`, because duplicity ends the
+// archives differently: volumes have no end marker, while signatures are padded to full records.
+struct TarWriter<W: Write> {
+    out: GzEncoder<HashWriter<W>>,
+    // the uncompressed length of the archive
+    len: u64,
+}
+
+// computes the size and the hash of the data written
+struct HashWriter<W> {
+    inner: W,
+    written: u64,
+    hash: Sha1,
+}
+
+// the volumes of a backup set
+struct Volumes<'a, B: WritableBackend> {
+    backend: &'a B,
+    prefix: String,
+    max_size: u64,
+    current: Option<OpenVolume<B::FileWriter>>,
+    volumes: Vec<Volume>,
+}
+
+struct OpenVolume<W: Write> {
+    tar: TarWriter<W>,
+    start: (Vec<u8>, Option<usize>),
+    end: (Vec<u8>, Option<usize>),
+}
+
+// the files of a backup set being written
+struct SetWriter<'a, B: WritableBackend> {
+    volumes: Volumes<'a, B>,
+    sigtar: TarWriter<B::FileWriter>,
+    owners: &'a Owners,
+}
+
+// computes the signature of the data read
+struct SigReader<'a, R> {
+    inner: R,
+    signature: &'a mut SignatureBuilder,
+}
+
+// a change to be stored in an incremental backup
+enum Change {
+    Snapshot(SourceEntry),
+    // a file stored as a delta against the librsync signature of its previous version
+    Diff(SourceEntry, Vec<u8>),
+    Deleted(Vec<u8>),
+}
+
+// the information about an entry of the previous snapshot
+struct OldEntry {
+    entry_type: SigEntryType,
+    mode: Option<u32>,
+    uid: Option<u64>,
+    gid: Option<u64>,
+    mtime: i64,
+    size_hint: Option<(usize, usize)>,
+    link: Option<Vec<u8>>,
+}
+
+// a file inside the source directory
+#[derive(Clone)]
+pub(crate) struct SourceEntry {
+    // the path relative to the source root, as used in archives
+    pub(crate) index: Vec<u8>,
+    pub(crate) path: PathBuf,
+    pub(crate) meta: Metadata,
+}
+
+// resolves user and group names for tar headers
+#[derive(Default)]
+pub(crate) struct Owners {
+    // the system users and groups, or `None` if names are not resolved
+    #[cfg(unix)]
+    system: Option<UsersCache>,
+}
+
+impl<'a, B: WritableBackend> BackupWriter<'a, B> {
+    /// Creates a new writer using the given backend.
+    ///
+    /// By default volumes are 200MB long, like in duplicity, and the hostname is read from the
+    /// system, if possible.
+    pub fn new(backend: &'a B) -> Self {
+        BackupWriter {
+            backend,
+            hostname: default_hostname(),
+            volume_size: 200 * 1024 * 1024,
+            naming: FileNaming::default(),
+        }
+    }
+
+    /// Sets the size of the volumes, in bytes.
+    ///
+    /// The size is not a strict limit: a volume is closed as soon as its size exceeds it.
+    pub fn volume_size(mut self, bytes: u64) -> Self {
+        self.volume_size = bytes;
+        self
+    }
+
+    /// Sets the hostname stored in the manifests.
+    pub fn hostname<S: Into<String>>(mut self, hostname: S) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Sets the prefixes of the written file names, which are also used to find the backup
+    /// chain continued by incremental backups.
+    pub fn naming(mut self, naming: &FileNaming) -> Self {
+        self.naming = naming.clone();
+        self
+    }
+
+    /// Writes a full backup of the given source directory, with the given snapshot time.
+    ///
+    /// The volumes, the signatures and the manifest are written in this order, so that the
+    /// snapshot is considered complete only when all its files are present. The written manifest
+    /// is returned.
+    ///
+    /// # Errors
+    /// Any error reading the source directory or writing through the backend aborts the backup.
+    /// The files already written are left in the backend, and can be removed by using the
+    /// `cleanup` module.
+    pub fn full<P: AsRef<Path>>(&self, source: P, time: Timespec) -> io::Result<Manifest> {
+        let source = source.as_ref();
+        let time_str = format_time_str(time);
+        let owners = Owners::from_system();
+        let mut set = SetWriter::new(
+            self,
+            self.archive_name(&format!("duplicity-full.{}", time_str)),
+            &self.signature_name(&format!("duplicity-full-signatures.{}.sigtar.gz", time_str)),
+            &owners,
+        )?;
+        walk_dir(source, &mut |entry| set.snapshot(entry))?;
+        let volumes = set.finish()?;
+        self.write_manifest(
+            &self.manifest_name(&format!("duplicity-full.{}.manifest", time_str)),
+            source,
+            volumes,
+        )
+    }
+
+    /// Writes an incremental backup of the given source directory, with the given snapshot
+    /// time.
+    ///
+    /// The backup is added to the last backup chain present in the backend. The source is
+    /// compared with the last snapshot of the chain, by using its signatures: new files and
+    /// changed entries are stored entirely, while changed files are stored as librsync deltas
+    /// against their previous version. Removed paths are marked as deleted. The written manifest
+    /// is returned.
+    ///
+    /// # Errors
+    /// A `NotFound` error is returned if there is no backup chain, or if its signatures are
+    /// missing. An `InvalidInput` error is returned if the given time does not follow the last
+    /// snapshot. Like for `full`, any other error aborts the backup.
+    pub fn incremental<P: AsRef<Path>>(&self, source: P, time: Timespec) -> io::Result<Manifest> {
+        let source = source.as_ref();
+        let collections =
+            Collections::from_file_infos_with(&self.naming, self.backend.file_infos()?);
+        let chain = collections
+            .backup_chains()
+            .last()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no full backup found"))?;
+        let prev_time = chain.end_time();
+        if time <= prev_time {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the backup time must follow the last snapshot",
+            ));
+        }
+        let sigchain = collections
+            .signature_chain_for(chain)
+            .filter(|s| s.end_time() == prev_time)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "missing signatures for the last backup chain",
+                )
+            })?;
+
+        // read the source first, so that the signatures are read only once and only the ones of
+        // the files stored as deltas are kept
+        let owners = Owners::from_system();
+        let mut entries = HashMap::new();
+        walk_dir(source, &mut |entry| {
+            let header = entry.header(&owners);
+            let link = entry.link()?;
+            entries.insert(entry.index.clone(), (entry.clone(), header, link));
+            Ok(())
+        })?;
+        let mut old = HashMap::new();
+        let sigfiles = Some(sigchain.full_signature())
+            .into_iter()
+            .chain(sigchain.inc_signatures());
+        read_signature_entries(self.backend, sigfiles, |path, sig_entry| {
+            match sig_entry {
+                Some((sig_entry, signature)) => {
+                    let old_entry = OldEntry::new(sig_entry);
+                    let is_diff = match entries.get(path) {
+                        Some((entry, header, link)) => {
+                            !signature.is_empty()
+                                && is_delta(entry, &old_entry)
+                                && old_entry.is_changed(entry, header, link.as_deref())
+                        }
+                        None => false,
+                    };
+                    let signature = if is_diff {
+                        Some(signature.to_owned())
+                    } else {
+                        None
+                    };
+                    old.insert(path.to_owned(), (old_entry, signature));
+                }
+                None => {
+                    old.remove(path);
+                }
+            }
+            Ok(())
+        })?;
+
+        // compare the source with the last snapshot
+        let mut changes = Vec::new();
+        for (index, (entry, header, link)) in entries {
+            match old.remove(&index) {
+                None => changes.push(Change::Snapshot(entry)),
+                Some((old_entry, signature)) => {
+                    if old_entry.is_changed(&entry, &header, link.as_deref()) {
+                        match signature {
+                            Some(signature) => changes.push(Change::Diff(entry, signature)),
+                            None => changes.push(Change::Snapshot(entry)),
+                        }
+                    }
+                }
+            }
+        }
+        changes.extend(old.into_keys().map(Change::Deleted));
+        changes.sort_by(|a, b| cmp_index(a.index(), b.index()));
+
+        let times = format!(
+            "{}.to.{}",
+            format_time_str(prev_time),
+            format_time_str(time)
+        );
+        let mut set = SetWriter::new(
+            self,
+            self.archive_name(&format!("duplicity-inc.{}", times)),
+            &self.signature_name(&format!("duplicity-new-signatures.{}.sigtar.gz", times)),
+            &owners,
+        )?;
+        for change in &changes {
+            match *change {
+                Change::Snapshot(ref entry) => set.snapshot(entry)?,
+                Change::Diff(ref entry, ref signature) => set.diff(entry, signature)?,
+                Change::Deleted(ref index) => set.deleted(index)?,
+            }
+        }
+        let volumes = set.finish()?;
+        self.write_manifest(
+            &self.manifest_name(&format!("duplicity-inc.{}.manifest", times)),
+            source,
+            volumes,
+        )
+    }
+
+    // the names of the files, with the configured prefixes
+    fn archive_name(&self, name: &str) -> String {
+        let naming = &self.naming;
+        format!(
+            "{}{}{}",
+            naming.file_prefix(),
+            naming.file_prefix_archive(),
+            name
+        )
+    }
+
+    fn manifest_name(&self, name: &str) -> String {
+        let naming = &self.naming;
+        format!(
+            "{}{}{}",
+            naming.file_prefix(),
+            naming.file_prefix_manifest(),
+            name
+        )
+    }
+
+    fn signature_name(&self, name: &str) -> String {
+        let naming = &self.naming;
+        format!(
+            "{}{}{}",
+            naming.file_prefix(),
+            naming.file_prefix_signature(),
+            name
+        )
+    }
+
+    fn write_manifest(
+        &self,
+        name: &str,
+        source: &Path,
+        volumes: Vec<Volume>,
+    ) -> io::Result<Manifest> {
+        let manifest = volumes
+            .into_iter()
+            .fold(
+                ManifestBuilder::new()
+                    .hostname(self.hostname.clone())
+                    .local_dir(os_bytes(source.as_os_str())),
+                ManifestBuilder::volume,
+            )
+            .build();
+        let mut file = self.backend.create_file(Path::new(name))?;
+        manifest.write_to(&mut file)?;
+        file.commit()?;
+        Ok(manifest)
+    }
+}
+
+impl<'a, B: WritableBackend> SetWriter<'a, B> {
+    fn new(
+        writer: &BackupWriter<'a, B>,
+        prefix: String,
+        sig_name: &str,
+        owners: &'a Owners,
+    ) -> io::Result<Self> {
+        Ok(SetWriter {
+            volumes: Volumes::new(writer.backend, prefix, writer.volume_size),
+            sigtar: TarWriter::new(writer.backend.create_file(Path::new(sig_name))?),
+            owners,
+        })
+    }
+
+    // stores the whole entry
+    fn snapshot(&mut self, entry: &SourceEntry) -> io::Result<()> {
+        let mut header = entry.header(self.owners);
+        if entry.meta.is_file() {
+            let mut signature = SignatureBuilder::new(rsync::block_len(entry.meta.len()));
+            let file = SigReader {
+                inner: File::open(&entry.path)?,
+                signature: &mut signature,
+            };
+            write_blocks(
+                &mut self.volumes,
+                &entry.index,
+                &mut header,
+                "snapshot",
+                file,
+            )?;
+            self.append_signature(entry, &mut header, signature)
+        } else {
+            let link = entry.link()?;
+            let name = prefixed(b"snapshot/", &entry.index);
+            let diff_name = if entry.index.is_empty() {
+                b"snapshot/./".to_vec()
+            } else {
+                dir_name(&entry.meta, name.clone())
+            };
+            self.volumes.append(&entry.index, None, false, |tar| {
+                tar.append_link(&mut header, &diff_name, link.as_ref(), io::empty())
+            })?;
+            self.sigtar.append_link(
+                &mut header,
+                &dir_name(&entry.meta, name),
+                link.as_ref(),
+                io::empty(),
+            )
+        }
+    }
+
+    // stores the differences between a regular file and its previous version
+    fn diff(&mut self, entry: &SourceEntry, old_signature: &[u8]) -> io::Result<()> {
+        let mut header = entry.header(self.owners);
+        let mut signature = SignatureBuilder::new(rsync::block_len(entry.meta.len()));
+        {
+            let file = SigReader {
+                inner: File::open(&entry.path)?,
+                signature: &mut signature,
+            };
+            let delta = Delta::new(RsSignature::parse(old_signature)?, file);
+            write_blocks(&mut self.volumes, &entry.index, &mut header, "diff", delta)?;
+        }
+        self.append_signature(entry, &mut header, signature)
+    }
+
+    fn deleted(&mut self, index: &[u8]) -> io::Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0);
+        header.set_size(0);
+        let name = prefixed(b"deleted/", index);
+        self.volumes.append(index, None, false, |tar| {
+            tar.append(&mut header, &name, io::empty())
+        })?;
+        self.sigtar.append(&mut header, &name, io::empty())
+    }
+
+    fn append_signature(
+        &mut self,
+        entry: &SourceEntry,
+        header: &mut Header,
+        signature: SignatureBuilder,
+    ) -> io::Result<()> {
+        let signature = signature.finish();
+        header.set_size(signature.len() as u64);
+        self.sigtar.append(
+            header,
+            &prefixed(b"signature/", &entry.index),
+            &signature[..],
+        )
+    }
+
+    fn finish(self) -> io::Result<Vec<Volume>> {
+        let volumes = self.volumes.finish()?;
+        self.sigtar.finish_records()?;
+        Ok(volumes)
+    }
+}
+
+impl<'a, R: Read> Read for SigReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.signature.update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+impl Change {
+    fn index(&self) -> &[u8] {
+        match *self {
+            Change::Snapshot(ref entry) | Change::Diff(ref entry, _) => &entry.index,
+            Change::Deleted(ref index) => index,
+        }
+    }
+}
+
+impl OldEntry {
+    fn new(entry: &SigEntry) -> Self {
+        OldEntry {
+            entry_type: entry.entry_type(),
+            mode: entry.mode(),
+            uid: entry.userid(),
+            gid: entry.groupid(),
+            mtime: entry.mtime().sec,
+            size_hint: entry.size_hint(),
+            link: entry.linked_path().map(|p| os_bytes(p.as_os_str())),
+        }
+    }
+
+    // compares the entry with the new version, the header of which has no size
+    fn is_changed(&self, entry: &SourceEntry, header: &Header, link: Option<&[u8]>) -> bool {
+        let entry_type = SigEntryType::new(header.entry_type().as_byte());
+        let size = entry.meta.len();
+        let size_changed = entry_type == SigEntryType::File
+            && match self.size_hint {
+                Some((min, max)) => size < min as u64 || size > max as u64,
+                None => true,
+            };
+        entry_type != self.entry_type
+            || self.mode.map(|m| m & 0o7777) != header.mode().ok().map(|m| m & 0o7777)
+            || self.uid != header.uid().ok()
+            || self.gid != header.gid().ok()
+            || Some(self.mtime) != header.mtime().ok().map(|t| t as i64)
+            || self.link.as_deref() != link
+            || size_changed
+    }
+}
+
+impl<W: FileWriter> TarWriter<W> {
+    fn new(file: W) -> Self {
+        TarWriter {
+            out: GzEncoder::new(HashWriter::new(file), Compression::default()),
+            len: 0,
+        }
+    }
+
+    fn written(&mut self) -> u64 {
+        self.out.get_ref().written
+    }
+
+    fn append<R: Read>(&mut self, header: &mut Header, name: &[u8], data: R) -> io::Result<()> {
+        self.append_link(header, name, None, data)
+    }
+
+    // appends an entry, by using GNU extensions for long names
+    fn append_link<R: Read>(
+        &mut self,
+        header: &mut Header,
+        name: &[u8],
+        link: Option<&Vec<u8>>,
+        data: R,
+    ) -> io::Result<()> {
+        let old = header.as_old_mut();
+        if name.len() > old.name.len() {
+            self.append_long_name(EntryType::GNULongName, name)?;
+        }
+        if let Some(link) = link {
+            if link.len() > old.linkname.len() {
+                self.append_long_name(EntryType::GNULongLink, link)?;
+            }
+        }
+        let old = header.as_old_mut();
+        copy_truncated(&mut old.name, name);
+        copy_truncated(&mut old.linkname, link.map_or(&[][..], |l| &l[..]));
+        set_cksum(header);
+        self.append_raw(header, data)
+    }
+
+    fn append_long_name(&mut self, tp: EntryType, name: &[u8]) -> io::Result<()> {
+        let mut header = Header::new_gnu();
+        copy_truncated(&mut header.as_old_mut().name, b"././@LongLink");
+        // all the numeric fields are zero, like in Python tar files
+        header.set_mode(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        header.set_device_major(0)?;
+        header.set_device_minor(0)?;
+        header.set_entry_type(tp);
+        header.set_size(name.len() as u64 + 1);
+        set_cksum(&mut header);
+        self.append_raw(&header, name.chain(&[0u8][..]))
+    }
+
+    // writes the header and the data, padded to a full block
+    fn append_raw<R: Read>(&mut self, header: &Header, mut data: R) -> io::Result<()> {
+        self.out.write_all(header.as_bytes())?;
+        let len = io::copy(&mut data, &mut self.out)?;
+        let padding = (TAR_BLOCK_SIZE - len % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+        self.write_zeros(padding)?;
+        self.len += TAR_BLOCK_SIZE + len + padding;
+        Ok(())
+    }
+
+    fn write_zeros(&mut self, len: u64) -> io::Result<()> {
+        io::copy(&mut io::repeat(0).take(len), &mut self.out).map(|_| ())
+    }
+
+    // writes the end marker of the archive, padded to a full record like in Python tar files,
+    // and returns the hash of the file
+    fn finish_records(mut self) -> io::Result<Vec<u8>> {
+        let len = self.len + 2 * TAR_BLOCK_SIZE;
+        let padding = (TAR_RECORD_SIZE - len % TAR_RECORD_SIZE) % TAR_RECORD_SIZE;
+        self.write_zeros(2 * TAR_BLOCK_SIZE + padding)?;
+        self.finish()
+    }
+
+    // ends the archive right after the last entry, and returns the hash of the file
+    fn finish(self) -> io::Result<Vec<u8>> {
+        let hash_writer = self.out.finish()?;
+        let hash = hash_writer.hash.finalize().to_vec();
+        hash_writer.inner.commit()?;
+        Ok(hash)
+    }
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        HashWriter {
+            inner,
+            written: 0,
+            hash: Sha1::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hash.update(&buf[..len]);
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a, B: WritableBackend> Volumes<'a, B> {
+    fn new(backend: &'a B, prefix: String, max_size: u64) -> Self {
+        Volumes {
+            backend,
+            prefix,
+            max_size,
+            current: None,
+            volumes: Vec::new(),
+        }
+    }
+
+    // appends a block of the given path, by using `f`
+    //
+    // `block` is the block number, for files split in multiple blocks, and `continues` tells
+    // whether more blocks for the same path will follow.
+    fn append<F>(
+        &mut self,
+        index: &[u8],
+        block: Option<usize>,
+        continues: bool,
+        f: F,
+    ) -> io::Result<()>
+    where
+        F: FnOnce(&mut TarWriter<B::FileWriter>) -> io::Result<()>,
+    {
+        if self.current.is_none() {
+            self.open_volume(index, block.filter(|&b| b > 1))?;
+        }
+        let full = {
+            let volume = self.current.as_mut().unwrap();
+            f(&mut volume.tar)?;
+            volume.end = (index_or_root(index), block.filter(|_| continues));
+            volume.tar.written() >= self.max_size
+        };
+        if full {
+            self.close_current()?;
+        }
+        Ok(())
+    }
+
+    fn open_volume(&mut self, index: &[u8], block: Option<usize>) -> io::Result<()> {
+        let name = format!("{}.vol{}.difftar.gz", self.prefix, self.volumes.len() + 1);
+        let file = self.backend.create_file(Path::new(&name))?;
+        let start = (index_or_root(index), block);
+        self.current = Some(OpenVolume {
+            tar: TarWriter::new(file),
+            end: start.clone(),
+            start,
+        });
+        Ok(())
+    }
+
+    fn close_current(&mut self) -> io::Result<()> {
+        if let Some(volume) = self.current.take() {
+            let hash = volume.tar.finish()?;
+            self.volumes.push(Volume::new(
+                volume.start,
+                volume.end,
+                "SHA1".to_owned(),
+                hash,
+            ));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<Vec<Volume>> {
+        if self.volumes.is_empty() && self.current.is_none() {
+            // like duplicity, write an empty volume if there are no changes
+            self.open_volume(b"", None)?;
+        }
+        self.close_current()?;
+        Ok(self.volumes)
+    }
+}
+
+impl SourceEntry {
+    pub(crate) fn header(&self, owners: &Owners) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&self.meta, HeaderMode::Complete);
+        header.set_size(0);
+        // like duplicity, keep only the permission bits, without the file type
+        if let Ok(mode) = header.mode() {
+            header.set_mode(mode & 0o7777);
+        }
+        owners.set_names(&mut header);
+        header
+    }
+
+    pub(crate) fn link(&self) -> io::Result<Option<Vec<u8>>> {
+        if self.meta.file_type().is_symlink() {
+            fs::read_link(&self.path).map(|p| Some(os_bytes(p.as_os_str())))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl Owners {
+    #[cfg(unix)]
+    fn from_system() -> Self {
+        Owners {
+            system: Some(UsersCache::new()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn from_system() -> Self {
+        Owners::default()
+    }
+
+    // sets the user and group names of the header from its ids, if known
+    #[cfg(unix)]
+    fn set_names(&self, header: &mut Header) {
+        let system = match self.system {
+            Some(ref system) => system,
+            None => return,
+        };
+        let id = |id: io::Result<u64>| id.ok().and_then(|id| u32::try_from(id).ok());
+        if let Some(user) = id(header.uid()).and_then(|uid| system.get_user_by_uid(uid)) {
+            if let Some(name) = user.name().to_str() {
+                let _ = header.set_username(name);
+            }
+        }
+        if let Some(group) = id(header.gid()).and_then(|gid| system.get_group_by_gid(gid)) {
+            if let Some(name) = group.name().to_str() {
+                let _ = header.set_groupname(name);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn set_names(&self, _header: &mut Header) {}
+}
+
+// writes the given data in the volumes, splitting it in blocks if necessary
+//
+// `kind` is the type of data stored: a "snapshot" or a "diff" of the file.
+fn write_blocks<B, R>(
+    volumes: &mut Volumes<B>,
+    index: &[u8],
+    header: &mut Header,
+    kind: &str,
+    mut data: R,
+) -> io::Result<()>
+where
+    B: WritableBackend,
+    R: Read,
+{
+    let mut block = read_block(&mut data)?;
+    let mut next = read_next_block(&mut data, &block)?;
+    if next.is_empty() {
+        // the whole data fits in a single block
+        header.set_size(block.len() as u64);
+        let name = prefixed(format!("{}/", kind).as_bytes(), index);
+        return volumes.append(index, None, false, |tar| {
+            tar.append(header, &name, &block[..])
+        });
+    }
+    let mut num = 1;
+    loop {
+        header.set_size(block.len() as u64);
+        let mut name = prefixed(format!("multivol_{}/", kind).as_bytes(), index);
+        name.extend_from_slice(format!("/{}", num).as_bytes());
+        let continues = !next.is_empty();
+        volumes.append(index, Some(num), continues, |tar| {
+            tar.append(header, &name, &block[..])
+        })?;
+        if !continues {
+            return Ok(());
+        }
+        block = next;
+        next = read_next_block(&mut data, &block)?;
+        num += 1;
+    }
+}
+
+// files changed since the previous snapshot are stored as deltas if they were files before
+fn is_delta(entry: &SourceEntry, old_entry: &OldEntry) -> bool {
+    entry.meta.is_file() && old_entry.entry_type == SigEntryType::File
+}
+
+// compares two paths by components, that is the order of the entries in backup archives
+pub(crate) fn cmp_index(a: &[u8], b: &[u8]) -> Ordering {
+    a.split(|&c| c == b'/').cmp(b.split(|&c| c == b'/'))
+}
+
+fn read_block<R: Read>(file: &mut R) -> io::Result<Vec<u8>> {
+    let mut block = Vec::with_capacity(BLOCK_SIZE);
+    file.take(BLOCK_SIZE as u64).read_to_end(&mut block)?;
+    Ok(block)
+}
+
+// reads the block following `prev`, if `prev` was not the last one
+fn read_next_block<R: Read>(file: &mut R, prev: &[u8]) -> io::Result<Vec<u8>> {
+    if prev.len() < BLOCK_SIZE {
+        Ok(Vec::new())
+    } else {
+        read_block(file)
+    }
+}
+
+// visits the root directory and all its content, in the order used by duplicity
+pub(crate) fn walk_dir<F>(root: &Path, f: &mut F) -> io::Result<()>
+where
+    F: FnMut(&SourceEntry) -> io::Result<()>,
+{
+    let meta = fs::metadata(root)?;
+    if !meta.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the backup source must be a directory",
+        ));
+    }
+    let root = SourceEntry {
+        index: Vec::new(),
+        path: root.to_owned(),
+        meta,
+    };
+    f(&root)?;
+    walk_children(&root, f)
+}
+
+fn walk_children<F>(dir: &SourceEntry, f: &mut F) -> io::Result<()>
+where
+    F: FnMut(&SourceEntry) -> io::Result<()>,
+{
+    let mut children = Vec::new();
+    for child in fs::read_dir(&dir.path)? {
+        let child = child?;
+        children.push((os_bytes(&child.file_name()), child.path()));
+    }
+    // sorting by name gives the same order of duplicity, that sorts by path components
+    children.sort();
+    for (name, path) in children {
+        let meta = fs::symlink_metadata(&path)?;
+        if !is_supported(&meta) {
+            continue;
+        }
+        let mut index = dir.index.clone();
+        if !index.is_empty() {
+            index.push(b'/');
+        }
+        index.extend_from_slice(&name);
+        let entry = SourceEntry { index, path, meta };
+        f(&entry)?;
+        if entry.meta.is_dir() {
+            walk_children(&entry, f)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_supported(meta: &Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    let tp = meta.file_type();
+    !tp.is_socket()
+}
+
+#[cfg(not(unix))]
+fn is_supported(meta: &Metadata) -> bool {
+    let tp = meta.file_type();
+    tp.is_file() || tp.is_dir() || tp.is_symlink()
+}
+
+#[cfg(unix)]
+pub(crate) fn os_bytes(s: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    s.as_bytes().to_owned()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn os_bytes(s: &OsStr) -> Vec<u8> {
+    s.to_string_lossy().into_owned().into_bytes()
+}
+
+// like duplicity, the hostname is the one of the system, if it can be found
+fn default_hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .or_else(|| env::var("HOSTNAME").ok().filter(|name| !name.is_empty()))
+        .unwrap_or_else(|| "localhost".to_owned())
+}
+
+// sets the checksum of the header as Python tar files do: six octal digits, a NUL and a space
+fn set_cksum(header: &mut Header) {
+    let bytes = header.as_mut_bytes();
+    bytes[148..156].copy_from_slice(b"        ");
+    let sum: u32 = bytes.iter().map(|&b| u32::from(b)).sum();
+    bytes[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+}
+
+fn prefixed(prefix: &[u8], index: &[u8]) -> Vec<u8> {
+    let mut name = prefix.to_vec();
+    name.extend_from_slice(index);
+    name
+}
+
+// directories are stored with a trailing slash
+fn dir_name(meta: &Metadata, mut name: Vec<u8>) -> Vec<u8> {
+    if meta.is_dir() && name.last() != Some(&b'/') {
+        name.push(b'/');
+    }
+    name
+}
+
+// manifests represent the root directory as "."
+fn index_or_root(index: &[u8]) -> Vec<u8> {
+    if index.is_empty() {
+        b".".to_vec()
+    } else {
+        index.to_vec()
+    }
+}
+
+fn copy_truncated(dst: &mut [u8], src: &[u8]) {
+    let len = src.len().min(dst.len());
+    dst[..len].copy_from_slice(&src[..len]);
+    for b in &mut dst[len..] {
+        *b = 0;
+    }
+}
+
+// sets the modification time of a file or a symbolic link in tests, by using `touch`, which
+// works with older toolchains too
+#[cfg(all(test, unix))]
+pub(crate) fn set_mtime(path: &Path, mtime: Timespec) {
+    let status = std::process::Command::new("touch")
+        .arg("-h")
+        .arg("-d")
+        .arg(time::at_utc(mtime).rfc3339().to_string())
+        .arg(path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::memory::{fixture, MemoryBackend};
+    use crate::signatures::EntryType as SigEntryType;
+    use crate::timefmt::parse_time_str;
+    use crate::Backup;
+    use flate2::read::GzDecoder;
+    use std::process;
+
+    // a temporary directory removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("ruplicity-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn make_source(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        let root = &dir.0;
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/small"), b"small file").unwrap();
+        fs::write(root.join("dir.txt"), b"").unwrap();
+        let large: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(root.join("large"), &large).unwrap();
+        let long_name = "a".repeat(150);
+        fs::write(root.join(&long_name), b"long name").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("dir/small", root.join("link")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn full_backup() {
+        let source = make_source("writer-full");
+        let backend = MemoryBackend::new();
+        let time = parse_time_str("20160108t223144z").unwrap();
+        let manifest = BackupWriter::new(&backend)
+            .hostname("host")
+            .full(&source.0, time)
+            .unwrap();
+        assert_eq!(manifest.hostname(), "host");
+        assert_eq!(manifest.last_volume_index(), 1);
+        assert!(backend.contains(Path::new("duplicity-full.20160108T223144Z.manifest")));
+        assert!(backend.contains(Path::new("duplicity-full.20160108T223144Z.vol1.difftar.gz")));
+
+        let backup = Backup::new(backend).unwrap();
+        let snapshots = backup.snapshots().unwrap();
+        let snapshot = snapshots.into_iter().next().unwrap();
+        assert_eq!(snapshot.time(), time);
+        assert_eq!(*snapshot.manifest().unwrap(), manifest);
+        let entries = snapshot.entries().unwrap();
+        let files: Vec<_> = entries
+            .as_signature()
+            .map(|e| {
+                (
+                    String::from_utf8_lossy(e.path_bytes()).into_owned(),
+                    e.entry_type(),
+                    e.size_hint(),
+                )
+            })
+            .collect();
+        let mut expected = vec![
+            ("".to_owned(), SigEntryType::Dir, Some((0, 0))),
+            ("a".repeat(150), SigEntryType::File, Some((0, 512))),
+            ("dir".to_owned(), SigEntryType::Dir, Some((0, 0))),
+            ("dir/small".to_owned(), SigEntryType::File, Some((0, 512))),
+            ("dir.txt".to_owned(), SigEntryType::File, Some((0, 0))),
+            (
+                "large".to_owned(),
+                SigEntryType::File,
+                Some((199_681, 200_192)),
+            ),
+        ];
+        if cfg!(unix) {
+            expected.push(("link".to_owned(), SigEntryType::SymLink, Some((0, 0))));
+        }
+        assert_eq!(files, expected);
+    }
+
+    // returns the names of the entries in the given tar archive
+    fn archive_entries(data: &[u8]) -> Vec<String> {
+        let mut tar = tar::Archive::new(GzDecoder::new(data));
+        tar.entries()
+            .unwrap()
+            .map(|e| String::from_utf8_lossy(&e.unwrap().path_bytes()).into_owned())
+            .collect()
+    }
+
+    fn archive_mode(data: &[u8], name: &str) -> u32 {
+        let mut tar = tar::Archive::new(GzDecoder::new(data));
+        let entry = tar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap())
+            .find(|e| &e.path_bytes()[..] == name.as_bytes())
+            .unwrap();
+        entry.header().mode().unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn incremental_backup() {
+        let source = make_source("writer-inc");
+        let root = &source.0;
+        let backend = MemoryBackend::new();
+        let writer = BackupWriter::new(&backend).hostname("host");
+        let time1 = parse_time_str("20160108t223144z").unwrap();
+        let time2 = parse_time_str("20160108t223159z").unwrap();
+        let time3 = parse_time_str("20160108t223209z").unwrap();
+        assert_eq!(
+            writer.incremental(root, time1).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        writer.full(root, time1).unwrap();
+        assert_eq!(
+            writer.incremental(root, time1).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        // change the source
+        let mut large = fs::read(root.join("large")).unwrap();
+        large[100_000] ^= 0xff;
+        large.extend_from_slice(b"appended");
+        fs::write(root.join("large"), &large).unwrap();
+        fs::remove_file(root.join("dir/small")).unwrap();
+        fs::write(root.join("dir/new"), b"new file").unwrap();
+        // make sure modification times are changed
+        set_mtime(&root.join("large"), Timespec::new(0, 0));
+        writer.incremental(root, time2).unwrap();
+        let prefix = "duplicity-inc.20160108T223144Z.to.20160108T223159Z";
+        let volume = backend
+            .get(Path::new(&format!("{}.vol1.difftar.gz", prefix)))
+            .unwrap();
+        let entries = archive_entries(&volume);
+        assert!(entries.contains(&"deleted/dir/small".to_owned()));
+        assert!(entries.contains(&"snapshot/dir/new".to_owned()));
+        assert!(entries.contains(&"diff/large".to_owned()));
+        assert!(!entries.contains(&"snapshot/dir.txt".to_owned()));
+        let signatures = backend
+            .get(Path::new(
+                "duplicity-new-signatures.20160108T223144Z.to.20160108T223159Z.sigtar.gz",
+            ))
+            .unwrap();
+        assert!(archive_entries(&signatures).contains(&"signature/large".to_owned()));
+        // deleted markers have no permissions, as in duplicity
+        assert_eq!(archive_mode(&volume, "deleted/dir/small"), 0);
+        assert_eq!(archive_mode(&signatures, "deleted/dir/small"), 0);
+
+        // a second incremental with no changes
+        let manifest = writer.incremental(root, time3).unwrap();
+        assert_eq!(manifest.last_volume_index(), 1);
+        let volume = backend
+            .get(Path::new(
+                "duplicity-inc.20160108T223159Z.to.20160108T223209Z.vol1.difftar.gz",
+            ))
+            .unwrap();
+        assert!(archive_entries(&volume).iter().all(|e| {
+            !e.starts_with("snapshot/") && !e.starts_with("diff/") && !e.starts_with("deleted/")
+        }));
+
+        let backup = Backup::new(backend).unwrap();
+        let snapshots = backup.snapshots().unwrap();
+        assert_eq!(snapshots.as_collections().backup_chains().count(), 1);
+        assert_eq!(snapshots.as_collections().signature_chains().count(), 1);
+        let times: Vec<_> = snapshots.into_iter().map(|s| s.time()).collect();
+        assert_eq!(times, vec![time1, time2, time3]);
+        for snapshot in backup.snapshots().unwrap().into_iter().skip(1) {
+            let entries = snapshot.entries().unwrap();
+            let files: Vec<_> = entries
+                .as_signature()
+                .map(|e| String::from_utf8_lossy(e.path_bytes()).into_owned())
+                .collect();
+            assert!(files.contains(&"dir/new".to_owned()));
+            assert!(!files.contains(&"dir/small".to_owned()));
+            let large = entries
+                .as_signature()
+                .find(|e| e.path_bytes() == b"large")
+                .unwrap();
+            assert_eq!(large.size_hint(), Some((199_681, 200_192)));
+            assert_eq!(large.mtime().sec, 0);
+        }
+    }
+
+    #[test]
+    fn custom_naming() {
+        let source = make_source("writer-naming");
+        let root = &source.0;
+        let backend = MemoryBackend::new();
+        let naming = FileNaming::new().prefix("hostA_").signature_prefix("sig_");
+        let writer = BackupWriter::new(&backend).hostname("host").naming(&naming);
+        let time1 = parse_time_str("20160108t223144z").unwrap();
+        let time2 = parse_time_str("20160108t223159z").unwrap();
+        let time3 = parse_time_str("20160108t223209z").unwrap();
+        writer.full(root, time1).unwrap();
+        // a more recent chain with other names must not be continued
+        BackupWriter::new(&backend).full(root, time2).unwrap();
+        writer.incremental(root, time3).unwrap();
+        for name in &[
+            "hostA_duplicity-inc.20160108T223144Z.to.20160108T223209Z.manifest",
+            "hostA_duplicity-inc.20160108T223144Z.to.20160108T223209Z.vol1.difftar.gz",
+            "hostA_sig_duplicity-new-signatures.20160108T223144Z.to.20160108T223209Z.sigtar.gz",
+        ] {
+            assert!(backend.contains(Path::new(name)), "missing {}", name);
+        }
+
+        let backup = Backup::with_naming(backend, &naming).unwrap();
+        let snapshots = backup.snapshots().unwrap();
+        assert_eq!(snapshots.as_collections().backup_chains().count(), 1);
+        let times: Vec<_> = snapshots.into_iter().map(|s| s.time()).collect();
+        assert_eq!(times, vec![time1, time3]);
+    }
+
+    #[test]
+    fn multiple_volumes() {
+        let source = make_source("writer-volumes");
+        let backend = MemoryBackend::new();
+        let time = parse_time_str("20160108t223144z").unwrap();
+        let manifest = BackupWriter::new(&backend)
+            .volume_size(1)
+            .full(&source.0, time)
+            .unwrap();
+        // one volume per block
+        let num_volumes = manifest.last_volume_index();
+        assert_eq!(num_volumes, if cfg!(unix) { 10 } else { 9 });
+        let large = manifest.first_volume_of_path(b"large").unwrap();
+        assert_eq!(manifest.last_volume_of_path(b"large"), Some(large + 3));
+        let vol = manifest.volume(large + 1).unwrap();
+        assert_eq!(vol.start_path_bytes(), b"large");
+        assert_eq!(vol.start_block(), Some(2));
+        assert_eq!(vol.end_block(), Some(2));
+        assert_eq!(manifest.volume(large + 3).unwrap().end_block(), None);
+
+        // the hashes are the SHA1 of the volume files
+        for i in 1..num_volumes + 1 {
+            let name = format!("duplicity-full.20160108T223144Z.vol{}.difftar.gz", i);
+            let data = backend.get(Path::new(&name)).unwrap();
+            assert_eq!(manifest.volume(i).unwrap().hash(), &Sha1::digest(&data)[..]);
+        }
+        let backup = Backup::new(backend).unwrap();
+        let snapshots = backup.snapshots().unwrap();
+        let chain = snapshots.as_collections().backup_chains().next().unwrap();
+        assert_eq!(chain.full_set().num_volumes(), num_volumes + 1);
+    }
+
+    // recreates the source of a full backup written by duplicity, from its single volume
+    #[cfg(unix)]
+    fn restore_full(volume: &[u8], root: &Path) {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let mut attributes = Vec::new();
+        let mut tar = tar::Archive::new(GzDecoder::new(volume));
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path_bytes().into_owned();
+            let pos = name.iter().position(|&b| b == b'/').unwrap();
+            let (kind, mut path) = (&name[..pos], &name[pos + 1..]);
+            if kind == b"multivol_snapshot" {
+                path = &path[..path.iter().rposition(|&b| b == b'/').unwrap()];
+            }
+            let path = path.strip_suffix(b"/").unwrap_or(path);
+            let path = if path == b"." {
+                root.to_path_buf()
+            } else {
+                root.join(OsStr::from_bytes(path))
+            };
+            let header = entry.header().clone();
+            match header.entry_type() {
+                EntryType::Directory => fs::create_dir_all(&path).unwrap(),
+                EntryType::Symlink => symlink(header.link_name().unwrap().unwrap(), &path).unwrap(),
+                EntryType::Fifo => {
+                    let status = process::Command::new("mkfifo").arg(&path).status().unwrap();
+                    assert!(status.success());
+                }
+                _ => {
+                    let mut file = fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .unwrap();
+                    io::copy(&mut entry, &mut file).unwrap();
+                }
+            }
+            attributes.push((path, header));
+        }
+        // set the attributes when the content is complete, children first
+        for (path, header) in attributes.iter().rev() {
+            if header.entry_type() != EntryType::Symlink {
+                let mode = fs::Permissions::from_mode(header.mode().unwrap());
+                fs::set_permissions(path, mode).unwrap();
+            }
+            set_mtime(path, Timespec::new(header.mtime().unwrap() as i64, 0));
+        }
+    }
+
+    // the headers and the contents of a compressed archive, excluding the owners, which depend
+    // on the system, and the header checksums
+    fn archive_contents(data: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut tar = tar::Archive::new(GzDecoder::new(data));
+        tar.entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut header = entry.header().as_bytes().to_vec();
+                for range in [108..124, 148..156, 265..329] {
+                    header[range].iter_mut().for_each(|b| *b = 0);
+                }
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (header, content)
+            })
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_as_duplicity() {
+        let single_vol = fixture("single_vol");
+        let fixture = |name: &str| single_vol.get(name);
+        let source = TempDir::new("writer-duplicity");
+        let root = source.0.join("dir1");
+        let volume_name = "duplicity-full.20150617T182545Z.vol1.difftar.gz";
+        restore_full(&fixture(volume_name).unwrap(), &root);
+        let backend = MemoryBackend::new();
+        let time = parse_time_str("20150617t182545z").unwrap();
+        let manifest = BackupWriter::new(&backend)
+            .hostname("dellxps")
+            .full(&root, time)
+            .unwrap();
+
+        // headers and contents are the same, including the GNU long names and the signatures
+        for name in &[
+            volume_name,
+            "duplicity-full-signatures.20150617T182545Z.sigtar.gz",
+        ] {
+            let expected = archive_contents(&fixture(name).unwrap());
+            let actual = archive_contents(&backend.get(Path::new(name)).unwrap());
+            assert_eq!(actual.len(), expected.len(), "{}", name);
+            for (actual, expected) in actual.iter().zip(&expected) {
+                assert!(
+                    actual == expected,
+                    "{}: {}",
+                    name,
+                    String::from_utf8_lossy(&expected.0[..100])
+                );
+            }
+        }
+        // the archives end in the same way
+        for name in &[
+            volume_name,
+            "duplicity-full-signatures.20150617T182545Z.sigtar.gz",
+        ] {
+            let uncompressed_len =
+                |data: &[u8]| io::copy(&mut GzDecoder::new(data), &mut io::sink()).unwrap();
+            assert_eq!(
+                uncompressed_len(&backend.get(Path::new(name)).unwrap()),
+                uncompressed_len(&fixture(name).unwrap()),
+                "{}",
+                name
+            );
+        }
+
+        // only the local directory and the hash of the compressed volume differ in manifests
+        let manifest_name = "duplicity-full.20150617T182545Z.manifest";
+        let lines = |data: &[u8]| -> Vec<Vec<u8>> {
+            data.split(|&b| b == b'\n')
+                .filter(|l| !l.starts_with(b"Localdir ") && !l.starts_with(b"    Hash "))
+                .map(<[u8]>::to_vec)
+                .collect()
+        };
+        assert_eq!(
+            lines(&backend.get(Path::new(manifest_name)).unwrap()),
+            lines(&fixture(manifest_name).unwrap())
+        );
+        assert_eq!(manifest.local_dir(), Some(root.as_path()));
+        let volume = backend.get(Path::new(volume_name)).unwrap();
+        assert_eq!(
+            manifest.volume(1).unwrap().hash(),
+            &Sha1::digest(&volume)[..]
+        );
+    }
+}